@@ -1,2 +1,79 @@
+#[cfg(target_os = "linux")]
+mod linux_v4l2;
+#[cfg(target_os = "macos")]
 mod mac_avfoundation;
-pub use mac_avfoundation::{Camera, Frame, ReceiverSharedFrame, SampleFormat, SenderSharedFrame};
+
+#[cfg(target_os = "linux")]
+pub use linux_v4l2::{devices, Camera};
+#[cfg(target_os = "macos")]
+pub use mac_avfoundation::{devices, Camera, Config};
+
+/// A video capture device as listed by [devices]. Pass it to `Camera::open` to capture from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Position in the list of [devices].
+    pub index: usize,
+    /// Human readable and localized name, like "FaceTime HD Camera".
+    pub name: String,
+    /// Stays the same for a device across reconnects and reboots, like the AVFoundation uniqueID
+    /// or the V4L2 bus info.
+    pub unique_id: String,
+    pub model: String,
+    pub position: Position,
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            index,
+            name,
+            unique_id,
+            model,
+            position,
+        } = self;
+        f.write_fmt(format_args!(
+            "{index}: {name} ({model}, {position:?}) id {unique_id}"
+        ))
+    }
+}
+
+/// Where a camera is mounted on the device, for laptops and phones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Unspecified,
+    Front,
+    Back,
+}
+
+/// Opens the camera a user means, see [find_device].
+pub fn find(query: &str) -> std::io::Result<Camera> {
+    let devices = devices();
+    let device = find_device(&devices, query).ok_or_else(|| {
+        let known: Vec<_> = devices.iter().map(|device| device.to_string()).collect();
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "no camera matches {query}, known are [{}]",
+                known.join(", ")
+            ),
+        )
+    })?;
+    Camera::open(device)
+}
+
+/// Finds the device a user means by an index, a unique ID or a part of a name, in this order.
+/// Names match case insensitively.
+pub fn find_device<'a>(devices: &'a [DeviceInfo], query: &str) -> Option<&'a DeviceInfo> {
+    if let Ok(index) = query.parse::<usize>() {
+        if let Some(device) = devices.iter().find(|device| device.index == index) {
+            return Some(device);
+        }
+    }
+    if let Some(device) = devices.iter().find(|device| device.unique_id == query) {
+        return Some(device);
+    }
+    let query = query.to_lowercase();
+    devices
+        .iter()
+        .find(|device| device.name.to_lowercase().contains(&query))
+}
@@ -0,0 +1,392 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+use v4l::{
+    buffer::{Metadata, Type},
+    capability::Flags,
+    context,
+    format::{Colorspace, Quantization, TransferFunction},
+    frameinterval::FrameIntervalEnum,
+    framesize::FrameSizeEnum,
+    io::traits::CaptureStream,
+    prelude::*,
+    video::{capture::Parameters, Capture},
+    Format, FourCC, Fraction,
+};
+
+use super::{DeviceInfo, Position};
+use crate::{
+    frame::{
+        BufferPool, Colorimetry, FrameStream, Matrix, OwnedFrame, Pixels, Primaries, Range,
+        ReceiverSharedFrame, SampleFormat, SenderSharedFrame, SharedFrame, Transfer, VideoFrame,
+    },
+    source::{DeviceFormat, VideoSource},
+};
+
+/// Number of memory mapped buffers the driver cycles through while capturing.
+const BUFFER_COUNT: u32 = 4;
+
+/// How long the capture thread waits for a frame before it checks again if it should stop.
+const DEQUEUE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A camera device. Use it to get and find out about a device and capture [frames](crate::frame::Frame).
+pub struct Camera {
+    name: String,
+    device: Device,
+    /// Moved into the capture thread while capturing and handed back on [stop](Camera::stop).
+    sender: Option<SenderSharedFrame>,
+    receiver: ReceiverSharedFrame,
+    capture: Option<CaptureThread>,
+    prefererred_format: Option<DeviceFormat>,
+}
+
+struct CaptureThread {
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<Option<SenderSharedFrame>>,
+}
+
+impl Camera {
+    /// Opens the first video capture device, usually `/dev/video0`.
+    pub fn default() -> Result<Self> {
+        let (_, path) = capture_nodes()
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no video capture device"))?;
+        Self::with_path(path)
+    }
+
+    /// Opens a device listed by [devices], found again by its unique ID.
+    pub fn open(info: &DeviceInfo) -> Result<Self> {
+        let (_, path) = capture_nodes()
+            .into_iter()
+            .find(|(node, _)| node.unique_id == info.unique_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} is gone", info.name)))?;
+        Self::with_path(path)
+    }
+
+    fn with_path(path: PathBuf) -> Result<Self> {
+        let device = Device::with_path(&path)?;
+        let name = device.query_caps()?.card;
+        let (sender, receiver) = watch::channel(None);
+        Ok(Self {
+            name,
+            device,
+            sender: Some(sender),
+            receiver,
+            capture: None,
+            prefererred_format: None,
+        })
+    }
+
+    fn max_framerate(&self, fourcc: FourCC, width: u32, height: u32) -> f64 {
+        let Ok(intervals) = self.device.enum_frameintervals(fourcc, width, height) else {
+            return 0.0;
+        };
+
+        intervals
+            .into_iter()
+            .map(|interval| match interval.interval {
+                FrameIntervalEnum::Discrete(fraction) => fraction,
+                FrameIntervalEnum::Stepwise(stepwise) => stepwise.min,
+            })
+            .map(fraction_to_framerate)
+            .max_by(f64::total_cmp)
+            .unwrap_or(0.0)
+    }
+
+    /// Sets the preferred format on the device, if there is one, and returns the format the driver actually uses.
+    fn apply_preferred_format(&self) -> Result<Format> {
+        let Some(preferred) = &self.prefererred_format else {
+            return self.device.format();
+        };
+
+        let fourcc = FourCC::new(&string_to_fourcc(&preferred.pixel_format));
+        let requested = Format::new(preferred.width as u32, preferred.height as u32, fourcc);
+        let format = self.device.set_format(&requested)?;
+
+        if preferred.max_framerate > 0.0 {
+            let interval = Fraction::new(1000, (preferred.max_framerate * 1000.0).round() as u32);
+            if let Err(err) = self.device.set_params(&Parameters::new(interval)) {
+                log::debug!("Camera: Setting frame interval {} failed. {}", interval, err);
+            }
+        }
+
+        Ok(format)
+    }
+}
+
+impl VideoSource for Camera {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formats(&self) -> Vec<DeviceFormat> {
+        let Ok(descriptions) = self.device.enum_formats() else {
+            return vec![];
+        };
+
+        let mut formats = vec![];
+        for description in descriptions {
+            let fourcc = description.fourcc;
+            let Ok(framesizes) = self.device.enum_framesizes(fourcc) else {
+                continue;
+            };
+
+            for framesize in framesizes {
+                // Stepwise and continuous sizes can span thousands of combinations,
+                // so only their smallest and biggest size is offered.
+                let sizes = match framesize.size {
+                    FrameSizeEnum::Discrete(size) => vec![(size.width, size.height)],
+                    FrameSizeEnum::Stepwise(size) => vec![
+                        (size.min_width, size.min_height),
+                        (size.max_width, size.max_height),
+                    ],
+                };
+
+                for (width, height) in sizes {
+                    formats.push(DeviceFormat {
+                        width: width as i32,
+                        height: height as i32,
+                        max_framerate: self.max_framerate(fourcc, width, height),
+                        pixel_format: fourcc_to_string(fourcc),
+                    });
+                }
+            }
+        }
+        formats
+    }
+
+    fn set_preferred_format(&mut self, format: Option<DeviceFormat>) {
+        self.prefererred_format = format;
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if self.capture.is_some() {
+            return Ok(());
+        }
+
+        let format = self.apply_preferred_format()?;
+        let sample_format = SampleFormat {
+            width: format.width as i32,
+            height: format.height as i32,
+            pixel_format: fourcc_to_string(format.fourcc),
+        };
+        let colorimetry = colorimetry(&format);
+
+        let mut stream = MmapStream::with_buffers(&self.device, Type::VideoCapture, BUFFER_COUNT)?;
+        stream.set_timeout(DEQUEUE_TIMEOUT);
+
+        let sender = self
+            .sender
+            .take()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "camera frame sender is gone"))?;
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let handle = std::thread::Builder::new()
+            .name("v4l2 capture".into())
+            .spawn(move || {
+                capture_frames(
+                    stream,
+                    sample_format,
+                    colorimetry,
+                    format.stride as usize,
+                    sender,
+                    thread_running,
+                )
+            })?;
+
+        self.capture = Some(CaptureThread { running, handle });
+        Ok(())
+    }
+
+    fn frames(&self) -> FrameStream {
+        WatchStream::new(self.receiver.clone())
+    }
+
+    fn stop(&mut self) {
+        let Some(capture) = self.capture.take() else { return };
+        capture.running.store(false, Ordering::Relaxed);
+        match capture.handle.join() {
+            Ok(sender) => self.sender = sender,
+            Err(_) => log::error!("Camera: Capture thread panicked."),
+        }
+    }
+}
+
+impl Drop for Camera {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Lists every video capture device. Metadata and output nodes, like the second node of UVC cameras, are left out.
+pub fn devices() -> Vec<DeviceInfo> {
+    capture_nodes().into_iter().map(|(info, _)| info).collect()
+}
+
+/// The capture devices with their device node paths, ordered by node number.
+fn capture_nodes() -> Vec<(DeviceInfo, PathBuf)> {
+    let mut nodes = context::enum_devices();
+    nodes.sort_by_key(|node| node.index());
+
+    let mut devices = vec![];
+    for node in nodes {
+        let caps = match Device::with_path(node.path()).and_then(|device| device.query_caps()) {
+            Ok(caps) => caps,
+            Err(err) => {
+                log::debug!("devices: Skip {}. ({})", node.path().display(), err);
+                continue;
+            }
+        };
+        if !caps.capabilities.contains(Flags::VIDEO_CAPTURE) {
+            continue;
+        }
+
+        // the bus info identifies the hardware, the node path is the fallback for virtual devices without one
+        let unique_id = if caps.bus.is_empty() {
+            node.path().display().to_string()
+        } else {
+            caps.bus
+        };
+        let info = DeviceInfo {
+            index: devices.len(),
+            name: caps.card,
+            unique_id,
+            model: caps.driver,
+            position: Position::Unspecified,
+        };
+        devices.push((info, node.path().to_owned()));
+    }
+    devices
+}
+
+/// Runs on the capture thread. Dequeues the memory mapped buffers and copies them into pooled [frames](VideoFrame),
+/// because the driver reuses the buffers as soon as they are queued again.
+/// The copies leave out the row padding of the driver, `stride` being the `bytesperline` of the format.
+///
+/// Returns the sender when stopped regularly. On a device error the sender gets dropped,
+/// which ends every [frame stream](Camera::frames).
+fn capture_frames(
+    mut stream: MmapStream<'static>,
+    format: SampleFormat,
+    colorimetry: Colorimetry,
+    stride: usize,
+    sender: SenderSharedFrame,
+    running: Arc<AtomicBool>,
+) -> Option<SenderSharedFrame> {
+    let start = Instant::now();
+    let pool = BufferPool::default();
+    while running.load(Ordering::Relaxed) {
+        match CaptureStream::next(&mut stream) {
+            Ok((data, meta)) => {
+                let used = meta.bytesused as usize;
+                let data = if used > 0 && used <= data.len() {
+                    &data[..used]
+                } else {
+                    data
+                };
+                let timestamp = timestamp(meta, start);
+                let pixels = Pixels::contiguous(data, &format, stride);
+                let copy = VideoFrame::copy_from_pixels(&pool, format.clone(), &pixels, timestamp);
+                let frame: SharedFrame = match copy {
+                    Some(mut frame) => {
+                        frame.set_colorimetry(colorimetry);
+                        Arc::new(frame)
+                    }
+                    // compressed formats, like MJPG, are passed on as they are
+                    None => Arc::new(OwnedFrame::new(format.clone(), data.to_vec(), timestamp)),
+                };
+                let _ = sender.send(Some(frame));
+            }
+            Err(err) if err.kind() == ErrorKind::TimedOut => {
+                log::trace!("capture_frames: No frame within {:?}.", DEQUEUE_TIMEOUT)
+            }
+            Err(err) => {
+                log::error!("capture_frames: Capturing failed. End. ({})", err);
+                return None;
+            }
+        }
+    }
+
+    log::debug!(
+        "capture_frames: Stop after {} frame buffer allocations.",
+        pool.allocations()
+    );
+    Some(sender)
+}
+
+/// The buffer timestamp of the driver, usually taken from the monotonic clock when the frame was captured.
+/// Drivers without timestamps get the time since the capture started.
+fn timestamp(meta: &Metadata, start: Instant) -> Duration {
+    let timestamp = Duration::from(meta.timestamp);
+    if timestamp.is_zero() {
+        start.elapsed()
+    } else {
+        timestamp
+    }
+}
+
+/// The colorimetry of a format. Where the driver leaves it to the defaults of the colorspace,
+/// they are the ones of V4L2. The YCbCr encoding is always the default, v4l does not expose it.
+///
+/// <https://www.kernel.org/doc/html/latest/userspace-api/media/v4l/colorspaces-defs.html>
+fn colorimetry(format: &Format) -> Colorimetry {
+    let colorspace = format.colorspace;
+    let matrix = match colorspace {
+        Colorspace::Rec709 | Colorspace::DCIP3 => Matrix::Bt709,
+        _ => Matrix::Bt601,
+    };
+    let range = match (format.quantization, colorspace) {
+        (Quantization::FullRange, _) | (Quantization::Default, Colorspace::JPEG) => Range::Full,
+        _ => Range::Video,
+    };
+    let primaries = match colorspace {
+        Colorspace::Rec709 | Colorspace::SRGB | Colorspace::JPEG => Primaries::Bt709,
+        _ => Primaries::Bt601,
+    };
+    let transfer = match (format.transfer, colorspace) {
+        (TransferFunction::SRGB, _)
+        | (TransferFunction::Default, Colorspace::SRGB | Colorspace::JPEG) => Transfer::Srgb,
+        _ => Transfer::Bt709,
+    };
+    Colorimetry {
+        matrix,
+        range,
+        primaries,
+        transfer,
+    }
+}
+
+/// V4L2 FOURCCs are stored little endian, so the characters read in memory order.
+/// NV12 is the preferred 4:2:0 format, YU12 is I420 and YV12 is YV12.
+///
+/// <https://www.kernel.org/doc/html/latest/userspace-api/media/v4l/pixfmt-yuv-planar.html>
+pub fn fourcc_to_string(fourcc: FourCC) -> String {
+    String::from_utf8_lossy(&fourcc.repr).to_string()
+}
+
+fn string_to_fourcc(string: &str) -> [u8; 4] {
+    let mut repr = [b' '; 4];
+    for (byte, char) in repr.iter_mut().zip(string.bytes()) {
+        *byte = char;
+    }
+    repr
+}
+
+fn fraction_to_framerate(interval: Fraction) -> f64 {
+    if interval.numerator == 0 {
+        0.0
+    } else {
+        interval.denominator as f64 / interval.numerator as f64
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(unused)]
-use std::{boxed, os::raw::c_char, sync::Arc};
+use std::{boxed, os::raw::c_char, sync::Arc, time::Duration};
 
 pub use std::ffi::c_void;
 pub use std::ptr::null;
@@ -29,11 +29,19 @@ extern "C" {
     pub fn CVPixelBufferGetHeight(buf: CVBufferRef) -> usize;
     pub fn CVPixelBufferIsPlanar(buf: CVBufferRef) -> bool;
     pub fn CVPixelBufferGetPlaneCount(buf: CVBufferRef) -> usize;
+    pub fn CVPixelBufferGetWidthOfPlane(buf: CVBufferRef, index: usize) -> usize;
     pub fn CVPixelBufferGetHeightOfPlane(buf: CVBufferRef, index: usize) -> usize;
     pub fn CVPixelBufferGetBytesPerRowOfPlane(buf: CVBufferRef, index: usize) -> usize;
     pub fn CVPixelBufferGetDataSize(buf: CVBufferRef) -> usize;
     pub fn CVPixelBufferGetPixelFormatType(buf: CVBufferRef) -> u32;
     pub fn CVPixelBufferGetBaseAddressOfPlane(buf: CVBufferRef, index: usize) -> *const u8;
+    pub fn CVBufferGetAttachment(buf: CVBufferRef, key: CFStringRef, mode: *mut u32) -> CFTypeRef;
+    pub static kCVImageBufferYCbCrMatrixKey: CFStringRef;
+    pub static kCVImageBufferYCbCrMatrix_ITU_R_709_2: CFStringRef;
+    pub static kCVImageBufferColorPrimariesKey: CFStringRef;
+    pub static kCVImageBufferColorPrimaries_ITU_R_709_2: CFStringRef;
+    pub static kCVImageBufferTransferFunctionKey: CFStringRef;
+    pub static kCVImageBufferTransferFunction_sRGB: CFStringRef;
 }
 
 #[link(name = "CoreMedia", kind = "framework")]
@@ -42,6 +50,7 @@ extern "C" {
         sbuf: *const CMSampleBuffer,
     ) -> *const CMFormatDescription;
     pub fn CMSampleBufferGetImageBuffer(sbuf: *const CMSampleBuffer) -> CVImageBufferRef;
+    pub fn CMSampleBufferGetPresentationTimeStamp(sbuf: *const CMSampleBuffer) -> CMTime;
     pub fn CMFormatDescriptionGetMediaSubType(desc: *const CMFormatDescription) -> u32;
     pub fn CMVideoFormatDescriptionGetDimensions(
         desc: *const CMFormatDescription,
@@ -52,6 +61,7 @@ extern "C" {
 extern "C" {
     pub fn CFRetain(cf: *const c_void) -> *const c_void;
     pub fn CFRelease(cf: *const c_void);
+    pub fn CFEqual(cf1: CFTypeRef, cf2: CFTypeRef) -> u8;
 }
 
 /// Use when you need to translate typedefs like this:
@@ -80,6 +90,8 @@ pub struct CVBuffer {
 }
 pub type CVBufferRef = *const CVBuffer;
 pub type CVImageBufferRef = CVBufferRef;
+pub type CFTypeRef = *const c_void;
+pub type CFStringRef = CFTypeRef;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -88,6 +100,49 @@ pub struct CMVideoDimensions {
     pub height: i32,
 }
 
+/// A rational time `value / timescale` seconds.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CMTime {
+    pub value: i64,
+    pub timescale: i32,
+    pub flags: u32,
+    pub epoch: i64,
+}
+
+impl CMTime {
+    /// kCMTimeFlags_Valid
+    const VALID: u32 = 1;
+    /// kCMTimeFlags_PositiveInfinity, kCMTimeFlags_NegativeInfinity and kCMTimeFlags_Indefinite
+    const NOT_NUMERIC: u32 = 4 | 8 | 16;
+
+    pub fn new(value: i64, timescale: i32) -> Self {
+        Self {
+            value,
+            timescale,
+            flags: Self::VALID,
+            epoch: 0,
+        }
+    }
+
+    /// Invalid, negative and infinite times are zero.
+    pub fn to_duration(self) -> Duration {
+        let numeric = self.flags & Self::VALID != 0 && self.flags & Self::NOT_NUMERIC == 0;
+        if !numeric || self.timescale <= 0 || self.value < 0 {
+            return Duration::ZERO;
+        }
+        let nanos = self.value as u128 * 1_000_000_000 / self.timescale as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+unsafe impl Encode for CMTime {
+    const ENCODING: Encoding = Encoding::Struct(
+        "?",
+        &[i64::ENCODING, i32::ENCODING, u32::ENCODING, i64::ENCODING],
+    );
+}
+
 /*  */
 
 use icrate::{
@@ -98,6 +153,16 @@ use icrate::{
 use tokio::sync::watch;
 use tokio_stream::wrappers::WatchStream;
 
+use super::{DeviceInfo, Position};
+use crate::{
+    frame::{
+        BufferPool, Colorimetry, Frame, FrameStream, Matrix, Pixels, Plane, Planes, Primaries,
+        ReceiverSharedFrame, SampleFormat, SenderSharedFrame, SharedFrame, Transfer, VideoFrame,
+        MAX_PLANES,
+    },
+    source::{DeviceFormat, VideoSource},
+};
+
 fn foo() {
     let x: NSString;
 }
@@ -133,13 +198,57 @@ impl AVCaptureDevice {
         unsafe { msg_send_id![Self::class(), defaultDeviceWithMediaType: video] }
     }
 
+    /// Media types are FOURCCs too, "vide" for video and "muxd" for muxed audio and video like capture cards.
+    pub fn devices_with_media_type(media_type: &NSString) -> Id<NSArray<Self>, Shared> {
+        unsafe { msg_send_id![Self::class(), devicesWithMediaType: media_type] }
+    }
+
+    pub fn with_unique_id(unique_id: &NSString) -> Option<Id<Self, Shared>> {
+        unsafe { msg_send_id![Self::class(), deviceWithUniqueID: unique_id] }
+    }
+
     pub fn localized_name(&self) -> Id<NSString, Shared> {
         unsafe { msg_send_id![self, localizedName] }
     }
 
+    pub fn unique_id(&self) -> Id<NSString, Shared> {
+        unsafe { msg_send_id![self, uniqueID] }
+    }
+
+    pub fn model_id(&self) -> Id<NSString, Shared> {
+        unsafe { msg_send_id![self, modelID] }
+    }
+
+    /// AVCaptureDevicePosition: 0 unspecified, 1 back, 2 front
+    pub fn position(&self) -> isize {
+        unsafe { msg_send![self, position] }
+    }
+
     pub fn formats(&self) -> Id<NSArray<AVCaptureDeviceFormat>, Shared> {
         unsafe { msg_send_id![self, formats] }
     }
+
+    /// Needed before setting the active format or frame durations.
+    pub fn lock_for_configuration(&self) -> bool {
+        let error = std::ptr::null_mut::<*mut NSError>();
+        unsafe { msg_send![self, lockForConfiguration: error] }
+    }
+
+    pub fn unlock_for_configuration(&self) {
+        unsafe { msg_send![self, unlockForConfiguration] }
+    }
+
+    pub fn set_active_format(&self, format: &AVCaptureDeviceFormat) {
+        unsafe { msg_send![self, setActiveFormat: format] }
+    }
+
+    pub fn set_active_video_min_frame_duration(&self, duration: CMTime) {
+        unsafe { msg_send![self, setActiveVideoMinFrameDuration: duration] }
+    }
+
+    pub fn set_active_video_max_frame_duration(&self, duration: CMTime) {
+        unsafe { msg_send![self, setActiveVideoMaxFrameDuration: duration] }
+    }
 }
 
 impl AVCaptureDeviceFormat {
@@ -164,6 +273,14 @@ impl AVFrameRateRange {
     pub fn min_framerate(&self) -> f64 {
         unsafe { msg_send![self, minFrameRate] }
     }
+
+    pub fn min_frame_duration(&self) -> CMTime {
+        unsafe { msg_send![self, minFrameDuration] }
+    }
+
+    pub fn max_frame_duration(&self) -> CMTime {
+        unsafe { msg_send![self, maxFrameDuration] }
+    }
 }
 
 extern_class! {
@@ -256,9 +373,6 @@ extern_methods! {
 
 type CallbackPtr = *const c_void;
 
-pub type SenderSharedFrame = tokio::sync::watch::Sender<Option<Arc<Frame>>>;
-pub type ReceiverSharedFrame = tokio::sync::watch::Receiver<Option<Arc<Frame>>>;
-
 declare_class!(
     pub struct MyVideoDataOutputDelegate {
         pub callback: CallbackPtr,
@@ -291,17 +405,17 @@ declare_class!(
             _connection: *const c_void,
         ) {
             let void_ptr: *const c_void = *self.callback;
-            let sender_ptr = unsafe { void_ptr.cast::<SenderSharedFrame>() };
-            if let Some(sender_ref) = unsafe { sender_ptr.as_ref() } {
+            let output_ptr = unsafe { void_ptr.cast::<CaptureOutput>() };
+            if let Some(output) = unsafe { output_ptr.as_ref() } {
                 if sample.is_null() {
                     log::warn!("captureOutput:didOutputSampleBuffer: sample is null");
-                    sender_ref.send(None);
+                    output.sender.send(None);
                 } else {
-                    sender_ref.send(Some(Arc::new(Frame::new(sample as _))));
+                    output.send(CameraFrame::new(sample as _));
                 }
             } else {
-                log::error!("captureOutput:didOutputSampleBuffer: sender_ptr is null");
-                // This means the sender_ptr was not initialized well.
+                log::error!("captureOutput:didOutputSampleBuffer: output_ptr is null");
+                // This means the output_ptr was not initialized well.
             }
         }
     }
@@ -309,87 +423,250 @@ declare_class!(
 
 impl MyVideoDataOutputDelegate {
     #[allow(clippy::borrowed_box)]
-    pub fn new(sender_ptr: *const SenderSharedFrame) -> Id<Self, Owned> {
-        let void_ptr = sender_ptr as *const c_void;
+    pub fn new(output_ptr: *const CaptureOutput) -> Id<Self, Owned> {
+        let void_ptr = output_ptr as *const c_void;
         let cls = Self::class();
         unsafe { msg_send_id![msg_send_id![cls, alloc], initWithCallback: void_ptr] }
     }
 }
 
+/// Where the delegate delivers the frames to.
+pub struct CaptureOutput {
+    sender: SenderSharedFrame,
+    pool: BufferPool,
+}
+
+impl CaptureOutput {
+    /// Copies the sample buffer into a pooled frame and releases it right away,
+    /// so consumers running behind don't keep the capture waiting for free sample buffers.
+    /// Formats without a known plane layout are sent as they are.
+    fn send(&self, frame: CameraFrame) {
+        let frame: SharedFrame = match VideoFrame::copy_from(&self.pool, &frame) {
+            Some(copy) => Arc::new(copy),
+            None => Arc::new(frame),
+        };
+        let _ = self.sender.send(Some(frame));
+    }
+}
+
 pub trait VideoDataOutputDelegate {
     fn frame(&self, sbuf: *const CMSampleBuffer);
 }
 
-impl<T: Fn(Option<Arc<Frame>>)> VideoDataOutputDelegate for T {
+impl<T: Fn(Option<Arc<CameraFrame>>)> VideoDataOutputDelegate for T {
     fn frame(&self, sbuf: *const CMSampleBuffer) {
         log::trace!("frame for Fn");
-        self(Some(Arc::new(Frame::new(sbuf))));
+        self(Some(Arc::new(CameraFrame::new(sbuf))));
     }
 }
 
-impl VideoDataOutputDelegate for tokio::sync::watch::Sender<Option<Frame>> {
+impl VideoDataOutputDelegate for tokio::sync::watch::Sender<Option<CameraFrame>> {
     fn frame(&self, sbuf: *const CMSampleBuffer) {
         log::trace!("frame for watch::Sender");
-        let _ = self.send(Some(Frame::new(sbuf)));
+        let _ = self.send(Some(CameraFrame::new(sbuf)));
     }
 }
 
 /* */
 
-use std::{io::Result, sync::mpsc};
+use std::{
+    io::{Error, ErrorKind, Result},
+    sync::mpsc,
+};
 
-/// A camera device. Use it to get and find out about a device and capture [frames](Frame).
+/// A camera device. Use it to get and find out about a device and capture [frames](CameraFrame).
 pub struct Camera {
     name: String,
     device: Id<AVCaptureDevice, Shared>,
     capture: Id<AVCaptureSession, Shared>,
-    sender: Arc<watch::Sender<Option<Arc<Frame>>>>,
-    receiver: watch::Receiver<Option<Arc<Frame>>>,
+    output: Arc<CaptureOutput>,
+    receiver: ReceiverSharedFrame,
     delegate: Id<MyVideoDataOutputDelegate, Owned>,
-    prefererred_format: Option<DeviceFormat>,
+    config: Config,
 }
 
 unsafe impl Send for Camera {}
 
 impl Camera {
+    /// Opens the default video device, the one the user chose in the system settings.
     pub fn default() -> Result<Self> {
-        let device = AVCaptureDevice::default_video().unwrap();
+        let device = AVCaptureDevice::default_video()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no video capture device"))?;
+        Ok(Self::with_device(device))
+    }
+
+    /// Opens a device listed by [devices], found again by its unique ID.
+    pub fn open(info: &DeviceInfo) -> Result<Self> {
+        let unique_id = NSString::from_str(&info.unique_id);
+        let device = AVCaptureDevice::with_unique_id(&unique_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} is gone", info.name)))?;
+        Ok(Self::with_device(device))
+    }
+
+    /// The format to capture on the next start. Other than a [preferred format](VideoSource::set_preferred_format)
+    /// it can leave constraints up to the device.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    fn with_device(device: Id<AVCaptureDevice, Shared>) -> Self {
         let name = device.localized_name().to_string();
         let (sender, receiver) = watch::channel(None);
-        let sender = Arc::new(sender);
-        let sender_ptr = Arc::as_ptr(&sender);
-        Ok(Self {
+        let output = Arc::new(CaptureOutput {
+            sender,
+            pool: BufferPool::default(),
+        });
+        let output_ptr = Arc::as_ptr(&output);
+        Self {
             name,
             device,
-            sender,
+            output,
             receiver,
             capture: AVCaptureSession::new(),
-            delegate: MyVideoDataOutputDelegate::new(unsafe { sender_ptr.as_ref() }.unwrap()),
-            prefererred_format: None,
-        })
+            delegate: MyVideoDataOutputDelegate::new(unsafe { output_ptr.as_ref() }.unwrap()),
+            config: Config::default(),
+        }
+    }
+
+    /// Finds the device format meeting every constraint of the config and the frame duration to set with it.
+    /// Returns `None` when the config leaves everything up to the device.
+    ///
+    /// The output converts to other pixel formats, so a device format of another pixel format only serves
+    /// when the device has none of the requested pixel format at the requested resolution.
+    fn matching_format(
+        &self,
+        config: &Config,
+    ) -> Result<Option<(Id<AVCaptureDeviceFormat, Shared>, Option<CMTime>)>> {
+        if config.resolution.is_none() && config.interval.is_none() && config.format.is_none() {
+            return Ok(None);
+        }
+
+        let formats = self.device.formats();
+        let mut candidates: Vec<&AVCaptureDeviceFormat> = formats.iter().collect();
+        let mut constraints = vec![];
+
+        if let Some((width, height)) = config.resolution {
+            constraints.push(format!("resolution {width}x{height}"));
+            candidates.retain(|format| {
+                let format = device_format(format);
+                format.width == width as i32 && format.height == height as i32
+            });
+            if candidates.is_empty() {
+                return Err(unmet_constraint(&constraints, &formats));
+            }
+        }
+
+        if let Some(fourcc) = &config.format {
+            let native: Vec<_> = candidates
+                .iter()
+                .copied()
+                .filter(|format| device_format(format).pixel_format == *fourcc)
+                .collect();
+            if !native.is_empty() {
+                candidates = native;
+            }
+        }
+
+        let Some(framerate) = config.framerate() else {
+            let format = candidates
+                .into_iter()
+                .max_by(|a, b| {
+                    device_format(a)
+                        .max_framerate
+                        .total_cmp(&device_format(b).max_framerate)
+                })
+                .map(retained);
+            return Ok(format.map(|format| (format, None)));
+        };
+
+        constraints.push(format!("{framerate:.2} fps"));
+        // a little tolerance for ranges like 29.97 to 30.000030 fps
+        const TOLERANCE: f64 = 0.01;
+        for format in candidates {
+            for range in format.video_supported_framerate_ranges().iter() {
+                if framerate < range.min_framerate() - TOLERANCE
+                    || framerate > range.max_framerate() + TOLERANCE
+                {
+                    continue;
+                }
+                // durations outside of the range throw, so the range bounds are taken instead
+                let duration = if framerate >= range.max_framerate() {
+                    range.min_frame_duration()
+                } else if framerate <= range.min_framerate() {
+                    range.max_frame_duration()
+                } else {
+                    let (num, den) = config.interval.unwrap();
+                    CMTime::new(num as i64, den as i32)
+                };
+                return Ok(Some((retained(format), Some(duration))));
+            }
+        }
+        Err(unmet_constraint(&constraints, &formats))
+    }
+
+    fn video_settings(
+        &self,
+        config: &Config,
+    ) -> Id<NSMutableDictionary<NSString, NSNumber>, Owned> {
+        return if let Some(fourcc) = &config.format {
+            video_settings_with_pixel_format(string_to_fourcc(fourcc))
+        } else {
+            let rgba = 0x20;
+            video_settings_with_pixel_format(rgba)
+        };
+
+        fn video_settings_with_pixel_format(
+            pixel_format: u32,
+        ) -> Id<NSMutableDictionary<NSString, NSNumber>, Owned> {
+            let mut settings = NSMutableDictionary::<NSString, NSNumber>::new();
+            let px_number = NSNumber::new_u32(pixel_format);
+            let px_format_type = NSString::from_str("PixelFormatType"); // kCVPixelBufferPixelFormatTypeKey
+            unsafe { settings.insert(px_format_type, Id::from_shared(px_number)) };
+            settings
+        }
     }
+}
 
-    pub fn name(&self) -> &str {
+impl VideoSource for Camera {
+    fn name(&self) -> &str {
         &self.name
     }
 
-    pub fn formats(&self) -> Vec<DeviceFormat> {
+    fn formats(&self) -> Vec<DeviceFormat> {
         self.device
             .formats()
             .iter()
-            .map(DeviceFormat::new)
+            .map(device_format)
             .collect()
     }
 
-    pub fn set_preferred_format(&mut self, format: Option<DeviceFormat>) {
-        self.prefererred_format = format;
+    fn set_preferred_format(&mut self, format: Option<DeviceFormat>) {
+        self.config = format.as_ref().map(Config::from).unwrap_or_default();
     }
 
-    pub fn start(&mut self) -> Result<()> {
-        let input = AVCaptureDeviceInput::from_device(&self.device).unwrap();
+    fn start(&mut self) -> Result<()> {
+        let config = self.config.clone();
+        let active_format = self.matching_format(&config)?;
+
+        let input = AVCaptureDeviceInput::from_device(&self.device)
+            .map_err(|_| Error::new(ErrorKind::Other, "camera can not be opened for capturing"))?;
         let mut output = AVCaptureVideoDataOutput::new();
 
-        let video_settings = self.video_settings(&Config {});
+        if let Some(fourcc) = &config.format {
+            let available = output.available_video_pixel_format_types();
+            if !available.contains(&string_to_fourcc(fourcc)) {
+                let available: Vec<_> = available.into_iter().map(fourcc_to_string).collect();
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "camera can not capture pixel format {fourcc}, available are {}",
+                        available.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        let video_settings = self.video_settings(&config);
         output.set_video_settings(&video_settings);
 
         let name = std::ffi::CString::new("video input").unwrap();
@@ -401,88 +678,127 @@ impl Camera {
 
         unsafe { dispatch_release(queue) };
 
-        assert!(self.capture.can_add_input(&input));
-        self.capture.add_input(&input);
+        if !self.capture.can_add_input(&input) || !self.capture.can_add_output(&output) {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "camera session takes no more input or output, it is capturing already",
+            ));
+        }
 
-        assert!(self.capture.can_add_output(&output));
+        // Everything which can fail comes before the session changes, so another start can succeed.
+        if active_format.is_some() && !self.device.lock_for_configuration() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "camera is locked for configuration by another application",
+            ));
+        }
+
+        self.capture.add_input(&input);
         self.capture.add_output(&output);
 
+        let Some((format, duration)) = active_format else {
+            self.capture.start_running();
+            return Ok(());
+        };
+
+        log::debug!("Camera: Activate format {}.", device_format(&format));
+        self.device.set_active_format(&format);
+        if let Some(duration) = duration {
+            self.device.set_active_video_min_frame_duration(duration);
+            self.device.set_active_video_max_frame_duration(duration);
+        }
         self.capture.start_running();
+        // Unlocking before the session runs would let the session preset replace the active format, like in ffmpeg.
+        self.device.unlock_for_configuration();
         Ok(())
     }
 
-    pub fn frames(&self) -> WatchStream<Option<Arc<Frame>>> {
+    fn frames(&self) -> FrameStream {
         WatchStream::new(self.receiver.clone())
     }
 
-    pub fn stop(&mut self) {
+    fn stop(&mut self) {
         self.capture.stop_running();
     }
+}
 
-    fn video_settings(
-        &self,
-        _config: &Config,
-    ) -> Id<NSMutableDictionary<NSString, NSNumber>, Owned> {
-        return if let Some(format) = &self.prefererred_format {
-            video_settings_with_pixel_format(str_to_u32(&format.pixel_format))
-        } else {
-            let rgba = 0x20;
-            video_settings_with_pixel_format(rgba)
-        };
+/// Configure a [camera](Camera) device to capture specific frame sizes, frame rates and different pixel formats.
+/// Every constraint left `None` is up to the device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Duration of a frame in seconds as numerator and denominator, like `(1, 30)` for 30 fps.
+    pub interval: Option<(u32, u32)>,
+    /// Width and height.
+    pub resolution: Option<(u32, u32)>,
+    /// FOURCC of the pixel format, like "420v".
+    pub format: Option<String>,
+}
 
-        fn str_to_u32(string: &str) -> u32 {
-            assert_eq!(4, string.len());
-            let bytes = string.as_bytes();
-            let a = bytes[0];
-            let b = bytes[1];
-            let c = bytes[2];
-            let d = bytes[3];
-            unsafe { std::mem::transmute::<[u8; 4], u32>([a, b, c, d]) }.to_be()
-        }
+impl Config {
+    pub fn framerate(&self) -> Option<f64> {
+        self.interval
+            .filter(|(num, _)| *num > 0)
+            .map(|(num, den)| den as f64 / num as f64)
+    }
+}
 
-        fn video_settings_with_pixel_format(
-            pixel_format: u32,
-        ) -> Id<NSMutableDictionary<NSString, NSNumber>, Owned> {
-            let mut settings = NSMutableDictionary::<NSString, NSNumber>::new();
-            let px_number = NSNumber::new_u32(pixel_format);
-            let px_format_type = NSString::from_str("PixelFormatType"); // kCVPixelBufferPixelFormatTypeKey
-            unsafe { settings.insert(px_format_type, Id::from_shared(px_number)) };
-            settings
+impl From<&DeviceFormat> for Config {
+    fn from(format: &DeviceFormat) -> Self {
+        let interval = (format.max_framerate > 0.0)
+            .then(|| (1000, (format.max_framerate * 1000.0).round() as u32));
+        Self {
+            interval,
+            resolution: Some((format.width as u32, format.height as u32)),
+            format: Some(format.pixel_format.clone()),
         }
     }
 }
 
-/// Not implemented. 🤷
-/// Configure a [camera](Camera) device to capture specific frame sizes, frame rates and different pixel formats.
-pub struct Config {
-    // pub interval: (u32, u32),
-    // pub resolution: (u32, u32),
-    // pub format: &'a [u8],
+/// Keeps a format of a device beyond the array it came from.
+fn retained(format: &AVCaptureDeviceFormat) -> Id<AVCaptureDeviceFormat, Shared> {
+    let ptr = format as *const AVCaptureDeviceFormat as *mut AVCaptureDeviceFormat;
+    unsafe { Id::retain(ptr) }.unwrap()
+}
+
+/// The error naming the constraints no device format could meet.
+fn unmet_constraint(constraints: &[String], formats: &NSArray<AVCaptureDeviceFormat>) -> Error {
+    let formats: Vec<_> = formats
+        .iter()
+        .map(|format| device_format(format).to_string())
+        .collect();
+    Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "camera can not capture {}, available are {}",
+            constraints.join(" at "),
+            formats.join(", ")
+        ),
+    )
 }
 
 struct FrameSender {
-    sender: mpsc::SyncSender<Frame>,
+    sender: mpsc::SyncSender<CameraFrame>,
 }
 
 impl VideoDataOutputDelegate for FrameSender {
     fn frame(&self, sbuf: *const CMSampleBuffer) {
-        let _ = self.sender.try_send(Frame::new(sbuf));
+        let _ = self.sender.try_send(CameraFrame::new(sbuf));
     }
 }
 
 /// Holds the frame data without copying it and releases it upon drop.
-/// You can find out about the [format](Frame::format) and get a locked reference to the [pixel data](Frame::pixels).
-pub struct Frame {
+/// The [pixel data](Frame::pixels) stays locked while it is referenced.
+pub struct CameraFrame {
     sbuf: &'static CMSampleBuffer,
 }
 
-impl std::fmt::Debug for Frame {
+impl std::fmt::Debug for CameraFrame {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Frame").finish()
+        f.debug_struct("CameraFrame").finish()
     }
 }
 
-impl Frame {
+impl CameraFrame {
     fn new(ptr: *const CMSampleBuffer) -> Self {
         Self {
             sbuf: Self::retain(ptr),
@@ -494,144 +810,150 @@ impl Frame {
         unsafe { ptr.as_ref() }.unwrap()
     }
 
-    pub fn format(&self) -> SampleFormat {
-        SampleFormat::new(self)
-    }
-
-    pub fn pixels(&self) -> Pixels {
-        Pixels::new(self)
-    }
-
     pub fn raw_sample_buffer(&self) -> *const CMSampleBuffer {
         self.sbuf
     }
 }
 
-impl Drop for Frame {
-    fn drop(&mut self) {
-        let ptr = self.sbuf as *const _ as *const _;
-        unsafe { CFRelease(ptr) };
+impl Frame for CameraFrame {
+    fn format(&self) -> SampleFormat {
+        sample_format(self.sbuf)
     }
-}
 
-/// Holds the locked pixel data of a frame and unlocks upon drop.
-pub struct Pixels<'a> {
-    pub ibuf: CVImageBufferRef,
-    pub data: &'a [u8],
-}
-
-impl<'a> Pixels<'a> {
-    fn new(frame: &'a Frame) -> Self {
-        let ibuf = unsafe { CMSampleBufferGetImageBuffer(frame.sbuf) };
-        debug_assert!(0 == unsafe { CVPixelBufferLockBaseAddress(ibuf, 1) });
-        let _address = unsafe { CVPixelBufferGetBaseAddress(ibuf) };
-        let stride = unsafe { CVPixelBufferGetBytesPerRow(ibuf) };
-        let _width = unsafe { CVPixelBufferGetWidth(ibuf) };
-        let height = unsafe { CVPixelBufferGetHeight(ibuf) };
-        let is_planar = unsafe { CVPixelBufferIsPlanar(ibuf) };
-        let plane_count = unsafe { CVPixelBufferGetPlaneCount(ibuf) };
-        let _data_size = unsafe { CVPixelBufferGetDataSize(ibuf) };
-        let _fourcc = unsafe { CVPixelBufferGetPixelFormatType(ibuf) };
-        let plane_address = unsafe { CVPixelBufferGetBaseAddressOfPlane(ibuf, 0) };
-        let mut plane_sizes = 0;
-
-        // println!("pixels {:?}", (address, stride, width, height, is_planar, plane_count, data_size, fourcc_to_string(fourcc)));
-        if is_planar {
-            for index in 0..plane_count {
-                let _plane_address = unsafe { CVPixelBufferGetBaseAddressOfPlane(ibuf, index) };
-                let plane_stride = unsafe { CVPixelBufferGetBytesPerRowOfPlane(ibuf, index) };
-                let plane_height = unsafe { CVPixelBufferGetHeightOfPlane(ibuf, index) };
-                // println!("        {:?}", (plane_address, plane_stride, plane_height));
-                plane_sizes += plane_stride * plane_height;
-            }
-        } else {
-            plane_sizes += stride * height;
-        }
-
-        let data = unsafe { std::slice::from_raw_parts(plane_address, plane_sizes) };
-        Self { ibuf, data }
+    fn pixels(&self) -> Pixels<'_> {
+        lock_pixels(self.sbuf)
     }
-}
 
-impl Drop for Pixels<'_> {
-    fn drop(&mut self) {
-        debug_assert!(0 == unsafe { CVPixelBufferUnlockBaseAddress(self.ibuf, 1) });
+    /// The presentation time stamp on the host time clock.
+    fn timestamp(&self) -> Duration {
+        unsafe { CMSampleBufferGetPresentationTimeStamp(self.sbuf) }.to_duration()
     }
-}
 
-/// A specific resolution, framerate and pixel format supported by a [camera](Camera) device.
-#[derive(Debug, Clone)]
-pub struct DeviceFormat {
-    pub width: i32,
-    pub height: i32,
-    pub max_framerate: f64,
-    pub pixel_format: String,
-}
-
-impl DeviceFormat {
-    pub fn new(format: &AVCaptureDeviceFormat) -> Self {
-        let format_desc = format.format_description();
-        let dim = unsafe { CMVideoFormatDescriptionGetDimensions(format_desc) };
-        let fourcc = unsafe { CMFormatDescriptionGetMediaSubType(format_desc) };
-        let max_framerate = format
-            .video_supported_framerate_ranges()
-            .iter()
-            .map(|range| range.max_framerate())
-            .max_by(f64::total_cmp)
-            .unwrap_or(0.0);
-
-        Self {
-            width: dim.width,
-            height: dim.height,
-            max_framerate,
-            pixel_format: fourcc_to_string(fourcc),
+    /// The range the FOURCC tells, matrix, primaries and transfer function the attachments of the image buffer.
+    fn colorimetry(&self) -> Colorimetry {
+        let ibuf = unsafe { CMSampleBufferGetImageBuffer(self.sbuf) };
+        let mut colorimetry = Colorimetry::for_format(&self.format());
+        let is_attached = |key: CFStringRef, value: CFStringRef| {
+            let attachment = unsafe { CVBufferGetAttachment(ibuf, key, std::ptr::null_mut()) };
+            !attachment.is_null() && unsafe { CFEqual(attachment, value) } != 0
+        };
+        unsafe {
+            if is_attached(
+                kCVImageBufferYCbCrMatrixKey,
+                kCVImageBufferYCbCrMatrix_ITU_R_709_2,
+            ) {
+                colorimetry.matrix = Matrix::Bt709;
+            }
+            if is_attached(
+                kCVImageBufferColorPrimariesKey,
+                kCVImageBufferColorPrimaries_ITU_R_709_2,
+            ) {
+                colorimetry.primaries = Primaries::Bt709;
+            }
+            if is_attached(
+                kCVImageBufferTransferFunctionKey,
+                kCVImageBufferTransferFunction_sRGB,
+            ) {
+                colorimetry.transfer = Transfer::Srgb;
+            }
         }
+        colorimetry
     }
 }
 
-impl std::fmt::Display for DeviceFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self {
-            width,
-            height,
-            max_framerate,
-            pixel_format,
-        } = self;
-        f.write_fmt(format_args!(
-            "{width}x{height} @ {max_framerate:.2} {pixel_format}"
-        ))
+impl Drop for CameraFrame {
+    fn drop(&mut self) {
+        let ptr = self.sbuf as *const _ as *const _;
+        unsafe { CFRelease(ptr) };
     }
 }
 
-/// The size and pixel format of a [frame](Frame).
-#[derive(Debug)]
-pub struct SampleFormat {
-    pub width: i32,
-    pub height: i32,
-    pub pixel_format: String,
-}
-
-impl SampleFormat {
-    pub fn new(frame: &Frame) -> Self {
-        let format = unsafe { CMSampleBufferGetFormatDescription(frame.sbuf) };
-        let dim = unsafe { CMVideoFormatDescriptionGetDimensions(format) };
-        let fourcc = unsafe { CMFormatDescriptionGetMediaSubType(format) };
-        let pixel_format = fourcc_to_string(fourcc);
-        Self {
-            width: dim.width,
-            height: dim.height,
-            pixel_format,
+/// Locks the pixel data of a sample buffer. The returned [pixels](Pixels) unlock it upon drop.
+fn lock_pixels(sbuf: &CMSampleBuffer) -> Pixels<'_> {
+    let ibuf = unsafe { CMSampleBufferGetImageBuffer(sbuf) };
+    // read only, 1 is kCVPixelBufferLock_ReadOnly
+    let result = unsafe { CVPixelBufferLockBaseAddress(ibuf, 1) };
+    debug_assert_eq!(0, result);
+    let is_planar = unsafe { CVPixelBufferIsPlanar(ibuf) };
+    let fourcc = unsafe { CVPixelBufferGetPixelFormatType(ibuf) };
+    let format = SampleFormat {
+        width: unsafe { CVPixelBufferGetWidth(ibuf) } as i32,
+        height: unsafe { CVPixelBufferGetHeight(ibuf) } as i32,
+        pixel_format: fourcc_to_string(fourcc),
+    };
+    let layout = format.plane_layout();
+
+    let mut planes = Planes::default();
+    if is_planar {
+        let plane_count = unsafe { CVPixelBufferGetPlaneCount(ibuf) }.min(MAX_PLANES);
+        for index in 0..plane_count {
+            let address = unsafe { CVPixelBufferGetBaseAddressOfPlane(ibuf, index) };
+            let stride = unsafe { CVPixelBufferGetBytesPerRowOfPlane(ibuf, index) };
+            let width = unsafe { CVPixelBufferGetWidthOfPlane(ibuf, index) };
+            let height = unsafe { CVPixelBufferGetHeightOfPlane(ibuf, index) };
+            let bytes_per_pixel = layout
+                .and_then(|layout| layout.get(index))
+                .map(|plane| plane.bytes_per_pixel)
+                .unwrap_or(stride / width.max(1));
+            let data = unsafe { std::slice::from_raw_parts(address, stride * height) };
+            planes.push(Plane::new(data, stride, width, height, bytes_per_pixel));
         }
-    }
-}
-
-impl std::fmt::Display for SampleFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(
-            "{}x{} {}",
-            self.width, self.height, self.pixel_format
-        ))
-    }
+    } else {
+        let address = unsafe { CVPixelBufferGetBaseAddress(ibuf) };
+        let stride = unsafe { CVPixelBufferGetBytesPerRow(ibuf) };
+        let (width, height) = (format.width as usize, format.height as usize);
+        let bytes_per_pixel = layout
+            .map(|layout| layout[0].bytes_per_pixel)
+            .unwrap_or(stride / width.max(1));
+        let data = unsafe { std::slice::from_raw_parts(address, stride * height) };
+        planes.push(Plane::new(data, stride, width, height, bytes_per_pixel));
+    }
+
+    Pixels::with_unlock(planes, move || {
+        let result = unsafe { CVPixelBufferUnlockBaseAddress(ibuf, 1) };
+        debug_assert_eq!(0, result);
+    })
+}
+
+/// The resolution, max framerate and pixel format of a capture device format.
+fn device_format(format: &AVCaptureDeviceFormat) -> DeviceFormat {
+    let format_desc = format.format_description();
+    let dim = unsafe { CMVideoFormatDescriptionGetDimensions(format_desc) };
+    let fourcc = unsafe { CMFormatDescriptionGetMediaSubType(format_desc) };
+    let max_framerate = format
+        .video_supported_framerate_ranges()
+        .iter()
+        .map(|range| range.max_framerate())
+        .max_by(f64::total_cmp)
+        .unwrap_or(0.0);
+
+    DeviceFormat {
+        width: dim.width,
+        height: dim.height,
+        max_framerate,
+        pixel_format: fourcc_to_string(fourcc),
+    }
+}
+
+/// The size and pixel format of a sample buffer.
+fn sample_format(sbuf: &CMSampleBuffer) -> SampleFormat {
+    let format = unsafe { CMSampleBufferGetFormatDescription(sbuf) };
+    let dim = unsafe { CMVideoFormatDescriptionGetDimensions(format) };
+    let fourcc = unsafe { CMFormatDescriptionGetMediaSubType(format) };
+    let pixel_format = fourcc_to_string(fourcc);
+    SampleFormat {
+        width: dim.width,
+        height: dim.height,
+        pixel_format,
+    }
+}
+
+fn string_to_fourcc(string: &str) -> u32 {
+    let mut bytes = [b' '; 4];
+    for (byte, char) in bytes.iter_mut().zip(string.bytes()) {
+        *byte = char;
+    }
+    u32::from_be_bytes(bytes)
 }
 
 /// FOURCC is a little crazy. Look at some references to interpret this obfuscation.
@@ -1,3 +1,9 @@
+mod vp8_decoder;
 mod vp8_encoder;
+mod vp9_encoder;
+mod vpx;
 
+pub use vp8_decoder::*;
 pub use vp8_encoder::*;
+pub use vp9_encoder::*;
+pub use vpx::*;
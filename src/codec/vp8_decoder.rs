@@ -0,0 +1,185 @@
+use std::mem::MaybeUninit;
+use std::time::Duration;
+use std::{ptr, slice};
+
+use vpx_sys::*;
+
+use super::{Error, Result};
+use crate::frame::{Frame, Pixels, Plane, SampleFormat};
+
+/// Decodes VP8 frames, like the ones of the [encoder](super::Vp8Encoder), into I420 images.
+pub struct Vp8Decoder {
+    context: vpx_codec_ctx,
+    /// Of the frame decoded last, VP8 decodes every frame into at most one image without reordering.
+    timestamp: Duration,
+}
+
+unsafe impl Send for Vp8Decoder {}
+
+impl Vp8Decoder {
+    pub fn new() -> Result<Self> {
+        let interface = vp8_decoder_interface()?;
+        let context = create_vp8_decoder_context(interface)?;
+
+        Ok(Self {
+            context,
+            timestamp: Duration::ZERO,
+        })
+    }
+
+    /// Decodes one compressed frame, its images get the timestamp.
+    /// A corrupt frame is an [error](Error::CorruptFrame), and the frames after it may fail too or show artifacts
+    /// until the next key frame.
+    pub fn decode(&mut self, data: &[u8], timestamp: Duration) -> Result<Vp8DecoderData<'_>> {
+        if data.is_empty() {
+            return Err(Error::CorruptFrame);
+        }
+        decode_frame(&mut self.context, data)?;
+        self.timestamp = timestamp;
+
+        Ok(Vp8DecoderData::new(self))
+    }
+}
+
+pub struct Vp8DecoderData<'dec> {
+    decoder: &'dec mut Vp8Decoder,
+    iterator: vpx_codec_iter_t,
+}
+
+unsafe impl<'dec> Send for Vp8DecoderData<'dec> {}
+
+impl<'dec> Vp8DecoderData<'dec> {
+    fn new(decoder: &'dec mut Vp8Decoder) -> Self {
+        Self {
+            decoder,
+            iterator: ptr::null(),
+        }
+    }
+
+    /// The decoded images, none for frames which are not shown, like alternate reference frames.
+    pub fn images(&mut self) -> impl Iterator<Item = Vp8Image<'_>> {
+        std::iter::from_fn(|| {
+            let image = next_image(&mut self.decoder.context, &mut self.iterator)?;
+            Some(unsafe { Vp8Image::new(image, self.decoder.timestamp) })
+        })
+    }
+}
+
+/// A decoded image, which points into the buffers of the decoder until the next frame gets decoded.
+/// Copy it, like into a [video frame](crate::frame::VideoFrame), to keep it longer.
+pub struct Vp8Image<'data> {
+    pub width: u32,
+    pub height: u32,
+    pub timestamp: Duration,
+
+    /// Y, U and V.
+    planes: [Plane<'data>; 3],
+}
+
+impl Vp8Image<'_> {
+    unsafe fn new(image: &vpx_image, timestamp: Duration) -> Self {
+        let (width, height) = (image.d_w, image.d_h);
+        let (chroma_width, chroma_height) = ((width as usize + 1) / 2, (height as usize + 1) / 2);
+        // VP8 only knows 8 bit 4:2:0, which the decoder always outputs with the planes apart like I420
+        let planes = unsafe {
+            [
+                plane(image, VPX_PLANE_Y as usize, width as usize, height as usize),
+                plane(image, VPX_PLANE_U as usize, chroma_width, chroma_height),
+                plane(image, VPX_PLANE_V as usize, chroma_width, chroma_height),
+            ]
+        };
+        Self {
+            width,
+            height,
+            timestamp,
+            planes,
+        }
+    }
+}
+
+/// The colorimetry is the one of I420, BT.601 video range like every VP8 stream.
+impl Frame for Vp8Image<'_> {
+    fn format(&self) -> SampleFormat {
+        SampleFormat {
+            width: self.width as i32,
+            height: self.height as i32,
+            pixel_format: "I420".to_string(),
+        }
+    }
+
+    fn pixels(&self) -> Pixels<'_> {
+        Pixels::new(self.planes.into_iter().collect())
+    }
+
+    fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+}
+
+impl Drop for Vp8Decoder {
+    fn drop(&mut self) {
+        let result = unsafe { vpx_codec_destroy(&mut self.context) };
+        if result != vpx_sys::VPX_CODEC_OK {
+            eprintln!("failed to destroy vpx codec: {result:?}");
+        }
+    }
+}
+
+fn vp8_decoder_interface() -> Result<&'static mut vpx_codec_iface> {
+    unsafe { vpx_codec_vp8_dx().as_mut() }.ok_or(Error::VP8Unsupported)
+}
+
+fn create_vp8_decoder_context(interface: &mut vpx_codec_iface) -> Result<vpx_codec_ctx> {
+    let mut context = MaybeUninit::zeroed();
+    // without configuration the decoder takes the size from the key frames and decodes on one thread
+    let result = unsafe {
+        vpx_codec_dec_init_ver(
+            context.assume_init_mut(),
+            interface,
+            ptr::null(),
+            0,
+            vpx_sys::VPX_DECODER_ABI_VERSION as _,
+        )
+    };
+    if result != VPX_CODEC_OK {
+        Err(Error::Bad)
+    } else {
+        Ok(unsafe { context.assume_init() })
+    }
+}
+
+fn decode_frame(context: &mut vpx_codec_ctx, data: &[u8]) -> Result<()> {
+    // no deadline, VP8 decodes in real time anyway
+    let result =
+        unsafe { vpx_codec_decode(context, data.as_ptr(), data.len() as _, ptr::null_mut(), 0) };
+    if result == VPX_CODEC_CORRUPT_FRAME || result == VPX_CODEC_UNSUP_BITSTREAM {
+        Err(Error::CorruptFrame)
+    } else if result != VPX_CODEC_OK {
+        Err(Error::Bad)
+    } else {
+        Ok(())
+    }
+}
+
+fn next_image<'iter>(
+    context: &mut vpx_codec_ctx,
+    iter: &'iter mut vpx_codec_iter_t,
+) -> Option<&'iter vpx_image> {
+    unsafe { vpx_codec_get_frame(context, iter).as_ref() }
+}
+
+/// A plane of an image, from the first byte of the first row to the last byte of pixel data of the last row.
+unsafe fn plane<'data>(
+    image: &vpx_image,
+    index: usize,
+    width: usize,
+    height: usize,
+) -> Plane<'data> {
+    let stride = image.stride[index] as usize;
+    let size = match height {
+        0 => 0,
+        height => stride * (height - 1) + width,
+    };
+    let data = unsafe { slice::from_raw_parts(image.planes[index] as *const u8, size) };
+    Plane::new(data, stride, width, height, 1)
+}
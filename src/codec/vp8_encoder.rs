@@ -1,8 +1,15 @@
-use std::mem::MaybeUninit;
-use std::{ptr, slice};
+use std::ffi::c_int;
+use std::str::FromStr;
 
 use vpx_sys::*;
 
+use super::vpx::{
+    create_encoder_context, create_image_wrap, default_encoder_config, encode_image,
+    encoder_control, flush_encoder, update_encoder_config, vpx_color, EncodeFlags,
+};
+use super::{Deadline, EncoderData, EncoderPacket, Error, FirstPassStats, ImageFormat, Result};
+use crate::frame::{Colorimetry, Pixels};
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[non_exhaustive]
 pub struct Vp8Config {
@@ -10,10 +17,19 @@ pub struct Vp8Config {
     pub height: u32,
     pub timebase: [i32; 2],
     pub bitrate: u32,
+    pub tuning: Vp8Tuning,
+    /// Whether the encoder measures the [PSNR](super::Psnr) of every frame, which takes time.
+    pub psnr: bool,
 }
 
 impl Vp8Config {
-    pub fn new(width: u32, height: u32, timebase: [i32; 2], bitrate: u32) -> Result<Self> {
+    pub fn new(
+        width: u32,
+        height: u32,
+        timebase: [i32; 2],
+        bitrate: u32,
+        tuning: Vp8Tuning,
+    ) -> Result<Self> {
         if width % 2 != 0 {
             return Err(Error::InvalidParam("width must be even"));
         };
@@ -25,127 +41,313 @@ impl Vp8Config {
             height,
             timebase,
             bitrate,
+            tuning,
+            psnr: false,
         })
     }
 }
 
+/// How the VP8 encoder hits the bitrate and how much time it takes for it,
+/// by default like libvpx for real time, or tuned by a [preset](Vp8Preset).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Vp8Tuning {
+    pub rate_control: RateControl,
+    /// The quantizer [constant quality](RateControl::Cq) aims at, from the min to the max quantizer.
+    pub cq_level: u32,
+    /// From 0 to 63, the lower the better the quality and the more bits.
+    pub min_quantizer: u32,
+    pub max_quantizer: u32,
+    /// How many percent of the bitrate the encoder may undershoot and overshoot, to save or to spend bits.
+    pub undershoot_pct: u32,
+    pub overshoot_pct: u32,
+    /// The buffer of the decoder in milliseconds of the bitrate, which the rate control keeps from running
+    /// empty and over, filled up to the initial size at the start and kept at the optimal size.
+    pub buffer_size: u32,
+    pub buffer_initial_size: u32,
+    pub buffer_optimal_size: u32,
+    /// In frames, key frames are placed automatically between these and on scene changes.
+    pub keyframe_min_interval: u32,
+    pub keyframe_max_interval: u32,
+    /// From -16 to 16, the higher the faster and the worse, negative values fix the speed, positive ones
+    /// let it adapt to the deadline.
+    pub cpu_used: i32,
+    pub threads: u32,
+    /// Frames the encoder holds back to look ahead, up to 25, which delays every frame by as many.
+    pub lag_in_frames: u32,
+    /// From 0 to 6, how strongly the encoder reduces noise, like of cameras in low light.
+    pub noise_sensitivity: u32,
+    /// Blocks which changed less than this get skipped, 0 encodes every block.
+    pub static_threshold: u32,
+    /// From 0 to 7, the higher the less the loop filter smoothes edges.
+    pub sharpness: u32,
+    /// Whether the frames get encoded so receivers recover from lost frames without a key frame.
+    pub error_resilient: bool,
+    pub deadline: Deadline,
+}
+
+impl Default for Vp8Tuning {
+    fn default() -> Self {
+        Self {
+            rate_control: RateControl::Vbr,
+            cq_level: 10,
+            min_quantizer: 4,
+            max_quantizer: 63,
+            undershoot_pct: 100,
+            overshoot_pct: 100,
+            buffer_size: 6000,
+            buffer_initial_size: 4000,
+            buffer_optimal_size: 5000,
+            keyframe_min_interval: 0,
+            keyframe_max_interval: 128,
+            cpu_used: 0,
+            threads: 8,
+            lag_in_frames: 0,
+            noise_sensitivity: 0,
+            static_threshold: 0,
+            sharpness: 0,
+            error_resilient: true,
+            deadline: Deadline::Realtime,
+        }
+    }
+}
+
+impl Vp8Tuning {
+    pub fn preset(preset: Vp8Preset) -> Self {
+        match preset {
+            // a small buffer and little overshoot keep the frames near the bitrate, so they do not queue up
+            // in the network, key frames only come on picture loss
+            Vp8Preset::LowLatency => Self {
+                rate_control: RateControl::Cbr,
+                min_quantizer: 2,
+                max_quantizer: 56,
+                overshoot_pct: 15,
+                buffer_size: 1000,
+                buffer_initial_size: 500,
+                buffer_optimal_size: 600,
+                keyframe_max_interval: 3000,
+                cpu_used: -6,
+                static_threshold: 1,
+                ..Self::default()
+            },
+            // mostly static content with sharp edges, changes like a new slide may overshoot more
+            Vp8Preset::Screen => Self {
+                rate_control: RateControl::Cbr,
+                min_quantizer: 2,
+                max_quantizer: 52,
+                overshoot_pct: 50,
+                buffer_size: 1000,
+                buffer_initial_size: 500,
+                buffer_optimal_size: 600,
+                keyframe_max_interval: 3000,
+                cpu_used: -4,
+                static_threshold: 100,
+                sharpness: 3,
+                ..Self::default()
+            },
+            // looks ahead and takes its time, nothing gets lost on the way to a file
+            Vp8Preset::Archive => Self {
+                rate_control: RateControl::Cq,
+                cq_level: 10,
+                min_quantizer: 0,
+                keyframe_max_interval: 300,
+                cpu_used: 1,
+                lag_in_frames: 16,
+                error_resilient: false,
+                deadline: Deadline::Good,
+                ..Self::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateControl {
+    /// Variable bitrate, which spends more bits on complex frames.
+    Vbr,
+    /// Constant bitrate, which real time needs so the frames fit the link.
+    Cbr,
+    /// Constant quality at the cq level, never more than the bitrate though.
+    Cq,
+}
+
+impl RateControl {
+    pub(super) fn vpx_rc_mode(self) -> vpx_rc_mode {
+        match self {
+            RateControl::Vbr => vpx_rc_mode::VPX_VBR,
+            RateControl::Cbr => vpx_rc_mode::VPX_CBR,
+            RateControl::Cq => vpx_rc_mode::VPX_CQ,
+        }
+    }
+}
+
+impl FromStr for RateControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "vbr" => Ok(RateControl::Vbr),
+            "cbr" => Ok(RateControl::Cbr),
+            "cq" => Ok(RateControl::Cq),
+            _ => Err(format!("unknown rate control {s}, use vbr, cbr or cq")),
+        }
+    }
+}
+
+/// Tunings for what the frames are for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vp8Preset {
+    /// Calls and live streams.
+    LowLatency,
+    /// Sharing a screen, mostly static with text.
+    Screen,
+    /// Recordings, in the best quality for the bits without real time.
+    Archive,
+}
+
+impl FromStr for Vp8Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "low-latency" => Ok(Vp8Preset::LowLatency),
+            "screen" => Ok(Vp8Preset::Screen),
+            "archive" => Ok(Vp8Preset::Archive),
+            _ => Err(format!(
+                "unknown preset {s}, use low-latency, screen or archive"
+            )),
+        }
+    }
+}
+
 pub struct Vp8Encoder {
     context: vpx_codec_ctx,
     config: Vp8Config,
+    /// What the context got created with and updated to since.
+    vpx_config: vpx_codec_enc_cfg,
+    /// Of the first pass, which the context of a second pass points into.
+    _stats: Option<FirstPassStats>,
 }
 
 unsafe impl Send for Vp8Encoder {}
 
 impl Vp8Encoder {
+    /// VP8 has no color metadata but a color space bit which only knows BT.601, and decoders take every
+    /// stream for BT.601 video range. Frames of any other colorimetry need to be converted to it.
+    pub const COLORIMETRY: Colorimetry = Colorimetry::BT601;
+
     pub fn new(config: &Vp8Config) -> Result<Self> {
+        Self::create(config, vpx_enc_pass::VPX_RC_ONE_PASS, None)
+    }
+
+    /// Puts out [statistics](super::EncoderPacket::Stats) instead of frames,
+    /// which a [second pass](Vp8Encoder::second_pass) over the same images uses to spend the bits where needed.
+    pub fn first_pass(config: &Vp8Config) -> Result<Self> {
+        Self::create(config, vpx_enc_pass::VPX_RC_FIRST_PASS, None)
+    }
+
+    pub fn second_pass(config: &Vp8Config, stats: FirstPassStats) -> Result<Self> {
+        if stats.is_empty() {
+            return Err(Error::InvalidParam("no statistics of a first pass"));
+        }
+        Self::create(config, vpx_enc_pass::VPX_RC_LAST_PASS, Some(stats))
+    }
+
+    fn create(
+        config: &Vp8Config,
+        pass: vpx_enc_pass,
+        stats: Option<FirstPassStats>,
+    ) -> Result<Self> {
         let interface = vp8_interface()?;
         let mut vpx_config = default_encoder_config(interface)?;
 
         set_encoder_config(&mut vpx_config, config);
-        vpx_config.g_threads = 8;
-        vpx_config.g_error_resilient = VPX_ERROR_RESILIENT_DEFAULT;
-
-        let context = create_vp8_context(interface, &vpx_config)?;
+        vpx_config.g_pass = pass;
+        if let Some(stats) = &stats {
+            vpx_config.rc_twopass_stats_in = stats.vpx_fixed_buf();
+        }
 
-        Ok(Self {
+        let context = create_encoder_context(interface, &vpx_config, config.psnr)?;
+        let mut encoder = Self {
             context,
             config: *config,
-        })
+            vpx_config,
+            _stats: stats,
+        };
+        // dropping the encoder destroys the context when a control fails
+        set_controls(&mut encoder.context, &config.tuning)?;
+
+        Ok(encoder)
     }
 
     /// Only YV12, I420 and NV12 images are supported.
+    /// `pts` and `duration` are in units of the [timebase](Vp8Config::timebase).
     pub fn encode(
         &mut self,
         pts: i64,
+        duration: u64,
         image: vpx_image,
         force_keyframe: bool,
-    ) -> Result<Vp8EncoderData> {
+    ) -> Result<EncoderData> {
         let flags = if force_keyframe {
-            Vp8Flags::FORCE_KF
+            EncodeFlags::FORCE_KF
         } else {
-            Vp8Flags::empty()
+            EncodeFlags::empty()
         };
-        encode_image(&mut self.context, &image, pts, 33, flags)?;
+        encode_image(
+            &mut self.context,
+            &image,
+            pts,
+            duration,
+            flags,
+            self.config.tuning.deadline,
+        )?;
 
-        Ok(Vp8EncoderData::new(self))
+        Ok(EncoderData::new(&mut self.context))
     }
 
-    pub fn config(&self) -> &Vp8Config {
-        &self.config
+    /// The frames the encoder still holds back to [look ahead](Vp8Tuning::lag_in_frames), with their other packets,
+    /// to get before the encoder is dropped. Each packet only lives for its call.
+    pub fn flush(&mut self, each: impl FnMut(EncoderPacket)) -> Result<()> {
+        flush_encoder(&mut self.context, self.config.tuning.deadline, each)
     }
 
-    pub fn wrap_image(&self, data: &[u8], format: ImageFormat) -> Result<vpx_image> {
-        create_image_wrap(self.config.width, self.config.height, data, format)
+    pub fn config(&self) -> &Vp8Config {
+        &self.config
     }
-}
-
-pub struct Vp8EncoderData<'enc> {
-    encoder: &'enc mut Vp8Encoder,
-    iterator: vpx_codec_iter_t,
-}
 
-unsafe impl<'enc> Send for Vp8EncoderData<'enc> {}
-
-impl<'enc> Vp8EncoderData<'enc> {
-    fn new(encoder: &'enc mut Vp8Encoder) -> Self {
-        Self {
-            encoder,
-            iterator: ptr::null(),
+    /// Changes the config without a new encoder, so the stream goes on without key frame, unless the size changes.
+    /// The size can not grow beyond the one the encoder was created with, nor can the timebase change.
+    /// The encoder stays as it was when it does not take the config.
+    ///
+    /// There is no frame rate to set, the rate control follows the durations of the images,
+    /// so frames coming less often get more bits each at the same bitrate.
+    pub fn update_config(&mut self, config: &Vp8Config) -> Result<()> {
+        if config.psnr != self.config.psnr {
+            return Err(Error::InvalidParam("PSNR only changes with a new encoder"));
         }
-    }
-
-    pub fn frames(&mut self) -> impl Iterator<Item = Vp8Frame> {
-        std::iter::from_fn(|| loop {
-            let Some(packet) = next_packet(&mut self.encoder.context, &mut self.iterator) else {
-                return None
-            };
-
-            match packet.kind {
-                vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT => {
-                    return Some(unsafe { Vp8Frame::new(packet) })
-                }
-                vpx_codec_cx_pkt_kind::VPX_CODEC_STATS_PKT => todo!(),
-                vpx_codec_cx_pkt_kind::VPX_CODEC_FPMB_STATS_PKT => todo!(),
-                vpx_codec_cx_pkt_kind::VPX_CODEC_PSNR_PKT => todo!(),
-                vpx_codec_cx_pkt_kind::VPX_CODEC_CUSTOM_PKT => todo!(),
+        let mut vpx_config = self.vpx_config;
+        set_encoder_config(&mut vpx_config, config);
+        update_encoder_config(&mut self.context, &vpx_config)?;
+        if config.tuning != self.config.tuning {
+            if let Err(err) = set_controls(&mut self.context, &config.tuning) {
+                // back to the config and the controls as they were, some controls may be set already
+                update_encoder_config(&mut self.context, &self.vpx_config)?;
+                set_controls(&mut self.context, &self.config.tuning)?;
+                return Err(err);
             }
-        })
-    }
-}
-
-pub struct Vp8Frame<'data> {
-    pub data: &'data [u8],
-    pub pts: i64,
-    pub duration: u64,
-    pub width: u32,
-    pub height: u32,
-
-    flags: InternalFrameFlags,
-}
-
-impl Vp8Frame<'_> {
-    unsafe fn new(packet: &vpx_codec_cx_pkt) -> Self {
-        let frame = unsafe { &packet.data.frame };
-        let data = unsafe { slice::from_raw_parts(frame.buf as _, frame.sz as usize) };
-        let pts = frame.pts;
-        let duration = frame.duration;
-        let flags = InternalFrameFlags::from_bits_truncate(frame.flags);
-        let width = frame.width[0];
-        let height = frame.height[0];
-        // * .partition_id not supported since partitioned frames are not supported for now
-        // * only consider layer 0 because VP8 only uses this one, ignore .spatial_layer_encoded completely
-        Self {
-            data,
-            pts,
-            duration,
-            flags,
-            width,
-            height,
         }
+        self.vpx_config = vpx_config;
+        self.config = *config;
+        Ok(())
     }
 
-    pub fn keyframe(&self) -> bool {
-        self.flags.contains(InternalFrameFlags::IS_KEY)
+    /// The image points into the planes of the pixels,
+    /// so keep them until the image is [encoded](Vp8Encoder::encode).
+    /// It is tagged with the [colorimetry](Vp8Encoder::COLORIMETRY) the stream signals.
+    pub fn wrap_image(&self, pixels: &Pixels, format: ImageFormat) -> Result<vpx_image> {
+        let mut image = create_image_wrap(self.config.width, self.config.height, pixels, format)?;
+        (image.cs, image.range) = vpx_color(&Self::COLORIMETRY);
+        Ok(image)
     }
 }
 
@@ -158,165 +360,53 @@ impl Drop for Vp8Encoder {
     }
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
-
-#[derive(thiserror::Error, Debug)]
-pub enum Error {
-    #[error("")]
-    VP8Unsupported,
-    #[error("")]
-    InvalidParam(&'static str),
-    #[error("")]
-    ImageWrapNotCreated,
-
-    // TODO we can be more specific than this
-    #[error("")]
-    Bad,
-}
-
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-#[allow(unused)]
-pub enum ImageFormat {
-    /// Same as f420 on Mac
-    I420,
-    YV12,
-    /// Same as 420v on Mac
-    NV12,
-}
-
-impl ImageFormat {
-    fn vpx_img_fmt(&self) -> vpx_img_fmt {
-        match self {
-            ImageFormat::I420 => vpx_img_fmt::VPX_IMG_FMT_I420,
-            ImageFormat::YV12 => vpx_img_fmt::VPX_IMG_FMT_YV12,
-            ImageFormat::NV12 => vpx_img_fmt::VPX_IMG_FMT_NV12,
-        }
-    }
-}
-
-// TODO test and consider using libvpx error strings
-//
-// fn vpx_error_to_string(error: vpx_codec_err_t) -> Cow<'static, str> {
-//     let string = unsafe { std::ffi::CStr::from_ptr(vpx_codec_err_to_string(error)) };
-//     string.to_string_lossy()
-// }
-
 fn vp8_interface() -> Result<&'static mut vpx_codec_iface> {
     unsafe { vpx_codec_vp8_cx().as_mut() }.ok_or(Error::VP8Unsupported)
 }
 
-fn default_encoder_config(interface: &mut vpx_codec_iface) -> Result<vpx_codec_enc_cfg> {
-    let mut vpx_config = MaybeUninit::zeroed();
-    let result = unsafe {
-        vpx_codec_enc_config_default(interface as *mut _, vpx_config.assume_init_mut(), 0)
-    };
-    if result != VPX_CODEC_OK {
-        Err(Error::Bad)
-    } else {
-        Ok(unsafe { vpx_config.assume_init() })
-    }
-}
-
 fn set_encoder_config(vpx_config: &mut vpx_codec_enc_cfg, config: &Vp8Config) {
     vpx_config.g_w = config.width;
     vpx_config.g_h = config.height;
     vpx_config.g_timebase.num = config.timebase[0];
     vpx_config.g_timebase.den = config.timebase[1];
     vpx_config.rc_target_bitrate = config.bitrate;
-}
 
-fn create_vp8_context(
-    interface: &mut vpx_codec_iface,
-    vpx_config: &vpx_codec_enc_cfg,
-) -> Result<vpx_codec_ctx> {
-    let mut context = MaybeUninit::zeroed();
-    let result = unsafe {
-        vpx_codec_enc_init_ver(
-            context.assume_init_mut(),
-            interface,
-            vpx_config,
-            0,
-            vpx_sys::VPX_ENCODER_ABI_VERSION as _,
-        )
-    };
-    if result != VPX_CODEC_OK {
-        Err(Error::Bad)
+    let tuning = &config.tuning;
+    vpx_config.rc_end_usage = tuning.rate_control.vpx_rc_mode();
+    vpx_config.rc_min_quantizer = tuning.min_quantizer;
+    vpx_config.rc_max_quantizer = tuning.max_quantizer;
+    vpx_config.rc_undershoot_pct = tuning.undershoot_pct;
+    vpx_config.rc_overshoot_pct = tuning.overshoot_pct;
+    vpx_config.rc_buf_sz = tuning.buffer_size;
+    vpx_config.rc_buf_initial_sz = tuning.buffer_initial_size;
+    vpx_config.rc_buf_optimal_sz = tuning.buffer_optimal_size;
+    vpx_config.kf_mode = vpx_kf_mode::VPX_KF_AUTO;
+    vpx_config.kf_min_dist = tuning.keyframe_min_interval;
+    vpx_config.kf_max_dist = tuning.keyframe_max_interval;
+    vpx_config.g_threads = tuning.threads;
+    vpx_config.g_lag_in_frames = tuning.lag_in_frames;
+    vpx_config.g_error_resilient = if tuning.error_resilient {
+        VPX_ERROR_RESILIENT_DEFAULT
     } else {
-        Ok(unsafe { context.assume_init() })
-    }
-}
-
-fn create_image_wrap(
-    width: u32,
-    height: u32,
-    data: &[u8],
-    format: ImageFormat,
-) -> Result<vpx_image> {
-    let mut image = MaybeUninit::zeroed();
-    let stride_align = 1;
-    let result = unsafe {
-        vpx_img_wrap(
-            image.assume_init_mut(),
-            format.vpx_img_fmt(),
-            width,
-            height,
-            stride_align,
-            data.as_ptr() as _,
-        )
-    };
-    if result.is_null() {
-        Err(Error::ImageWrapNotCreated)
-    } else {
-        Ok(unsafe { image.assume_init() })
-    }
-}
-
-fn encode_image(
-    context: &mut vpx_codec_ctx,
-    image: &vpx_image,
-    pts: vpx_codec_pts_t,
-    duration: u64,
-    flags: Vp8Flags,
-) -> Result<()> {
-    let result = unsafe {
-        vpx_codec_encode(
-            context,
-            image,
-            pts,
-            duration,
-            flags.bits as i64,
-            vpx_sys::VPX_DL_REALTIME as u64,
-        )
+        0
     };
-    if result != VPX_CODEC_OK {
-        Err(Error::Bad)
-    } else {
-        Ok(())
-    }
 }
 
-fn next_packet<'iter>(
-    context: &mut vpx_codec_ctx,
-    iter: &'iter mut vpx_codec_iter_t,
-) -> Option<&'iter vpx_codec_cx_pkt> {
-    unsafe { vpx_codec_get_cx_data(context, iter).as_ref() }
-}
-
-bitflags::bitflags! {
-    struct InternalFrameFlags: u32 {
-        const IS_KEY = VPX_FRAME_IS_KEY;
-        const IS_DROPPABLE = VPX_FRAME_IS_DROPPABLE;
-        const IS_VISIBLE = VPX_FRAME_IS_INVISIBLE;
-        const IS_FRAGMENT = VPX_FRAME_IS_FRAGMENT;
-    }
-}
-
-bitflags::bitflags! {
-    struct Vp8Flags: u32 {
-        const FORCE_KF = VPX_EFLAG_FORCE_KF;
-
-        const NO_REF_LAST = VP8_EFLAG_NO_REF_LAST;
-        const NO_REF_GF = VP8_EFLAG_NO_REF_GF;
-        // TODO see vp8cx.h to add more
-    }
+fn set_controls(context: &mut vpx_codec_ctx, tuning: &Vp8Tuning) -> Result<()> {
+    use vp8e_enc_control_id::*;
+
+    encoder_control(context, VP8E_SET_CPUUSED, tuning.cpu_used)?;
+    encoder_control(
+        context,
+        VP8E_SET_NOISE_SENSITIVITY,
+        tuning.noise_sensitivity as c_int,
+    )?;
+    encoder_control(
+        context,
+        VP8E_SET_STATIC_THRESHOLD,
+        tuning.static_threshold as c_int,
+    )?;
+    encoder_control(context, VP8E_SET_SHARPNESS, tuning.sharpness as c_int)?;
+    encoder_control(context, VP8E_SET_CQ_LEVEL, tuning.cq_level as c_int)?;
+    Ok(())
 }
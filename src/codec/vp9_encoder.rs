@@ -0,0 +1,282 @@
+use std::ffi::c_int;
+use std::str::FromStr;
+
+use vpx_sys::*;
+
+use super::vpx::{
+    create_encoder_context, create_image_wrap, default_encoder_config, encode_image,
+    encoder_control, flush_encoder, update_encoder_config, vpx_color, EncodeFlags,
+};
+use super::{Deadline, EncoderData, EncoderPacket, Error, ImageFormat, RateControl, Result};
+use crate::frame::{Colorimetry, Pixels};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Vp9Config {
+    pub width: u32,
+    pub height: u32,
+    pub timebase: [i32; 2],
+    pub bitrate: u32,
+    pub controls: Vp9Controls,
+    /// Whether the encoder measures the [PSNR](super::Psnr) of every frame, which takes time.
+    pub psnr: bool,
+}
+
+impl Vp9Config {
+    pub fn new(
+        width: u32,
+        height: u32,
+        timebase: [i32; 2],
+        bitrate: u32,
+        controls: Vp9Controls,
+    ) -> Result<Self> {
+        if width % 2 != 0 {
+            return Err(Error::InvalidParam("width must be even"));
+        };
+        if height % 2 != 0 {
+            return Err(Error::InvalidParam("height must be even"));
+        };
+        Ok(Self {
+            width,
+            height,
+            timebase,
+            bitrate,
+            controls,
+            psnr: false,
+        })
+    }
+}
+
+/// The settings of the VP9 encoder, by default for real time like WebRTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Vp9Controls {
+    pub rate_control: RateControl,
+    /// From -9 to 9, the higher the faster and the worse, real time needs 5 or more.
+    pub speed: i32,
+    pub threads: u32,
+    /// Log2 of the tile columns to encode and decode in parallel, limited by the width to 256 pixels per tile.
+    pub tile_columns: u32,
+    /// Whether threads also encode rows of tiles in parallel, which makes the most of them at low resolutions.
+    pub row_mt: bool,
+    pub aq_mode: AqMode,
+    pub tune: Tune,
+    /// Whether the frames get encoded so receivers recover from lost frames without a key frame.
+    pub error_resilient: bool,
+    pub deadline: Deadline,
+}
+
+impl Default for Vp9Controls {
+    fn default() -> Self {
+        Self {
+            rate_control: RateControl::Vbr,
+            speed: 7,
+            threads: 8,
+            tile_columns: 2,
+            row_mt: true,
+            aq_mode: AqMode::CyclicRefresh,
+            tune: Tune::Default,
+            error_resilient: true,
+            deadline: Deadline::Realtime,
+        }
+    }
+}
+
+/// How the encoder spends bits on the parts of a frame, adaptive quantization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AqMode {
+    Off = 0,
+    /// More bits for flat parts, where artifacts stand out.
+    Variance = 1,
+    /// More bits for complex parts.
+    Complexity = 2,
+    /// Refreshes parts of every frame, so losses heal without key frames, which real time wants.
+    CyclicRefresh = 3,
+}
+
+impl FromStr for AqMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(AqMode::Off),
+            "variance" => Ok(AqMode::Variance),
+            "complexity" => Ok(AqMode::Complexity),
+            "cyclic" => Ok(AqMode::CyclicRefresh),
+            _ => Err(format!(
+                "unknown aq mode {s}, use off, variance, complexity or cyclic"
+            )),
+        }
+    }
+}
+
+/// What the frames show, the encoder tunes its tools for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tune {
+    /// Camera pictures.
+    Default = 0,
+    /// Screen content with text and sharp edges.
+    Screen = 1,
+}
+
+impl FromStr for Tune {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Tune::Default),
+            "screen" => Ok(Tune::Screen),
+            _ => Err(format!("unknown tune {s}, use default or screen")),
+        }
+    }
+}
+
+pub struct Vp9Encoder {
+    context: vpx_codec_ctx,
+    config: Vp9Config,
+    /// What the context got created with and updated to since.
+    vpx_config: vpx_codec_enc_cfg,
+}
+
+unsafe impl Send for Vp9Encoder {}
+
+impl Vp9Encoder {
+    pub fn new(config: &Vp9Config) -> Result<Self> {
+        let interface = vp9_interface()?;
+        let mut vpx_config = default_encoder_config(interface)?;
+
+        set_encoder_config(&mut vpx_config, config);
+        // by default VP9 looks ahead 25 frames, too much latency for real time
+        vpx_config.g_lag_in_frames = 0;
+
+        let context = create_encoder_context(interface, &vpx_config, config.psnr)?;
+        let mut encoder = Self {
+            context,
+            config: *config,
+            vpx_config,
+        };
+        // dropping the encoder destroys the context when a control fails
+        set_controls(&mut encoder.context, &config.controls)?;
+
+        Ok(encoder)
+    }
+
+    /// Only YV12, I420 and NV12 images are supported.
+    /// `pts` and `duration` are in units of the [timebase](Vp9Config::timebase).
+    pub fn encode(
+        &mut self,
+        pts: i64,
+        duration: u64,
+        image: vpx_image,
+        force_keyframe: bool,
+    ) -> Result<EncoderData> {
+        let flags = if force_keyframe {
+            EncodeFlags::FORCE_KF
+        } else {
+            EncodeFlags::empty()
+        };
+        encode_image(
+            &mut self.context,
+            &image,
+            pts,
+            duration,
+            flags,
+            self.config.controls.deadline,
+        )?;
+
+        Ok(EncoderData::new(&mut self.context))
+    }
+
+    /// Nothing to put out at the end, the encoder does not look ahead. Each packet only lives for its call.
+    pub fn flush(&mut self, each: impl FnMut(EncoderPacket)) -> Result<()> {
+        flush_encoder(&mut self.context, self.config.controls.deadline, each)
+    }
+
+    pub fn config(&self) -> &Vp9Config {
+        &self.config
+    }
+
+    /// Changes the config without a new encoder, so the stream goes on without key frame, unless the size changes.
+    /// The size can not grow beyond the one the encoder was created with, nor can the timebase change.
+    /// The encoder stays as it was when it does not take the config.
+    ///
+    /// There is no frame rate to set, the rate control follows the durations of the images,
+    /// so frames coming less often get more bits each at the same bitrate.
+    pub fn update_config(&mut self, config: &Vp9Config) -> Result<()> {
+        if config.psnr != self.config.psnr {
+            return Err(Error::InvalidParam("PSNR only changes with a new encoder"));
+        }
+        let mut vpx_config = self.vpx_config;
+        set_encoder_config(&mut vpx_config, config);
+        update_encoder_config(&mut self.context, &vpx_config)?;
+        if config.controls != self.config.controls {
+            if let Err(err) = set_controls(&mut self.context, &config.controls) {
+                // back to the config and the controls as they were, some controls may be set already
+                update_encoder_config(&mut self.context, &self.vpx_config)?;
+                set_controls(&mut self.context, &self.config.controls)?;
+                return Err(err);
+            }
+        }
+        self.vpx_config = vpx_config;
+        self.config = *config;
+        Ok(())
+    }
+
+    /// The image points into the planes of the pixels,
+    /// so keep them until the image is [encoded](Vp9Encoder::encode).
+    /// Unlike VP8, the stream signals the matrix and the range of every image, so it takes them as they are.
+    pub fn wrap_image(
+        &self,
+        pixels: &Pixels,
+        format: ImageFormat,
+        colorimetry: &Colorimetry,
+    ) -> Result<vpx_image> {
+        let mut image = create_image_wrap(self.config.width, self.config.height, pixels, format)?;
+        (image.cs, image.range) = vpx_color(colorimetry);
+        Ok(image)
+    }
+}
+
+impl Drop for Vp9Encoder {
+    fn drop(&mut self) {
+        let result = unsafe { vpx_codec_destroy(&mut self.context) };
+        if result != vpx_sys::VPX_CODEC_OK {
+            eprintln!("failed to destroy vpx codec: {result:?}");
+        }
+    }
+}
+
+fn vp9_interface() -> Result<&'static mut vpx_codec_iface> {
+    unsafe { vpx_codec_vp9_cx().as_mut() }.ok_or(Error::VP9Unsupported)
+}
+
+fn set_encoder_config(vpx_config: &mut vpx_codec_enc_cfg, config: &Vp9Config) {
+    vpx_config.g_w = config.width;
+    vpx_config.g_h = config.height;
+    vpx_config.g_timebase.num = config.timebase[0];
+    vpx_config.g_timebase.den = config.timebase[1];
+    vpx_config.rc_target_bitrate = config.bitrate;
+
+    let controls = &config.controls;
+    vpx_config.rc_end_usage = controls.rate_control.vpx_rc_mode();
+    vpx_config.g_threads = controls.threads;
+    vpx_config.g_error_resilient = if controls.error_resilient {
+        VPX_ERROR_RESILIENT_DEFAULT
+    } else {
+        0
+    };
+}
+
+fn set_controls(context: &mut vpx_codec_ctx, controls: &Vp9Controls) -> Result<()> {
+    use vp8e_enc_control_id::*;
+
+    encoder_control(context, VP8E_SET_CPUUSED, controls.speed)?;
+    encoder_control(
+        context,
+        VP9E_SET_TILE_COLUMNS,
+        controls.tile_columns as c_int,
+    )?;
+    encoder_control(context, VP9E_SET_ROW_MT, controls.row_mt as c_int)?;
+    encoder_control(context, VP9E_SET_AQ_MODE, controls.aq_mode as c_int)?;
+    encoder_control(context, VP9E_SET_TUNE_CONTENT, controls.tune as c_int)?;
+    Ok(())
+}
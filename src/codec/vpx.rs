@@ -0,0 +1,626 @@
+//! What the VP8 and VP9 encoders and decoders of libvpx share.
+
+use std::array;
+use std::ffi::c_int;
+use std::mem::MaybeUninit;
+use std::str::FromStr;
+use std::{ptr, slice};
+
+use vpx_sys::*;
+
+use crate::frame::{Colorimetry, Matrix, Pixels, Range};
+
+/// A codec libvpx encodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Vp8,
+    Vp9,
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "vp8" => Ok(Codec::Vp8),
+            "vp9" => Ok(Codec::Vp9),
+            _ => Err(format!("unknown codec {s}, use vp8 or vp9")),
+        }
+    }
+}
+
+/// How long the encoder may take for a frame, the longer the better it compresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deadline {
+    /// As fast as the frames come, what sending them live needs.
+    Realtime,
+    Good,
+    /// Without limit, far slower than real time.
+    Best,
+}
+
+impl Deadline {
+    fn vpx_deadline(self) -> u64 {
+        (match self {
+            Deadline::Realtime => VPX_DL_REALTIME,
+            Deadline::Good => VPX_DL_GOOD_QUALITY,
+            Deadline::Best => VPX_DL_BEST_QUALITY,
+        }) as u64
+    }
+}
+
+impl FromStr for Deadline {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "realtime" => Ok(Deadline::Realtime),
+            "good" => Ok(Deadline::Good),
+            "best" => Ok(Deadline::Best),
+            _ => Err(format!("unknown deadline {s}, use realtime, good or best")),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("")]
+    VP8Unsupported,
+    #[error("")]
+    VP9Unsupported,
+    #[error("")]
+    InvalidParam(&'static str),
+    #[error("")]
+    ImageWrapNotCreated,
+    /// The frame is not VP8 or got damaged, like truncated.
+    #[error("corrupt frame")]
+    CorruptFrame,
+
+    // TODO we can be more specific than this
+    #[error("")]
+    Bad,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[allow(unused)]
+pub enum ImageFormat {
+    /// Same as f420 on Mac
+    I420,
+    YV12,
+    /// Same as 420v on Mac
+    NV12,
+}
+
+impl ImageFormat {
+    /// Maps the FOURCCs of the different sources to the image format with the same memory layout.
+    pub fn from_fourcc(fourcc: &str) -> Option<Self> {
+        match fourcc {
+            "420v" | "420f" | "NV12" => Some(ImageFormat::NV12),
+            "y420" | "f420" | "I420" | "YU12" => Some(ImageFormat::I420),
+            "YV12" => Some(ImageFormat::YV12),
+            _ => None,
+        }
+    }
+
+    /// In memory order the planes are Y and UV for NV12, Y, U and V for I420 and Y, V and U for YV12.
+    fn plane_count(&self) -> usize {
+        match self {
+            ImageFormat::I420 | ImageFormat::YV12 => 3,
+            ImageFormat::NV12 => 2,
+        }
+    }
+
+    fn vpx_img_fmt(&self) -> vpx_img_fmt {
+        match self {
+            ImageFormat::I420 => vpx_img_fmt::VPX_IMG_FMT_I420,
+            ImageFormat::YV12 => vpx_img_fmt::VPX_IMG_FMT_YV12,
+            ImageFormat::NV12 => vpx_img_fmt::VPX_IMG_FMT_NV12,
+        }
+    }
+}
+
+// TODO test and consider using libvpx error strings
+//
+// fn vpx_error_to_string(error: vpx_codec_err_t) -> Cow<'static, str> {
+//     let string = unsafe { std::ffi::CStr::from_ptr(vpx_codec_err_to_string(error)) };
+//     string.to_string_lossy()
+// }
+
+/// The frames an encoder made of an image.
+pub struct EncoderData<'enc> {
+    context: &'enc mut vpx_codec_ctx,
+    iterator: vpx_codec_iter_t,
+}
+
+unsafe impl<'enc> Send for EncoderData<'enc> {}
+
+impl<'enc> EncoderData<'enc> {
+    pub(super) fn new(context: &'enc mut vpx_codec_ctx) -> Self {
+        Self {
+            context,
+            iterator: ptr::null(),
+        }
+    }
+
+    pub fn packets(&mut self) -> impl Iterator<Item = EncoderPacket> {
+        std::iter::from_fn(|| {
+            let packet = next_packet(self.context, &mut self.iterator)?;
+            Some(unsafe { EncoderPacket::new(packet) })
+        })
+    }
+}
+
+/// What an encoder puts out for an image, the compressed frames and what else it got asked for.
+/// Points into the buffers of the encoder until it encodes the next image, like the frames.
+pub enum EncoderPacket<'data> {
+    Frame(EncoderFrame<'data>),
+    /// Statistics of a first pass, to [collect](FirstPassStats) for the second pass.
+    Stats(&'data [u8]),
+    /// Statistics per macroblock of a first pass, only VP9 makes them.
+    MbStats(&'data [u8]),
+    /// Of a compressed frame of the same image, when the encoder got created to measure it.
+    Psnr(Psnr),
+    /// Of codec extensions, libvpx itself makes none.
+    Custom(&'data [u8]),
+}
+
+impl EncoderPacket<'_> {
+    unsafe fn new(packet: &vpx_codec_cx_pkt) -> Self {
+        match packet.kind {
+            vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT => {
+                EncoderPacket::Frame(unsafe { EncoderFrame::new(packet) })
+            }
+            vpx_codec_cx_pkt_kind::VPX_CODEC_STATS_PKT => {
+                EncoderPacket::Stats(unsafe { fixed_buf(&packet.data.twopass_stats) })
+            }
+            vpx_codec_cx_pkt_kind::VPX_CODEC_FPMB_STATS_PKT => {
+                EncoderPacket::MbStats(unsafe { fixed_buf(&packet.data.firstpass_mb_stats) })
+            }
+            vpx_codec_cx_pkt_kind::VPX_CODEC_PSNR_PKT => {
+                let psnr = unsafe { &packet.data.psnr };
+                EncoderPacket::Psnr(Psnr {
+                    psnr: psnr.psnr,
+                    sse: psnr.sse,
+                    samples: psnr.samples,
+                })
+            }
+            vpx_codec_cx_pkt_kind::VPX_CODEC_CUSTOM_PKT => {
+                EncoderPacket::Custom(unsafe { fixed_buf(&packet.data.raw) })
+            }
+        }
+    }
+}
+
+unsafe fn fixed_buf<'data>(buf: &vpx_fixed_buf) -> &'data [u8] {
+    if buf.buf.is_null() {
+        return &[];
+    }
+    unsafe { slice::from_raw_parts(buf.buf as _, buf.sz as usize) }
+}
+
+/// The peak signal-to-noise ratio of a compressed frame to its image in dB, the higher the closer.
+/// Index 0 is of all planes together, 1 to 3 of Y, U and V.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Psnr {
+    pub psnr: [f64; 4],
+    /// The sums of the squared errors.
+    pub sse: [u64; 4],
+    pub samples: [u32; 4],
+}
+
+/// What an encoder put out so far, like to log at the end.
+#[derive(Clone, Debug, Default)]
+pub struct EncoderMetrics {
+    pub frames: u64,
+    pub keyframes: u64,
+    pub bytes: u64,
+    /// Of the statistics of a first pass, per frame and per macroblock.
+    pub stats_bytes: u64,
+    first_pass_stats: FirstPassStats,
+    last_psnr: Option<Psnr>,
+    /// Of the frames which got measured.
+    psnr_frames: u64,
+    psnr_sums: [f64; 4],
+    sse: [u64; 4],
+    samples: [u64; 4],
+}
+
+impl EncoderMetrics {
+    pub fn add(&mut self, packet: &EncoderPacket) {
+        match packet {
+            EncoderPacket::Frame(frame) => {
+                self.frames += 1;
+                self.keyframes += frame.keyframe() as u64;
+                self.bytes += frame.data.len() as u64;
+            }
+            EncoderPacket::Stats(stats) | EncoderPacket::MbStats(stats) => {
+                self.stats_bytes += stats.len() as u64;
+                self.first_pass_stats.add(packet);
+            }
+            EncoderPacket::Psnr(psnr) => {
+                self.last_psnr = Some(*psnr);
+                self.psnr_frames += 1;
+                self.psnr_sums = array::from_fn(|index| self.psnr_sums[index] + psnr.psnr[index]);
+                self.sse = array::from_fn(|index| self.sse[index] + psnr.sse[index]);
+                self.samples =
+                    array::from_fn(|index| self.samples[index] + psnr.samples[index] as u64);
+            }
+            EncoderPacket::Custom(_) => {}
+        }
+    }
+
+    /// What a [first pass](super::Vp8Encoder::first_pass) put out so far, for the second pass.
+    pub fn first_pass_stats(&self) -> &FirstPassStats {
+        &self.first_pass_stats
+    }
+
+    /// The PSNR of the last frame which got measured, per plane.
+    pub fn last_psnr(&self) -> Option<&Psnr> {
+        self.last_psnr.as_ref()
+    }
+
+    /// The average of the PSNR of the frames, indexed like [`Psnr::psnr`], none without measured frames.
+    pub fn average_psnr(&self) -> Option<[f64; 4]> {
+        if self.psnr_frames == 0 {
+            return None;
+        }
+        Some(self.psnr_sums.map(|sum| sum / self.psnr_frames as f64))
+    }
+
+    /// The PSNR of the squared errors of all frames together, which weighs bad frames more than the average.
+    pub fn overall_psnr(&self) -> Option<[f64; 4]> {
+        if self.psnr_frames == 0 {
+            return None;
+        }
+        Some(array::from_fn(|index| {
+            sse_to_psnr(self.samples[index], self.sse[index])
+        }))
+    }
+}
+
+/// For 8 bit samples, capped at 100 dB like libvpx does for identical ones.
+fn sse_to_psnr(samples: u64, sse: u64) -> f64 {
+    const MAX_PSNR: f64 = 100.0;
+    if sse == 0 {
+        return MAX_PSNR;
+    }
+    let psnr = 10.0 * (255.0 * 255.0 * samples as f64 / sse as f64).log10();
+    psnr.min(MAX_PSNR)
+}
+
+/// The statistics of a first pass, collected from its [packets](EncoderPacket::Stats),
+/// which a [second pass](super::Vp8Encoder::second_pass) reads.
+#[derive(Clone, Debug, Default)]
+pub struct FirstPassStats {
+    data: Vec<u8>,
+}
+
+impl FirstPassStats {
+    /// Appends the statistics of a frame, other packets are left out.
+    pub fn add(&mut self, packet: &EncoderPacket) {
+        if let EncoderPacket::Stats(stats) = packet {
+            self.data.extend_from_slice(stats);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Like to store them in a file until the second pass.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Points into the collected statistics, so keep them as long as the encoder.
+    pub(super) fn vpx_fixed_buf(&self) -> vpx_fixed_buf {
+        vpx_fixed_buf {
+            buf: self.data.as_ptr() as _,
+            sz: self.data.len() as _,
+        }
+    }
+}
+
+/// Statistics a first pass stored before.
+impl From<Vec<u8>> for FirstPassStats {
+    fn from(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+/// A compressed frame, which points into the buffer of the encoder until it encodes the next image.
+pub struct EncoderFrame<'data> {
+    pub data: &'data [u8],
+    pub pts: i64,
+    pub duration: u64,
+    pub width: u32,
+    pub height: u32,
+
+    flags: InternalFrameFlags,
+}
+
+impl EncoderFrame<'_> {
+    unsafe fn new(packet: &vpx_codec_cx_pkt) -> Self {
+        let frame = unsafe { &packet.data.frame };
+        let data = unsafe { slice::from_raw_parts(frame.buf as _, frame.sz as usize) };
+        let pts = frame.pts;
+        let duration = frame.duration;
+        let flags = InternalFrameFlags::from_bits_truncate(frame.flags);
+        let width = frame.width[0];
+        let height = frame.height[0];
+        // * .partition_id not supported since partitioned frames are not supported for now
+        // * only consider layer 0 because VP8 and VP9 without spatial layers only use this one, ignore .spatial_layer_encoded completely
+        Self {
+            data,
+            pts,
+            duration,
+            flags,
+            width,
+            height,
+        }
+    }
+
+    pub fn keyframe(&self) -> bool {
+        self.flags.contains(InternalFrameFlags::IS_KEY)
+    }
+}
+
+pub(super) fn default_encoder_config(interface: &mut vpx_codec_iface) -> Result<vpx_codec_enc_cfg> {
+    let mut vpx_config = MaybeUninit::zeroed();
+    let result = unsafe {
+        vpx_codec_enc_config_default(interface as *mut _, vpx_config.assume_init_mut(), 0)
+    };
+    if result != VPX_CODEC_OK {
+        Err(Error::Bad)
+    } else {
+        Ok(unsafe { vpx_config.assume_init() })
+    }
+}
+
+/// Changes the config of a running encoder. Sizes up to the first one and bitrates change without a new
+/// encoder, but the encoder fails on others, like larger sizes.
+pub(super) fn update_encoder_config(
+    context: &mut vpx_codec_ctx,
+    vpx_config: &vpx_codec_enc_cfg,
+) -> Result<()> {
+    let result = unsafe { vpx_codec_enc_config_set(context, vpx_config) };
+    if result != VPX_CODEC_OK {
+        Err(Error::InvalidParam(
+            "config not taken by the running encoder",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+pub(super) fn encoder_control(
+    context: &mut vpx_codec_ctx,
+    id: vp8e_enc_control_id,
+    value: c_int,
+) -> Result<()> {
+    let result = unsafe { vpx_codec_control_(context, id as _, value) };
+    if result != VPX_CODEC_OK {
+        Err(Error::InvalidParam("control not taken"))
+    } else {
+        Ok(())
+    }
+}
+
+/// With PSNR the encoder puts out a [PSNR packet](EncoderPacket::Psnr) for every frame.
+pub(super) fn create_encoder_context(
+    interface: &mut vpx_codec_iface,
+    vpx_config: &vpx_codec_enc_cfg,
+    psnr: bool,
+) -> Result<vpx_codec_ctx> {
+    let flags = if psnr { VPX_CODEC_USE_PSNR } else { 0 };
+    let mut context = MaybeUninit::zeroed();
+    let result = unsafe {
+        vpx_codec_enc_init_ver(
+            context.assume_init_mut(),
+            interface,
+            vpx_config,
+            flags as _,
+            vpx_sys::VPX_ENCODER_ABI_VERSION as _,
+        )
+    };
+    if result != VPX_CODEC_OK {
+        Err(Error::Bad)
+    } else {
+        Ok(unsafe { context.assume_init() })
+    }
+}
+
+pub(super) fn create_image_wrap(
+    width: u32,
+    height: u32,
+    pixels: &Pixels,
+    format: ImageFormat,
+) -> Result<vpx_image> {
+    let planes = &pixels.planes;
+    if planes.len() < format.plane_count() {
+        return Err(Error::InvalidParam("too few planes for the image format"));
+    }
+    if planes[0].width < width as usize || planes[0].height < height as usize {
+        return Err(Error::InvalidParam("planes smaller than the image"));
+    }
+
+    let mut image = MaybeUninit::zeroed();
+    let stride_align = 1;
+    let result = unsafe {
+        vpx_img_wrap(
+            image.assume_init_mut(),
+            format.vpx_img_fmt(),
+            width,
+            height,
+            stride_align,
+            planes[0].data.as_ptr() as _,
+        )
+    };
+    if result.is_null() {
+        return Err(Error::ImageWrapNotCreated);
+    }
+    let mut image = unsafe { image.assume_init() };
+
+    // vpx_img_wrap expects the planes right after each other without row padding,
+    // so the actual plane addresses and strides replace the calculated ones.
+    let (y, u, v) = (
+        VPX_PLANE_Y as usize,
+        VPX_PLANE_U as usize,
+        VPX_PLANE_V as usize,
+    );
+    let address = |index: usize| planes[index].data.as_ptr() as *mut u8;
+    let stride = |index: usize| planes[index].stride as i32;
+    image.planes[y] = address(0);
+    image.stride[y] = stride(0);
+    match format {
+        ImageFormat::I420 => {
+            (image.planes[u], image.stride[u]) = (address(1), stride(1));
+            (image.planes[v], image.stride[v]) = (address(2), stride(2));
+        }
+        ImageFormat::YV12 => {
+            (image.planes[v], image.stride[v]) = (address(1), stride(1));
+            (image.planes[u], image.stride[u]) = (address(2), stride(2));
+        }
+        ImageFormat::NV12 => {
+            // U and V interleaved, V always one byte after U
+            (image.planes[u], image.stride[u]) = (address(1), stride(1));
+            (image.planes[v], image.stride[v]) = (address(1).wrapping_add(1), stride(1));
+        }
+    }
+    Ok(image)
+}
+
+/// The color space and range of a colorimetry, BT.601 standing for SMPTE 170M primaries too.
+pub(super) fn vpx_color(colorimetry: &Colorimetry) -> (vpx_color_space, vpx_color_range) {
+    let space = match colorimetry.matrix {
+        Matrix::Bt601 => vpx_color_space::VPX_CS_BT_601,
+        Matrix::Bt709 => vpx_color_space::VPX_CS_BT_709,
+    };
+    let range = match colorimetry.range {
+        Range::Video => vpx_color_range::VPX_CR_STUDIO_RANGE,
+        Range::Full => vpx_color_range::VPX_CR_FULL_RANGE,
+    };
+    (space, range)
+}
+
+pub(super) fn encode_image(
+    context: &mut vpx_codec_ctx,
+    image: &vpx_image,
+    pts: vpx_codec_pts_t,
+    duration: u64,
+    flags: EncodeFlags,
+    deadline: Deadline,
+) -> Result<()> {
+    let result = unsafe {
+        vpx_codec_encode(
+            context,
+            image,
+            pts,
+            duration,
+            flags.bits as i64,
+            deadline.vpx_deadline(),
+        )
+    };
+    if result != VPX_CODEC_OK {
+        Err(Error::Bad)
+    } else {
+        Ok(())
+    }
+}
+
+/// Makes the encoder put out the frames it holds back to look ahead, without taking another image.
+/// Every round puts out at most one frame, so it goes on until a round puts out no packets.
+/// Each packet only lives for its call, because the next round reuses the buffers.
+pub(super) fn flush_encoder(
+    context: &mut vpx_codec_ctx,
+    deadline: Deadline,
+    mut each: impl FnMut(EncoderPacket),
+) -> Result<()> {
+    loop {
+        let result =
+            unsafe { vpx_codec_encode(context, ptr::null(), -1, 0, 0, deadline.vpx_deadline()) };
+        if result != VPX_CODEC_OK {
+            return Err(Error::Bad);
+        }
+        let mut count = 0;
+        for packet in EncoderData::new(context).packets() {
+            each(packet);
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(());
+        }
+    }
+}
+
+fn next_packet<'iter>(
+    context: &mut vpx_codec_ctx,
+    iter: &'iter mut vpx_codec_iter_t,
+) -> Option<&'iter vpx_codec_cx_pkt> {
+    unsafe { vpx_codec_get_cx_data(context, iter).as_ref() }
+}
+
+bitflags::bitflags! {
+    struct InternalFrameFlags: u32 {
+        const IS_KEY = VPX_FRAME_IS_KEY;
+        const IS_DROPPABLE = VPX_FRAME_IS_DROPPABLE;
+        const IS_VISIBLE = VPX_FRAME_IS_INVISIBLE;
+        const IS_FRAGMENT = VPX_FRAME_IS_FRAGMENT;
+    }
+}
+
+bitflags::bitflags! {
+    pub(super) struct EncodeFlags: u32 {
+        const FORCE_KF = VPX_EFLAG_FORCE_KF;
+
+        // only VP8
+        const NO_REF_LAST = VP8_EFLAG_NO_REF_LAST;
+        const NO_REF_GF = VP8_EFLAG_NO_REF_GF;
+        // TODO see vp8cx.h to add more
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_collect_first_pass_stats_and_psnr() {
+        let frame = EncoderFrame {
+            data: &[1, 2, 3],
+            pts: 0,
+            duration: 1,
+            width: 16,
+            height: 16,
+            flags: InternalFrameFlags::IS_KEY,
+        };
+        let psnr = |db| Psnr {
+            psnr: [db; 4],
+            sse: [0; 4],
+            samples: [256; 4],
+        };
+        let mut metrics = EncoderMetrics::default();
+        assert_eq!(metrics.last_psnr(), None);
+        for packet in [
+            EncoderPacket::Stats(&[1, 2]),
+            EncoderPacket::MbStats(&[9]),
+            EncoderPacket::Stats(&[3]),
+            EncoderPacket::Frame(frame),
+            EncoderPacket::Psnr(psnr(30.0)),
+            EncoderPacket::Psnr(psnr(40.0)),
+        ] {
+            metrics.add(&packet);
+        }
+
+        assert_eq!(metrics.first_pass_stats().as_bytes(), [1, 2, 3]);
+        assert_eq!(metrics.stats_bytes, 4);
+        assert_eq!(
+            (metrics.frames, metrics.keyframes, metrics.bytes),
+            (1, 1, 3)
+        );
+        assert_eq!(metrics.last_psnr(), Some(&psnr(40.0)));
+        assert_eq!(metrics.average_psnr(), Some([35.0; 4]));
+    }
+}
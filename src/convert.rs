@@ -0,0 +1,761 @@
+//! Converts frames between the common capture formats and the formats the encoder and the GUI take.
+//!
+//! Every format is described by where its channels are, so one kernel per pair of YUV and RGB
+//! covers every pair of formats. The common pairs of 4:2:0 and RGB go row by row through
+//! [kernels](kernels::Kernels) with SIMD implementations instead, chosen by what the CPU supports.
+
+use crate::frame::{
+    BufferPool, Colorimetry, Frame, Plane, PlaneMut, Range, SampleFormat, VideoFrame,
+};
+
+mod kernels;
+#[cfg(target_arch = "aarch64")]
+mod neon;
+#[cfg(target_arch = "x86_64")]
+mod x86;
+
+use kernels::{clamp, Kernels, RgbToYuv, YuvToRgb};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("can not convert from {0}")]
+    UnsupportedFormat(String),
+    #[error("the planes of the frame do not match {0}")]
+    InvalidPlanes(SampleFormat),
+}
+
+/// The memory layouts a [converter](Converter) reads and writes. Each stands for every FOURCC of the same layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum PixelFormat {
+    /// 4:2:0, Y plane followed by one plane with interleaved U and V. Also 420v and 420f on Mac.
+    NV12,
+    /// 4:2:0, Y plane followed by a U and a V plane. Also YU12 with V4L2 and y420 and f420 on Mac.
+    I420,
+    /// 4:2:0, Y plane followed by a V and a U plane.
+    YV12,
+    /// 4:2:2, one plane of Y0 U Y1 V. Also yuvs on Mac.
+    YUYV,
+    /// 4:2:2, one plane of U Y0 V Y1. Also 2vuy on Mac.
+    UYVY,
+    RGBA,
+    BGRA,
+}
+
+/// Where a channel is, like U at every second byte of the second plane for NV12.
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    plane: usize,
+    offset: usize,
+    /// Bytes from one sample of the channel to the next in a row.
+    step: usize,
+}
+
+const fn channel(plane: usize, offset: usize, step: usize) -> Channel {
+    Channel {
+        plane,
+        offset,
+        step,
+    }
+}
+
+impl PixelFormat {
+    pub fn from_fourcc(fourcc: &str) -> Option<Self> {
+        match fourcc {
+            "420v" | "420f" | "NV12" => Some(PixelFormat::NV12),
+            "y420" | "f420" | "I420" | "YU12" => Some(PixelFormat::I420),
+            "YV12" => Some(PixelFormat::YV12),
+            "YUYV" | "yuvs" => Some(PixelFormat::YUYV),
+            "UYVY" | "2vuy" => Some(PixelFormat::UYVY),
+            "RGBA" => Some(PixelFormat::RGBA),
+            "BGRA" => Some(PixelFormat::BGRA),
+            _ => None,
+        }
+    }
+
+    pub fn fourcc(&self) -> &'static str {
+        match self {
+            PixelFormat::NV12 => "NV12",
+            PixelFormat::I420 => "I420",
+            PixelFormat::YV12 => "YV12",
+            PixelFormat::YUYV => "YUYV",
+            PixelFormat::UYVY => "UYVY",
+            PixelFormat::RGBA => "RGBA",
+            PixelFormat::BGRA => "BGRA",
+        }
+    }
+
+    pub fn is_rgb(&self) -> bool {
+        matches!(self, PixelFormat::RGBA | PixelFormat::BGRA)
+    }
+
+    /// Horizontal and vertical, 1 for RGB.
+    fn chroma_subsampling(&self) -> (usize, usize) {
+        match self {
+            PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::YV12 => (2, 2),
+            PixelFormat::YUYV | PixelFormat::UYVY => (2, 1),
+            PixelFormat::RGBA | PixelFormat::BGRA => (1, 1),
+        }
+    }
+
+    /// Y, U and V or R, G and B.
+    fn channels(&self) -> [Channel; 3] {
+        match self {
+            PixelFormat::NV12 => [channel(0, 0, 1), channel(1, 0, 2), channel(1, 1, 2)],
+            PixelFormat::I420 => [channel(0, 0, 1), channel(1, 0, 1), channel(2, 0, 1)],
+            PixelFormat::YV12 => [channel(0, 0, 1), channel(2, 0, 1), channel(1, 0, 1)],
+            PixelFormat::YUYV => [channel(0, 0, 2), channel(0, 1, 4), channel(0, 3, 4)],
+            PixelFormat::UYVY => [channel(0, 1, 2), channel(0, 0, 4), channel(0, 2, 4)],
+            PixelFormat::RGBA => [channel(0, 0, 4), channel(0, 1, 4), channel(0, 2, 4)],
+            PixelFormat::BGRA => [channel(0, 2, 4), channel(0, 1, 4), channel(0, 0, 4)],
+        }
+    }
+
+    /// Alpha of the RGB formats.
+    fn alpha(&self) -> Option<Channel> {
+        self.is_rgb().then_some(channel(0, 3, 4))
+    }
+}
+
+/// Converts frames of any [pixel format](PixelFormat) into one pixel format, with buffers of its own pool.
+///
+/// YUV targets get the matrix and the range of the converter, converting from YUV the colorimetry of the frame
+/// decodes it. Primaries and transfer stay the ones of the frame, converting them needs linear light.
+pub struct Converter {
+    target: PixelFormat,
+    colorimetry: Colorimetry,
+    pool: BufferPool,
+    kernels: Kernels,
+    /// U and V rows of NV12, split to go through the planar kernels.
+    scratch: Vec<u8>,
+}
+
+impl Converter {
+    pub fn new(target: PixelFormat, colorimetry: Colorimetry) -> Self {
+        Self {
+            target,
+            colorimetry,
+            pool: BufferPool::default(),
+            kernels: Kernels::detect(),
+            scratch: vec![],
+        }
+    }
+
+    pub fn target(&self) -> PixelFormat {
+        self.target
+    }
+
+    /// Changes the matrix and the range of YUV targets from the next frame on.
+    pub fn set_colorimetry(&mut self, colorimetry: Colorimetry) {
+        self.colorimetry = colorimetry;
+    }
+
+    /// Whether the frame needs a conversion to be in the target format, matrix and range.
+    pub fn needs_conversion(&self, frame: &dyn Frame) -> bool {
+        let format = frame.format();
+        PixelFormat::from_fourcc(&format.pixel_format) != Some(self.target)
+            || (!self.target.is_rgb() && !frame.colorimetry().same_encoding(&self.colorimetry))
+    }
+
+    /// The colorimetry of YUV targets.
+    fn target_colorimetry(&self, source: Colorimetry) -> Colorimetry {
+        Colorimetry {
+            matrix: self.colorimetry.matrix,
+            range: self.colorimetry.range,
+            ..source
+        }
+    }
+
+    pub fn convert(&mut self, frame: &dyn Frame) -> Result<VideoFrame> {
+        let format = frame.format();
+        let source_format = PixelFormat::from_fourcc(&format.pixel_format)
+            .ok_or_else(|| Error::UnsupportedFormat(format.pixel_format.clone()))?;
+        let pixels = frame.pixels();
+        if !format.matches_planes(&pixels.planes) {
+            return Err(Error::InvalidPlanes(format));
+        }
+
+        let (width, height) = (format.width as usize, format.height as usize);
+        let target_format = SampleFormat {
+            width: format.width,
+            height: format.height,
+            pixel_format: self.target.fourcc().to_string(),
+        };
+        let mut converted = VideoFrame::new(&self.pool, target_format, frame.timestamp())
+            .expect("every pixel format has a plane layout");
+
+        let source_colorimetry = frame.colorimetry();
+        let source = Reader {
+            planes: &pixels.planes,
+            format: source_format,
+        };
+        let mut planes: [Option<PlaneMut>; 3] = [None, None, None];
+        for (slot, plane) in planes.iter_mut().zip(converted.planes_mut()) {
+            *slot = Some(plane);
+        }
+        let mut target = Writer {
+            planes,
+            format: self.target,
+        };
+
+        let fast = self.convert_rows(&source, &mut target, width, height, source_colorimetry);
+        let colorimetry = match (fast, source_format.is_rgb(), self.target.is_rgb()) {
+            (Some(colorimetry), _, _) => colorimetry,
+            (None, false, false) if source_colorimetry.matrix == self.colorimetry.matrix => {
+                let (from, to) = (source_colorimetry.range, self.colorimetry.range);
+                let (luma, chroma) = (range_table(from, to, false), range_table(from, to, true));
+                yuv_to_yuv(&source, &mut target, width, height, &luma, &chroma);
+                self.target_colorimetry(source_colorimetry)
+            }
+            (None, false, false) => {
+                // another matrix mixes luma and chroma, so the colors go through RGB
+                let to_rgb = YuvToRgb::new(source_colorimetry);
+                let [luma, u, v] = source_format.channels();
+                let (horizontal, vertical) = source_format.chroma_subsampling();
+                let rgb = |x, y| {
+                    let (cx, cy) = (x / horizontal, y / vertical);
+                    let [r, g, b] = to_rgb.rgb(
+                        source.get(luma, x, y),
+                        source.get(u, cx, cy),
+                        source.get(v, cx, cy),
+                    );
+                    [r as i32, g as i32, b as i32]
+                };
+                let matrix = RgbToYuv::new(self.colorimetry);
+                rgb_to_yuv(rgb, &mut target, width, height, &matrix);
+                self.target_colorimetry(source_colorimetry)
+            }
+            (None, false, true) => {
+                let matrix = YuvToRgb::new(source_colorimetry);
+                yuv_to_rgb(&source, &mut target, width, height, &matrix);
+                source_colorimetry
+            }
+            (None, true, false) => {
+                let [red, green, blue] = source_format.channels();
+                let rgb = |x, y| [red, green, blue].map(|channel| source.get(channel, x, y) as i32);
+                let matrix = RgbToYuv::new(self.colorimetry);
+                rgb_to_yuv(rgb, &mut target, width, height, &matrix);
+                self.target_colorimetry(source_colorimetry)
+            }
+            (None, true, true) => {
+                rgb_to_rgb(&source, &mut target, width, height);
+                source_colorimetry
+            }
+        };
+        converted.set_colorimetry(colorimetry);
+        Ok(converted)
+    }
+
+    /// Converts between 4:2:0 formats of the same matrix and range and between 4:2:0 and RGB with the row kernels.
+    /// `None` without touching the target for every other pair, which takes the generic kernels.
+    fn convert_rows(
+        &mut self,
+        source: &Reader,
+        target: &mut Writer,
+        width: usize,
+        height: usize,
+        source_colorimetry: Colorimetry,
+    ) -> Option<Colorimetry> {
+        let is_420 = |format: PixelFormat| format.chroma_subsampling() == (2, 2);
+        let (from, to) = (source.format, target.format);
+        let (kernels, scratch) = (&self.kernels, &mut self.scratch);
+        let (chroma_width, chroma_height) = chroma_size(PixelFormat::NV12, width, height);
+        let [first, second, third] = &mut target.planes;
+
+        if is_420(from) && is_420(to) && source_colorimetry.same_encoding(&self.colorimetry) {
+            let luma = plane_mut(first);
+            for y in 0..height {
+                luma.row_mut(y)
+                    .copy_from_slice(&source.planes[0].row(y)[..width]);
+            }
+            for y in 0..chroma_height {
+                match (from, to) {
+                    (PixelFormat::NV12, PixelFormat::NV12) => {
+                        let uv = &source.planes[1].row(y)[..2 * chroma_width];
+                        plane_mut(second).row_mut(y).copy_from_slice(uv);
+                    }
+                    (PixelFormat::NV12, _) => {
+                        let (u, v) = chroma_rows_mut(to, plane_mut(second), plane_mut(third), y);
+                        (kernels.deinterleave)(source.planes[1].row(y), u, v);
+                    }
+                    (_, PixelFormat::NV12) => {
+                        let (u, v) = chroma_rows(from, source.planes, y, chroma_width);
+                        (kernels.interleave)(u, v, plane_mut(second).row_mut(y));
+                    }
+                    _ => {
+                        let (from_u, from_v) = chroma_rows(from, source.planes, y, chroma_width);
+                        let (u, v) = chroma_rows_mut(to, plane_mut(second), plane_mut(third), y);
+                        u.copy_from_slice(from_u);
+                        v.copy_from_slice(from_v);
+                    }
+                }
+            }
+            Some(source_colorimetry)
+        } else if is_420(from) && to.is_rgb() {
+            let matrix = YuvToRgb::new(source_colorimetry);
+            scratch.resize(2 * chroma_width, 0);
+            let (scratch_u, scratch_v) = scratch.split_at_mut(chroma_width);
+            let rgb = plane_mut(first);
+            for y in 0..height {
+                let (u, v) = if from == PixelFormat::NV12 {
+                    if y % 2 == 0 {
+                        (kernels.deinterleave)(source.planes[1].row(y / 2), scratch_u, scratch_v);
+                    }
+                    (&*scratch_u, &*scratch_v)
+                } else {
+                    chroma_rows(from, source.planes, y / 2, chroma_width)
+                };
+                let luma = &source.planes[0].row(y)[..width];
+                let swap = to == PixelFormat::BGRA;
+                (kernels.yuv_to_rgb)(luma, u, v, rgb.row_mut(y), &matrix, swap);
+            }
+            Some(source_colorimetry)
+        } else if from.is_rgb() && is_420(to) {
+            let matrix = RgbToYuv::new(self.colorimetry).ordered(from == PixelFormat::BGRA);
+            scratch.resize(2 * chroma_width, 0);
+            let (scratch_u, scratch_v) = scratch.split_at_mut(chroma_width);
+            let rgb = |y: usize| &source.planes[0].row(y)[..4 * width];
+            let luma = plane_mut(first);
+            for y in 0..height {
+                (kernels.rgb_to_luma)(rgb(y), luma.row_mut(y), &matrix);
+            }
+            for y in 0..chroma_height {
+                // an odd last row averages with itself
+                let (top, bottom) = (rgb(2 * y), rgb((2 * y + 1).min(height - 1)));
+                if to == PixelFormat::NV12 {
+                    (kernels.rgb_to_chroma)(top, bottom, scratch_u, scratch_v, &matrix);
+                    (kernels.interleave)(scratch_u, scratch_v, plane_mut(second).row_mut(y));
+                } else {
+                    let (u, v) = chroma_rows_mut(to, plane_mut(second), plane_mut(third), y);
+                    (kernels.rgb_to_chroma)(top, bottom, u, v, &matrix);
+                }
+            }
+            Some(self.target_colorimetry(source_colorimetry))
+        } else {
+            None
+        }
+    }
+}
+
+fn plane_mut<'a, 'b>(plane: &'a mut Option<PlaneMut<'b>>) -> &'a mut PlaneMut<'b> {
+    plane.as_mut().expect("a plane for every channel")
+}
+
+/// The U and V rows of planar 4:2:0 pixels.
+fn chroma_rows<'a>(
+    format: PixelFormat,
+    planes: &[Plane<'a>],
+    y: usize,
+    chroma_width: usize,
+) -> (&'a [u8], &'a [u8]) {
+    let [_, u, v] = format.channels();
+    (
+        &planes[u.plane].row(y)[..chroma_width],
+        &planes[v.plane].row(y)[..chroma_width],
+    )
+}
+
+/// The U and V rows of the second and third plane of planar 4:2:0 pixels, in the order of the format.
+fn chroma_rows_mut<'a>(
+    format: PixelFormat,
+    second: &'a mut PlaneMut,
+    third: &'a mut PlaneMut,
+    y: usize,
+) -> (&'a mut [u8], &'a mut [u8]) {
+    let [_, u, _] = format.channels();
+    if u.plane == 1 {
+        (second.row_mut(y), third.row_mut(y))
+    } else {
+        let (v, u) = (second.row_mut(y), third.row_mut(y));
+        (u, v)
+    }
+}
+
+struct Reader<'a, 'b> {
+    planes: &'a [Plane<'b>],
+    format: PixelFormat,
+}
+
+impl Reader<'_, '_> {
+    fn get(&self, channel: Channel, x: usize, y: usize) -> u8 {
+        let plane = &self.planes[channel.plane];
+        plane.data[y * plane.stride + channel.offset + x * channel.step]
+    }
+}
+
+struct Writer<'a> {
+    planes: [Option<PlaneMut<'a>>; 3],
+    format: PixelFormat,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, channel: Channel, x: usize, y: usize, value: u8) {
+        let plane = self.planes[channel.plane]
+            .as_mut()
+            .expect("a plane for every channel");
+        plane.data[y * plane.stride + channel.offset + x * channel.step] = value;
+    }
+}
+
+fn chroma_size(format: PixelFormat, width: usize, height: usize) -> (usize, usize) {
+    let (horizontal, vertical) = format.chroma_subsampling();
+    (
+        (width + horizontal - 1) / horizontal,
+        (height + vertical - 1) / vertical,
+    )
+}
+
+/// Repacks the planes and converts the range. Chroma samples covering the area of a target sample get averaged,
+/// like two rows of 4:2:2 for 4:2:0, or repeated, like one row of 4:2:0 for two rows of 4:2:2.
+fn yuv_to_yuv(
+    source: &Reader,
+    target: &mut Writer,
+    width: usize,
+    height: usize,
+    luma: &[u8; 256],
+    chroma: &[u8; 256],
+) {
+    let [source_y, source_u, source_v] = source.format.channels();
+    let [target_y, target_u, target_v] = target.format.channels();
+    for y in 0..height {
+        for x in 0..width {
+            let value = luma[source.get(source_y, x, y) as usize];
+            target.put(target_y, x, y, value);
+        }
+    }
+
+    let (source_width, source_height) = chroma_size(source.format, width, height);
+    let (target_width, target_height) = chroma_size(target.format, width, height);
+    let (source_horizontal, source_vertical) = source.format.chroma_subsampling();
+    let (target_horizontal, target_vertical) = target.format.chroma_subsampling();
+    for cy in 0..target_height {
+        let y0 = (cy * target_vertical / source_vertical).min(source_height - 1);
+        let y1 = (((cy + 1) * target_vertical - 1) / source_vertical).min(source_height - 1);
+        for cx in 0..target_width {
+            let x0 = (cx * target_horizontal / source_horizontal).min(source_width - 1);
+            let x1 = (((cx + 1) * target_horizontal - 1) / source_horizontal).min(source_width - 1);
+            let (mut u, mut v, mut count) = (0, 0, 0);
+            for sy in y0..=y1 {
+                for sx in x0..=x1 {
+                    u += source.get(source_u, sx, sy) as usize;
+                    v += source.get(source_v, sx, sy) as usize;
+                    count += 1;
+                }
+            }
+            let u = chroma[(u + count / 2) / count];
+            let v = chroma[(v + count / 2) / count];
+            target.put(target_u, cx, cy, u);
+            target.put(target_v, cx, cy, v);
+        }
+    }
+}
+
+/// Every pixel takes the chroma sample it lies in.
+fn yuv_to_rgb(
+    source: &Reader,
+    target: &mut Writer,
+    width: usize,
+    height: usize,
+    matrix: &YuvToRgb,
+) {
+    let [source_y, source_u, source_v] = source.format.channels();
+    let [red, green, blue] = target.format.channels();
+    let alpha = target.format.alpha();
+    let (horizontal, vertical) = source.format.chroma_subsampling();
+    for y in 0..height {
+        for x in 0..width {
+            let luma = source.get(source_y, x, y);
+            let u = source.get(source_u, x / horizontal, y / vertical);
+            let v = source.get(source_v, x / horizontal, y / vertical);
+            let [r, g, b] = matrix.rgb(luma, u, v);
+            target.put(red, x, y, r);
+            target.put(green, x, y, g);
+            target.put(blue, x, y, b);
+            if let Some(alpha) = alpha {
+                target.put(alpha, x, y, 255);
+            }
+        }
+    }
+}
+
+/// Every chroma sample comes from the average color of the pixels it covers.
+/// The colors come from `rgb` by column and row, so any source can be converted.
+fn rgb_to_yuv(
+    rgb: impl Fn(usize, usize) -> [i32; 3],
+    target: &mut Writer,
+    width: usize,
+    height: usize,
+    matrix: &RgbToYuv,
+) {
+    let [target_y, target_u, target_v] = target.format.channels();
+    for y in 0..height {
+        for x in 0..width {
+            target.put(target_y, x, y, matrix.luma(rgb(x, y)));
+        }
+    }
+
+    let (horizontal, vertical) = target.format.chroma_subsampling();
+    let (chroma_width, chroma_height) = chroma_size(target.format, width, height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let (mut sum, mut count) = ([0; 3], 0);
+            for y in cy * vertical..((cy + 1) * vertical).min(height) {
+                for x in cx * horizontal..((cx + 1) * horizontal).min(width) {
+                    for (sum, value) in sum.iter_mut().zip(rgb(x, y)) {
+                        *sum += value;
+                    }
+                    count += 1;
+                }
+            }
+            let average = sum.map(|sum| (sum + count / 2) / count);
+            let (u, v) = matrix.chroma(average);
+            target.put(target_u, cx, cy, u);
+            target.put(target_v, cx, cy, v);
+        }
+    }
+}
+
+fn rgb_to_rgb(source: &Reader, target: &mut Writer, width: usize, height: usize) {
+    let source_alpha = source.format.alpha();
+    let target_alpha = target.format.alpha();
+    for y in 0..height {
+        for x in 0..width {
+            for (from, to) in source
+                .format
+                .channels()
+                .into_iter()
+                .zip(target.format.channels())
+            {
+                target.put(to, x, y, source.get(from, x, y));
+            }
+            if let Some(to) = target_alpha {
+                let alpha = source_alpha.map_or(255, |from| source.get(from, x, y));
+                target.put(to, x, y, alpha);
+            }
+        }
+    }
+}
+
+/// Maps the luma or chroma values of one range to the other, the identity for the same range.
+fn range_table(from: Range, to: Range, chroma: bool) -> [u8; 256] {
+    // the value of black or no color and the span up to white or the most color
+    let scale = |range| match (range, chroma) {
+        (Range::Video, false) => (16.0, 219.0),
+        (Range::Video, true) => (128.0, 224.0),
+        (Range::Full, false) => (0.0, 255.0),
+        (Range::Full, true) => (128.0, 255.0),
+    };
+    let (from_offset, from_span) = scale(from);
+    let (to_offset, to_span) = scale(to);
+    let mut table = [0; 256];
+    for (value, mapped) in table.iter_mut().enumerate() {
+        let normalized = (value as f32 - from_offset) / from_span;
+        *mapped = clamp((to_offset + normalized * to_span).round() as i32);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Matrix;
+    use std::time::Duration;
+
+    const FORMATS: [PixelFormat; 7] = [
+        PixelFormat::NV12,
+        PixelFormat::I420,
+        PixelFormat::YV12,
+        PixelFormat::YUYV,
+        PixelFormat::UYVY,
+        PixelFormat::RGBA,
+        PixelFormat::BGRA,
+    ];
+
+    const COLORIMETRIES: [Colorimetry; 4] = [
+        Colorimetry::BT601,
+        Colorimetry {
+            range: Range::Full,
+            ..Colorimetry::BT601
+        },
+        Colorimetry::BT709,
+        Colorimetry {
+            range: Range::Full,
+            ..Colorimetry::BT709
+        },
+    ];
+
+    /// Black, white, red, green, blue and gray as RGB, as YUV rounded like in the standards
+    /// and as the RGB this YUV decodes to, which is off by one for some.
+    fn colors(colorimetry: Colorimetry) -> [[[u8; 3]; 6]; 3] {
+        let rgb = [
+            [0, 0, 0],
+            [255, 255, 255],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [128, 128, 128],
+        ];
+        let (yuv, decoded) = match (colorimetry.matrix, colorimetry.range) {
+            (Matrix::Bt601, Range::Video) => (
+                [
+                    [16, 128, 128],
+                    [235, 128, 128],
+                    [81, 90, 240],
+                    [145, 54, 34],
+                    [41, 240, 110],
+                    [126, 128, 128],
+                ],
+                [
+                    [0, 0, 0],
+                    [255, 255, 255],
+                    [254, 0, 0],
+                    [0, 255, 1],
+                    [0, 0, 255],
+                    [128, 128, 128],
+                ],
+            ),
+            (Matrix::Bt601, Range::Full) => (
+                [
+                    [0, 128, 128],
+                    [255, 128, 128],
+                    [76, 85, 255],
+                    [150, 44, 21],
+                    [29, 255, 107],
+                    [128, 128, 128],
+                ],
+                [
+                    [0, 0, 0],
+                    [255, 255, 255],
+                    [254, 0, 0],
+                    [0, 255, 1],
+                    [0, 0, 254],
+                    [128, 128, 128],
+                ],
+            ),
+            (Matrix::Bt709, Range::Video) => (
+                [
+                    [16, 128, 128],
+                    [235, 128, 128],
+                    [63, 102, 240],
+                    [173, 42, 26],
+                    [32, 240, 118],
+                    [126, 128, 128],
+                ],
+                [
+                    [0, 0, 0],
+                    [255, 255, 255],
+                    [255, 1, 0],
+                    [0, 255, 1],
+                    [1, 0, 255],
+                    [128, 128, 128],
+                ],
+            ),
+            (Matrix::Bt709, Range::Full) => (
+                [
+                    [0, 128, 128],
+                    [255, 128, 128],
+                    [54, 99, 255],
+                    [182, 30, 12],
+                    [18, 255, 116],
+                    [128, 128, 128],
+                ],
+                [
+                    [0, 0, 0],
+                    [255, 255, 255],
+                    [254, 0, 0],
+                    [0, 255, 0],
+                    [0, 0, 254],
+                    [128, 128, 128],
+                ],
+            ),
+        };
+        [rgb, yuv, decoded]
+    }
+
+    /// A frame of 2x2 blocks of the values, three blocks wide and two high,
+    /// so every chroma sample of every format lies in one block.
+    fn frame(format: PixelFormat, colorimetry: Colorimetry, values: [[u8; 3]; 6]) -> VideoFrame {
+        let sample_format = SampleFormat {
+            width: 6,
+            height: 4,
+            pixel_format: format.fourcc().to_string(),
+        };
+        let mut frame = VideoFrame::new(&BufferPool::new(0), sample_format, Duration::ZERO)
+            .expect("every pixel format has a plane layout");
+        frame.set_colorimetry(colorimetry);
+
+        let mut planes: [Option<PlaneMut>; 3] = [None, None, None];
+        for (slot, plane) in planes.iter_mut().zip(frame.planes_mut()) {
+            *slot = Some(plane);
+        }
+        let mut target = Writer { planes, format };
+        for (index, channel) in format.channels().into_iter().enumerate() {
+            let (horizontal, vertical) = match index {
+                0 => (1, 1),
+                _ => format.chroma_subsampling(),
+            };
+            for y in 0..4 / vertical {
+                for x in 0..6 / horizontal {
+                    let block = y * vertical / 2 * 3 + x * horizontal / 2;
+                    target.put(channel, x, y, values[block][index]);
+                }
+            }
+        }
+        if let Some(alpha) = format.alpha() {
+            for y in 0..4 {
+                for x in 0..6 {
+                    target.put(alpha, x, y, 255);
+                }
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn golden_frames() {
+        for colorimetry in COLORIMETRIES {
+            let [rgb, yuv, decoded] = colors(colorimetry);
+            for from in FORMATS {
+                let source = frame(from, colorimetry, if from.is_rgb() { rgb } else { yuv });
+                for to in FORMATS {
+                    let expected = match (from.is_rgb(), to.is_rgb()) {
+                        (true, true) => rgb,
+                        (false, true) => decoded,
+                        (_, false) => yuv,
+                    };
+                    let expected = frame(to, colorimetry, expected);
+                    let converted = Converter::new(to, colorimetry).convert(&source).unwrap();
+                    assert_eq!(
+                        expected.pixels().to_vec(),
+                        converted.pixels().to_vec(),
+                        "{from:?} to {to:?} in {colorimetry:?}"
+                    );
+                    assert_eq!(colorimetry, converted.colorimetry());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn full_to_video_range() {
+        let full = COLORIMETRIES[1];
+        let [_, full_yuv, _] = colors(full);
+        let [_, video_yuv, _] = colors(Colorimetry::BT601);
+        for from in FORMATS.into_iter().filter(|format| !format.is_rgb()) {
+            let source = frame(from, full, full_yuv);
+            for to in FORMATS.into_iter().filter(|format| !format.is_rgb()) {
+                let expected = frame(to, Colorimetry::BT601, video_yuv);
+                let mut converter = Converter::new(to, Colorimetry::BT601);
+                let converted = converter.convert(&source).unwrap();
+                assert_eq!(
+                    expected.pixels().to_vec(),
+                    converted.pixels().to_vec(),
+                    "{from:?} to {to:?}"
+                );
+                assert_eq!(Colorimetry::BT601, converted.colorimetry());
+            }
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use crate::frame::Colorimetry;
+
+/// Fractional bits of the fixed point color coefficients. With 13 bits every coefficient fits into 16 bits,
+/// so SIMD multiplies 16 bit lanes into 32 bit sums, exactly like the scalar kernels.
+pub(super) const FRACTION_BITS: i32 = 13;
+/// One half in fixed point, to round when shifting back.
+pub(super) const HALF: i32 = 1 << (FRACTION_BITS - 1);
+
+/// The row kernels of one implementation. SIMD implementations only cover full blocks of pixels
+/// and leave the rest of a row to the scalar kernels, so every implementation gives the same result.
+#[derive(Clone, Copy)]
+pub(super) struct Kernels {
+    pub name: &'static str,
+    /// Splits interleaved UV into as many U and V samples as the U row has.
+    pub deinterleave: fn(uv: &[u8], u: &mut [u8], v: &mut [u8]),
+    /// Interleaves as many U and V samples as the U row has.
+    pub interleave: fn(u: &[u8], v: &[u8], uv: &mut [u8]),
+    /// Converts as many pixels as the Y row has to RGBA, or BGRA when swapping red and blue.
+    /// Every U and V sample belongs to two pixels.
+    pub yuv_to_rgb: YuvToRgbKernel,
+    /// Converts as many pixels as the Y row has from RGBA, or BGRA with [ordered](RgbToYuv::ordered) coefficients.
+    pub rgb_to_luma: fn(rgb: &[u8], y: &mut [u8], matrix: &RgbToYuv),
+    /// Converts two rows of pixels to as many U and V samples as the U row has,
+    /// each from the average of two by two pixels.
+    pub rgb_to_chroma: RgbToChromaKernel,
+}
+
+type YuvToRgbKernel =
+    fn(y: &[u8], u: &[u8], v: &[u8], rgb: &mut [u8], matrix: &YuvToRgb, swap: bool);
+type RgbToChromaKernel =
+    fn(row0: &[u8], row1: &[u8], u: &mut [u8], v: &mut [u8], matrix: &RgbToYuv);
+
+impl Kernels {
+    pub const SCALAR: Kernels = Kernels {
+        name: "scalar",
+        deinterleave,
+        interleave,
+        yuv_to_rgb,
+        rgb_to_luma,
+        rgb_to_chroma,
+    };
+
+    /// The fastest implementation the CPU supports.
+    pub fn detect() -> Self {
+        *Self::available().last().unwrap_or(&Self::SCALAR)
+    }
+
+    /// Every implementation the CPU supports, from the slowest to the fastest.
+    pub fn available() -> Vec<Self> {
+        let mut kernels = vec![Self::SCALAR];
+        #[cfg(target_arch = "x86_64")]
+        kernels.extend(
+            [super::x86::sse2(), super::x86::avx2()]
+                .into_iter()
+                .flatten(),
+        );
+        #[cfg(target_arch = "aarch64")]
+        kernels.extend(super::neon::neon());
+        kernels
+    }
+}
+
+/// YUV to RGB coefficients of a colorimetry, in fixed point.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct YuvToRgb {
+    pub luma_offset: i16,
+    pub luma: i16,
+    pub red_v: i16,
+    pub green_u: i16,
+    pub green_v: i16,
+    pub blue_u: i16,
+}
+
+impl YuvToRgb {
+    /// The coefficients of [yuv_to_rgb](Colorimetry::yuv_to_rgb), with the offsets subtracted from the values
+    /// before weighing them.
+    pub fn new(colorimetry: Colorimetry) -> Self {
+        let [red, green, blue] = colorimetry.yuv_to_rgb();
+        Self {
+            luma_offset: colorimetry.range.scales().0,
+            luma: fixed(red[0]),
+            red_v: fixed(red[2]),
+            green_u: fixed(-green[1]),
+            green_v: fixed(-green[2]),
+            blue_u: fixed(blue[1]),
+        }
+    }
+
+    pub fn rgb(&self, y: u8, u: u8, v: u8) -> [u8; 3] {
+        let luma = (y as i32 - self.luma_offset as i32) * self.luma as i32;
+        let (u, v) = (u as i32 - 128, v as i32 - 128);
+        [
+            clamp((luma + self.red_v as i32 * v + HALF) >> FRACTION_BITS),
+            clamp(
+                (luma - self.green_u as i32 * u - self.green_v as i32 * v + HALF) >> FRACTION_BITS,
+            ),
+            clamp((luma + self.blue_u as i32 * u + HALF) >> FRACTION_BITS),
+        ]
+    }
+}
+
+/// RGB to YUV coefficients of a colorimetry, in fixed point and weighing red, green and blue in this order.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct RgbToYuv {
+    pub luma_offset: i16,
+    pub y: [i16; 3],
+    pub u: [i16; 3],
+    pub v: [i16; 3],
+}
+
+impl RgbToYuv {
+    pub fn new(colorimetry: Colorimetry) -> Self {
+        let (kr, kb) = colorimetry.matrix.weights();
+        let kg = 1.0 - kr - kb;
+        let (luma_offset, luma_scale, chroma_scale) = colorimetry.range.scales();
+        let u_scale = chroma_scale / (2.0 * (1.0 - kb));
+        let v_scale = chroma_scale / (2.0 * (1.0 - kr));
+        Self {
+            luma_offset,
+            y: [kr, kg, kb].map(|k| fixed(k * luma_scale)),
+            u: [-kr, -kg, 1.0 - kb].map(|k| fixed(k * u_scale)),
+            v: [1.0 - kr, -kg, -kb].map(|k| fixed(k * v_scale)),
+        }
+    }
+
+    /// Weighs the channels in memory order, blue, green and red for BGRA.
+    pub fn ordered(mut self, bgr: bool) -> Self {
+        if bgr {
+            self.y.swap(0, 2);
+            self.u.swap(0, 2);
+            self.v.swap(0, 2);
+        }
+        self
+    }
+
+    pub fn luma(&self, rgb: [i32; 3]) -> u8 {
+        clamp(self.luma_offset as i32 + ((dot(self.y, rgb) + HALF) >> FRACTION_BITS))
+    }
+
+    pub fn chroma(&self, rgb: [i32; 3]) -> (u8, u8) {
+        let u = 128 + ((dot(self.u, rgb) + HALF) >> FRACTION_BITS);
+        let v = 128 + ((dot(self.v, rgb) + HALF) >> FRACTION_BITS);
+        (clamp(u), clamp(v))
+    }
+}
+
+fn dot(coefficients: [i16; 3], values: [i32; 3]) -> i32 {
+    coefficients
+        .iter()
+        .zip(values)
+        .map(|(coefficient, value)| *coefficient as i32 * value)
+        .sum()
+}
+
+fn fixed(value: f32) -> i16 {
+    (value * (1 << FRACTION_BITS) as f32).round() as i16
+}
+
+pub(super) fn clamp(value: i32) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+pub(super) fn deinterleave(uv: &[u8], u: &mut [u8], v: &mut [u8]) {
+    for ((pair, u), v) in uv.chunks_exact(2).zip(u).zip(v) {
+        *u = pair[0];
+        *v = pair[1];
+    }
+}
+
+pub(super) fn interleave(u: &[u8], v: &[u8], uv: &mut [u8]) {
+    for ((pair, u), v) in uv.chunks_exact_mut(2).zip(u).zip(v) {
+        pair[0] = *u;
+        pair[1] = *v;
+    }
+}
+
+pub(super) fn yuv_to_rgb(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    rgb: &mut [u8],
+    matrix: &YuvToRgb,
+    swap: bool,
+) {
+    for (x, (luma, pixel)) in y.iter().zip(rgb.chunks_exact_mut(4)).enumerate() {
+        let [red, green, blue] = matrix.rgb(*luma, u[x / 2], v[x / 2]);
+        let (red, blue) = if swap { (blue, red) } else { (red, blue) };
+        pixel.copy_from_slice(&[red, green, blue, 255]);
+    }
+}
+
+pub(super) fn rgb_to_luma(rgb: &[u8], y: &mut [u8], matrix: &RgbToYuv) {
+    for (pixel, luma) in rgb.chunks_exact(4).zip(y) {
+        *luma = matrix.luma([pixel[0] as i32, pixel[1] as i32, pixel[2] as i32]);
+    }
+}
+
+/// The last pixel of an odd row counts twice, which averages the same as counting it once.
+pub(super) fn rgb_to_chroma(
+    row0: &[u8],
+    row1: &[u8],
+    u: &mut [u8],
+    v: &mut [u8],
+    matrix: &RgbToYuv,
+) {
+    let width = row0.len().min(row1.len()) / 4;
+    for (index, (u, v)) in u.iter_mut().zip(v).enumerate() {
+        let left = 2 * index;
+        let right = (left + 1).min(width - 1);
+        let mut sum = [0; 3];
+        for row in [row0, row1] {
+            for x in [left, right] {
+                for (sum, value) in sum.iter_mut().zip(&row[4 * x..4 * x + 3]) {
+                    *sum += *value as i32;
+                }
+            }
+        }
+        (*u, *v) = matrix.chroma(sum.map(|sum| (sum + 2) >> 2));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        hint::black_box,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::frame::Range;
+
+    /// Xorshift, random enough for test data.
+    struct Random(u32);
+
+    impl Random {
+        fn bytes(&mut self, count: usize) -> Vec<u8> {
+            (0..count)
+                .map(|_| {
+                    self.0 ^= self.0 << 13;
+                    self.0 ^= self.0 >> 17;
+                    self.0 ^= self.0 << 5;
+                    (self.0 >> 24) as u8
+                })
+                .collect()
+        }
+    }
+
+    /// Runs every kernel of every implementation on random rows of any width, starting at any alignment,
+    /// so the SIMD blocks and the scalar rest both have to give what the scalar kernels give.
+    #[test]
+    fn simd_matches_scalar() {
+        let mut random = Random(0x2545_f491);
+        let colorimetries = [
+            Colorimetry::BT601,
+            Colorimetry {
+                range: Range::Full,
+                ..Colorimetry::BT709
+            },
+        ];
+        for kernels in Kernels::available() {
+            for width in 1..100 {
+                let chroma_width = (width + 1) / 2;
+                for offset in 0..4 {
+                    let luma = random.bytes(offset + width);
+                    let u = random.bytes(offset + chroma_width);
+                    let v = random.bytes(offset + chroma_width);
+                    let uv = random.bytes(offset + 2 * chroma_width);
+                    let row0 = random.bytes(offset + 4 * width);
+                    let row1 = random.bytes(offset + 4 * width);
+                    let (luma, u, v, uv) =
+                        (&luma[offset..], &u[offset..], &v[offset..], &uv[offset..]);
+                    let (row0, row1) = (&row0[offset..], &row1[offset..]);
+
+                    let check = |kernel: &str, size: usize, run: &dyn Fn(&Kernels, &mut [u8])| {
+                        let mut expected = vec![0x55; offset + size];
+                        let mut actual = expected.clone();
+                        run(&Kernels::SCALAR, &mut expected[offset..]);
+                        run(&kernels, &mut actual[offset..]);
+                        assert_eq!(
+                            expected, actual,
+                            "{} {kernel} of {width} pixels at offset {offset}",
+                            kernels.name
+                        );
+                    };
+
+                    check("deinterleave", 2 * chroma_width, &|kernels, out| {
+                        let (u, v) = out.split_at_mut(chroma_width);
+                        (kernels.deinterleave)(uv, u, v)
+                    });
+                    check("interleave", 2 * chroma_width, &|kernels, out| {
+                        (kernels.interleave)(u, v, out)
+                    });
+                    for colorimetry in colorimetries {
+                        let to_rgb = YuvToRgb::new(colorimetry);
+                        for swap in [false, true] {
+                            check("yuv_to_rgb", 4 * width, &|kernels, out| {
+                                (kernels.yuv_to_rgb)(luma, u, v, out, &to_rgb, swap)
+                            });
+                        }
+                        for bgr in [false, true] {
+                            let to_yuv = RgbToYuv::new(colorimetry).ordered(bgr);
+                            check("rgb_to_luma", width, &|kernels, out| {
+                                (kernels.rgb_to_luma)(row0, out, &to_yuv)
+                            });
+                            check("rgb_to_chroma", 2 * chroma_width, &|kernels, out| {
+                                let (u, v) = out.split_at_mut(chroma_width);
+                                (kernels.rgb_to_chroma)(row0, row1, u, v, &to_yuv)
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Measures the throughput of every kernel of every [available](Kernels::available) implementation
+    /// on 1080p frames and prints it, with `cargo test --release benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark() {
+        let luma: Vec<u8> = (0..WIDTH).map(|x| (x * 7 % 256) as u8).collect();
+        let chroma: Vec<u8> = (0..WIDTH).map(|x| (x * 13 % 256) as u8).collect();
+        let (u, v) = chroma.split_at(WIDTH / 2);
+        let rgb: Vec<u8> = (0..WIDTH * 4).map(|x| (x * 5 % 256) as u8).collect();
+        let (mut u_out, mut v_out) = (vec![0; WIDTH / 2], vec![0; WIDTH / 2]);
+        let (mut uv_out, mut y_out, mut rgb_out) =
+            (vec![0; WIDTH], vec![0; WIDTH], vec![0; WIDTH * 4]);
+        let to_rgb = YuvToRgb::new(Colorimetry::default());
+        let to_yuv = RgbToYuv::new(Colorimetry::default());
+
+        // black_box keeps the compiler from hoisting the kernels out of the loops or dropping them
+        println!("kernel throughput in 1080p frames per second");
+        for kernels in Kernels::available() {
+            let name = kernels.name;
+            measure(name, "deinterleave", HEIGHT / 2, || {
+                (kernels.deinterleave)(
+                    black_box(&chroma),
+                    black_box(&mut u_out),
+                    black_box(&mut v_out),
+                )
+            });
+            measure(name, "interleave", HEIGHT / 2, || {
+                (kernels.interleave)(black_box(u), black_box(v), black_box(&mut uv_out))
+            });
+            measure(name, "yuv_to_rgb", HEIGHT, || {
+                (kernels.yuv_to_rgb)(
+                    black_box(&luma),
+                    black_box(u),
+                    black_box(v),
+                    black_box(&mut rgb_out),
+                    &to_rgb,
+                    false,
+                )
+            });
+            measure(name, "rgb_to_luma", HEIGHT, || {
+                (kernels.rgb_to_luma)(black_box(&rgb), black_box(&mut y_out), &to_yuv)
+            });
+            measure(name, "rgb_to_chroma", HEIGHT / 2, || {
+                (kernels.rgb_to_chroma)(
+                    black_box(&rgb),
+                    black_box(&rgb),
+                    black_box(&mut u_out),
+                    black_box(&mut v_out),
+                    &to_yuv,
+                )
+            });
+        }
+    }
+
+    const WIDTH: usize = 1920;
+    const HEIGHT: usize = 1080;
+    const FRAMES: u32 = 20;
+
+    /// Runs a kernel for the rows of some frames.
+    fn measure(implementation: &str, kernel: &str, rows: usize, mut run: impl FnMut()) {
+        let start = Instant::now();
+        for _ in 0..FRAMES as usize * rows {
+            run();
+        }
+        let elapsed = start.elapsed().max(Duration::from_nanos(1));
+        let fps = FRAMES as f64 / elapsed.as_secs_f64();
+        println!("{implementation:>8} {kernel:<14} {fps:>8.0} fps");
+    }
+}
@@ -0,0 +1,216 @@
+//! NEON [kernels](Kernels). Multiplications widen 16 bit lanes into 32 bit sums,
+//! which are the same sums the scalar kernels compute.
+
+use std::arch::aarch64::*;
+
+use super::kernels::{self, Kernels, RgbToYuv, YuvToRgb, FRACTION_BITS, HALF};
+
+pub(super) fn neon() -> Option<Kernels> {
+    std::arch::is_aarch64_feature_detected!("neon").then_some(Kernels {
+        name: "neon",
+        deinterleave: |uv, u, v| unsafe { deinterleave_neon(uv, u, v) },
+        interleave: |u, v, uv| unsafe { interleave_neon(u, v, uv) },
+        yuv_to_rgb: |y, u, v, rgb, matrix, swap| unsafe {
+            yuv_to_rgb_neon(y, u, v, rgb, matrix, swap)
+        },
+        rgb_to_luma: |rgb, y, matrix| unsafe { rgb_to_luma_neon(rgb, y, matrix) },
+        rgb_to_chroma: |row0, row1, u, v, matrix| unsafe {
+            rgb_to_chroma_neon(row0, row1, u, v, matrix)
+        },
+    })
+}
+
+/// How many samples of a row both kernels can process in whole blocks of `block` samples.
+fn blocks(samples: usize, block: usize) -> usize {
+    samples / block * block
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn deinterleave_neon(uv: &[u8], u: &mut [u8], v: &mut [u8]) {
+    let count = u.len().min(v.len()).min(uv.len() / 2);
+    let blocks = blocks(count, 16);
+    for x in (0..blocks).step_by(16) {
+        let samples = vld2q_u8(uv.as_ptr().add(2 * x));
+        vst1q_u8(u.as_mut_ptr().add(x), samples.0);
+        vst1q_u8(v.as_mut_ptr().add(x), samples.1);
+    }
+    kernels::deinterleave(
+        &uv[2 * blocks..],
+        &mut u[blocks..count],
+        &mut v[blocks..count],
+    );
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn interleave_neon(u: &[u8], v: &[u8], uv: &mut [u8]) {
+    let count = u.len().min(v.len()).min(uv.len() / 2);
+    let blocks = blocks(count, 16);
+    for x in (0..blocks).step_by(16) {
+        let samples = uint8x16x2_t(vld1q_u8(u.as_ptr().add(x)), vld1q_u8(v.as_ptr().add(x)));
+        vst2q_u8(uv.as_mut_ptr().add(2 * x), samples);
+    }
+    kernels::interleave(&u[blocks..count], &v[blocks..count], &mut uv[2 * blocks..]);
+}
+
+/// `a * ca + b * cb + c * cc` of eight 16 bit lanes, rounded back from fixed point.
+#[target_feature(enable = "neon")]
+unsafe fn dot_neon(
+    a: int16x8_t,
+    ca: i16,
+    b: int16x8_t,
+    cb: i16,
+    c: int16x8_t,
+    cc: i16,
+) -> int16x8_t {
+    let half = vdupq_n_s32(HALF);
+    let low = vmlal_n_s16(half, vget_low_s16(a), ca);
+    let low = vmlal_n_s16(low, vget_low_s16(b), cb);
+    let low = vmlal_n_s16(low, vget_low_s16(c), cc);
+    let high = vmlal_n_s16(half, vget_high_s16(a), ca);
+    let high = vmlal_n_s16(high, vget_high_s16(b), cb);
+    let high = vmlal_n_s16(high, vget_high_s16(c), cc);
+    vcombine_s16(
+        vqmovn_s32(vshrq_n_s32::<FRACTION_BITS>(low)),
+        vqmovn_s32(vshrq_n_s32::<FRACTION_BITS>(high)),
+    )
+}
+
+/// The lower or upper eight bytes as 16 bit lanes.
+#[target_feature(enable = "neon")]
+unsafe fn widen_neon(bytes: uint8x8_t) -> int16x8_t {
+    vreinterpretq_s16_u16(vmovl_u8(bytes))
+}
+
+/// Two times eight 16 bit lanes to bytes.
+#[target_feature(enable = "neon")]
+unsafe fn narrow_neon(low: int16x8_t, high: int16x8_t) -> uint8x16_t {
+    vcombine_u8(vqmovun_s16(low), vqmovun_s16(high))
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn yuv_to_rgb_neon(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    rgb: &mut [u8],
+    matrix: &YuvToRgb,
+    swap: bool,
+) {
+    let count = y.len().min(rgb.len() / 4);
+    let chroma = u.len().min(v.len());
+    let blocks = blocks(count.min(2 * chroma), 16);
+    let zero = vdupq_n_s16(0);
+    let luma_offset = vdupq_n_s16(matrix.luma_offset);
+    let chroma_offset = vdupq_n_s16(128);
+    let alpha = vdupq_n_u8(255);
+    for x in (0..blocks).step_by(16) {
+        let luma = vld1q_u8(y.as_ptr().add(x));
+        // every chroma sample for two pixels
+        let us = vld1_u8(u.as_ptr().add(x / 2));
+        let us = vzip_u8(us, us);
+        let vs = vld1_u8(v.as_ptr().add(x / 2));
+        let vs = vzip_u8(vs, vs);
+
+        let halves = [
+            (vget_low_u8(luma), us.0, vs.0),
+            (vget_high_u8(luma), us.1, vs.1),
+        ];
+        let mut channels = [[zero; 2]; 3];
+        for (index, (luma, u, v)) in halves.into_iter().enumerate() {
+            let luma = vsubq_s16(widen_neon(luma), luma_offset);
+            let u = vsubq_s16(widen_neon(u), chroma_offset);
+            let v = vsubq_s16(widen_neon(v), chroma_offset);
+            channels[0][index] = dot_neon(luma, matrix.luma, v, matrix.red_v, zero, 0);
+            channels[1][index] =
+                dot_neon(luma, matrix.luma, u, -matrix.green_u, v, -matrix.green_v);
+            channels[2][index] = dot_neon(luma, matrix.luma, u, matrix.blue_u, zero, 0);
+        }
+        let mut r = narrow_neon(channels[0][0], channels[0][1]);
+        let g = narrow_neon(channels[1][0], channels[1][1]);
+        let mut b = narrow_neon(channels[2][0], channels[2][1]);
+        if swap {
+            std::mem::swap(&mut r, &mut b);
+        }
+        vst4q_u8(rgb.as_mut_ptr().add(4 * x), uint8x16x4_t(r, g, b, alpha));
+    }
+    kernels::yuv_to_rgb(
+        &y[blocks..count],
+        &u[blocks / 2..],
+        &v[blocks / 2..],
+        &mut rgb[4 * blocks..],
+        matrix,
+        swap,
+    );
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn rgb_to_luma_neon(rgb: &[u8], y: &mut [u8], matrix: &RgbToYuv) {
+    let count = y.len().min(rgb.len() / 4);
+    let blocks = blocks(count, 16);
+    let offset = vdupq_n_s16(matrix.luma_offset);
+    let [w0, w1, w2] = matrix.y;
+    for x in (0..blocks).step_by(16) {
+        let pixels = vld4q_u8(rgb.as_ptr().add(4 * x));
+        let low = dot_neon(
+            widen_neon(vget_low_u8(pixels.0)),
+            w0,
+            widen_neon(vget_low_u8(pixels.1)),
+            w1,
+            widen_neon(vget_low_u8(pixels.2)),
+            w2,
+        );
+        let high = dot_neon(
+            widen_neon(vget_high_u8(pixels.0)),
+            w0,
+            widen_neon(vget_high_u8(pixels.1)),
+            w1,
+            widen_neon(vget_high_u8(pixels.2)),
+            w2,
+        );
+        let luma = narrow_neon(vaddq_s16(low, offset), vaddq_s16(high, offset));
+        vst1q_u8(y.as_mut_ptr().add(x), luma);
+    }
+    kernels::rgb_to_luma(&rgb[4 * blocks..], &mut y[blocks..count], matrix);
+}
+
+/// The average of a channel over blocks of two by two pixels.
+#[target_feature(enable = "neon")]
+unsafe fn average_neon(top: uint8x16_t, bottom: uint8x16_t) -> int16x8_t {
+    let sums = vpadalq_u8(vpaddlq_u8(top), bottom);
+    vreinterpretq_s16_u16(vshrq_n_u16::<2>(vaddq_u16(sums, vdupq_n_u16(2))))
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn rgb_to_chroma_neon(
+    row0: &[u8],
+    row1: &[u8],
+    u: &mut [u8],
+    v: &mut [u8],
+    matrix: &RgbToYuv,
+) {
+    // only pairs of pixels, an odd last pixel is left to the scalar kernel
+    let count = u.len().min(v.len());
+    let blocks = blocks(count.min(row0.len().min(row1.len()) / 8), 8);
+    let offset = vdupq_n_s16(128);
+    for x in (0..blocks).step_by(8) {
+        let top = vld4q_u8(row0.as_ptr().add(8 * x));
+        let bottom = vld4q_u8(row1.as_ptr().add(8 * x));
+        let averages = [
+            average_neon(top.0, bottom.0),
+            average_neon(top.1, bottom.1),
+            average_neon(top.2, bottom.2),
+        ];
+        for (weights, out) in [(matrix.u, u.as_mut_ptr()), (matrix.v, v.as_mut_ptr())] {
+            let [w0, w1, w2] = weights;
+            let sums = dot_neon(averages[0], w0, averages[1], w1, averages[2], w2);
+            vst1_u8(out.add(x), vqmovun_s16(vaddq_s16(sums, offset)));
+        }
+    }
+    kernels::rgb_to_chroma(
+        &row0[8 * blocks..],
+        &row1[8 * blocks..],
+        &mut u[blocks..count],
+        &mut v[blocks..count],
+        matrix,
+    );
+}
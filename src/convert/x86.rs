@@ -0,0 +1,558 @@
+//! SSE2 and AVX2 [kernels](Kernels). Multiplications go through `madd` on 16 bit lanes into 32 bit sums,
+//! which are the same sums the scalar kernels compute.
+
+use std::arch::x86_64::*;
+
+use super::kernels::{self, Kernels, RgbToYuv, YuvToRgb, FRACTION_BITS, HALF};
+
+pub(super) fn sse2() -> Option<Kernels> {
+    is_x86_feature_detected!("sse2").then_some(Kernels {
+        name: "sse2",
+        deinterleave: |uv, u, v| unsafe { deinterleave_sse2(uv, u, v) },
+        interleave: |u, v, uv| unsafe { interleave_sse2(u, v, uv) },
+        yuv_to_rgb: |y, u, v, rgb, matrix, swap| unsafe {
+            yuv_to_rgb_sse2(y, u, v, rgb, matrix, swap)
+        },
+        rgb_to_luma: |rgb, y, matrix| unsafe { rgb_to_luma_sse2(rgb, y, matrix) },
+        rgb_to_chroma: |row0, row1, u, v, matrix| unsafe {
+            rgb_to_chroma_sse2(row0, row1, u, v, matrix)
+        },
+    })
+}
+
+pub(super) fn avx2() -> Option<Kernels> {
+    is_x86_feature_detected!("avx2").then_some(Kernels {
+        name: "avx2",
+        deinterleave: |uv, u, v| unsafe { deinterleave_avx2(uv, u, v) },
+        interleave: |u, v, uv| unsafe { interleave_avx2(u, v, uv) },
+        yuv_to_rgb: |y, u, v, rgb, matrix, swap| unsafe {
+            yuv_to_rgb_avx2(y, u, v, rgb, matrix, swap)
+        },
+        rgb_to_luma: |rgb, y, matrix| unsafe { rgb_to_luma_avx2(rgb, y, matrix) },
+        rgb_to_chroma: |row0, row1, u, v, matrix| unsafe {
+            rgb_to_chroma_avx2(row0, row1, u, v, matrix)
+        },
+    })
+}
+
+/// Two 16 bit coefficients for `madd`, the first one for the lower lane.
+fn pair(first: i16, second: i16) -> i32 {
+    (first as u16 as i32) | ((second as i32) << 16)
+}
+
+/// The coefficients for `madd` on a pixel of four 16 bit channels, alpha weighing nothing.
+fn channel_weights(weights: [i16; 3]) -> [i32; 2] {
+    [pair(weights[0], weights[1]), pair(weights[2], 0)]
+}
+
+/// Orders the 64 bit lanes 0, 2, 1, 3, to undo packing within 128 bit lanes.
+const INTERLEAVED_LANES: i32 = 0b11_01_10_00;
+
+/// How many samples of a row both kernels can process in whole blocks of `block` samples.
+fn blocks(samples: usize, block: usize) -> usize {
+    samples / block * block
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn deinterleave_sse2(uv: &[u8], u: &mut [u8], v: &mut [u8]) {
+    let count = u.len().min(v.len()).min(uv.len() / 2);
+    let blocks = blocks(count, 16);
+    let mask = _mm_set1_epi16(0x00ff);
+    for x in (0..blocks).step_by(16) {
+        let a = _mm_loadu_si128(uv.as_ptr().add(2 * x) as *const __m128i);
+        let b = _mm_loadu_si128(uv.as_ptr().add(2 * x + 16) as *const __m128i);
+        let us = _mm_packus_epi16(_mm_and_si128(a, mask), _mm_and_si128(b, mask));
+        let vs = _mm_packus_epi16(_mm_srli_epi16(a, 8), _mm_srli_epi16(b, 8));
+        _mm_storeu_si128(u.as_mut_ptr().add(x) as *mut __m128i, us);
+        _mm_storeu_si128(v.as_mut_ptr().add(x) as *mut __m128i, vs);
+    }
+    kernels::deinterleave(
+        &uv[2 * blocks..],
+        &mut u[blocks..count],
+        &mut v[blocks..count],
+    );
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn interleave_sse2(u: &[u8], v: &[u8], uv: &mut [u8]) {
+    let count = u.len().min(v.len()).min(uv.len() / 2);
+    let blocks = blocks(count, 16);
+    for x in (0..blocks).step_by(16) {
+        let us = _mm_loadu_si128(u.as_ptr().add(x) as *const __m128i);
+        let vs = _mm_loadu_si128(v.as_ptr().add(x) as *const __m128i);
+        let out = uv.as_mut_ptr().add(2 * x) as *mut __m128i;
+        _mm_storeu_si128(out, _mm_unpacklo_epi8(us, vs));
+        _mm_storeu_si128(out.add(1), _mm_unpackhi_epi8(us, vs));
+    }
+    kernels::interleave(&u[blocks..count], &v[blocks..count], &mut uv[2 * blocks..]);
+}
+
+/// `a * ab.0 + b * ab.1 + c * cd.0 + d * cd.1` of eight 16 bit lanes, back from fixed point.
+#[target_feature(enable = "sse2")]
+unsafe fn dot_sse2(
+    a: __m128i,
+    b: __m128i,
+    ab: __m128i,
+    c: __m128i,
+    d: __m128i,
+    cd: __m128i,
+) -> __m128i {
+    let low = _mm_add_epi32(
+        _mm_madd_epi16(_mm_unpacklo_epi16(a, b), ab),
+        _mm_madd_epi16(_mm_unpacklo_epi16(c, d), cd),
+    );
+    let high = _mm_add_epi32(
+        _mm_madd_epi16(_mm_unpackhi_epi16(a, b), ab),
+        _mm_madd_epi16(_mm_unpackhi_epi16(c, d), cd),
+    );
+    _mm_packs_epi32(
+        _mm_srai_epi32(low, FRACTION_BITS),
+        _mm_srai_epi32(high, FRACTION_BITS),
+    )
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn yuv_to_rgb_sse2(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    rgb: &mut [u8],
+    matrix: &YuvToRgb,
+    swap: bool,
+) {
+    let count = y.len().min(rgb.len() / 4);
+    let chroma = u.len().min(v.len());
+    let blocks = blocks(count.min(2 * chroma), 16);
+    let zero = _mm_setzero_si128();
+    let one = _mm_set1_epi16(1);
+    let luma_offset = _mm_set1_epi16(matrix.luma_offset);
+    let chroma_offset = _mm_set1_epi16(128);
+    let red = _mm_set1_epi32(pair(matrix.luma, matrix.red_v));
+    let green = _mm_set1_epi32(pair(matrix.luma, -matrix.green_u));
+    let green_v = _mm_set1_epi32(pair(-matrix.green_v, HALF as i16));
+    let blue = _mm_set1_epi32(pair(matrix.luma, matrix.blue_u));
+    let round = _mm_set1_epi32(pair(HALF as i16, 0));
+    let alpha = _mm_set1_epi8(-1);
+    for x in (0..blocks).step_by(16) {
+        let luma = _mm_loadu_si128(y.as_ptr().add(x) as *const __m128i);
+        let us = _mm_loadl_epi64(u.as_ptr().add(x / 2) as *const __m128i);
+        let vs = _mm_loadl_epi64(v.as_ptr().add(x / 2) as *const __m128i);
+        // every chroma sample for two pixels
+        let us = _mm_unpacklo_epi8(us, us);
+        let vs = _mm_unpacklo_epi8(vs, vs);
+
+        let halves = [
+            (
+                _mm_unpacklo_epi8(luma, zero),
+                _mm_unpacklo_epi8(us, zero),
+                _mm_unpacklo_epi8(vs, zero),
+            ),
+            (
+                _mm_unpackhi_epi8(luma, zero),
+                _mm_unpackhi_epi8(us, zero),
+                _mm_unpackhi_epi8(vs, zero),
+            ),
+        ];
+        let mut channels = [[zero; 2]; 3];
+        for (index, (luma, u, v)) in halves.into_iter().enumerate() {
+            let luma = _mm_sub_epi16(luma, luma_offset);
+            let u = _mm_sub_epi16(u, chroma_offset);
+            let v = _mm_sub_epi16(v, chroma_offset);
+            channels[0][index] = dot_sse2(luma, v, red, one, zero, round);
+            channels[1][index] = dot_sse2(luma, u, green, v, one, green_v);
+            channels[2][index] = dot_sse2(luma, u, blue, one, zero, round);
+        }
+        let [mut r, g, mut b] = channels.map(|[low, high]| _mm_packus_epi16(low, high));
+        if swap {
+            std::mem::swap(&mut r, &mut b);
+        }
+
+        let rg_low = _mm_unpacklo_epi8(r, g);
+        let rg_high = _mm_unpackhi_epi8(r, g);
+        let ba_low = _mm_unpacklo_epi8(b, alpha);
+        let ba_high = _mm_unpackhi_epi8(b, alpha);
+        let out = rgb.as_mut_ptr().add(4 * x) as *mut __m128i;
+        _mm_storeu_si128(out, _mm_unpacklo_epi16(rg_low, ba_low));
+        _mm_storeu_si128(out.add(1), _mm_unpackhi_epi16(rg_low, ba_low));
+        _mm_storeu_si128(out.add(2), _mm_unpacklo_epi16(rg_high, ba_high));
+        _mm_storeu_si128(out.add(3), _mm_unpackhi_epi16(rg_high, ba_high));
+    }
+    kernels::yuv_to_rgb(
+        &y[blocks..count],
+        &u[blocks / 2..],
+        &v[blocks / 2..],
+        &mut rgb[4 * blocks..],
+        matrix,
+        swap,
+    );
+}
+
+/// Adds neighboring 32 bit lanes, like the two halves of the weighted channels of a pixel after `madd`.
+#[target_feature(enable = "sse2")]
+unsafe fn add_pairs_sse2(low: __m128i, high: __m128i) -> __m128i {
+    let low = _mm_shuffle_epi32(low, INTERLEAVED_LANES);
+    let high = _mm_shuffle_epi32(high, INTERLEAVED_LANES);
+    _mm_add_epi32(_mm_unpacklo_epi64(low, high), _mm_unpackhi_epi64(low, high))
+}
+
+/// Weighs the channels of four pixels of 16 bit channels in two registers into four 32 bit sums.
+#[target_feature(enable = "sse2")]
+unsafe fn weigh_sse2(low: __m128i, high: __m128i, weights: __m128i) -> __m128i {
+    add_pairs_sse2(_mm_madd_epi16(low, weights), _mm_madd_epi16(high, weights))
+}
+
+/// Rounds four 32 bit sums back from fixed point and adds the offset.
+#[target_feature(enable = "sse2")]
+unsafe fn round_sse2(sum: __m128i, offset: __m128i) -> __m128i {
+    let rounded = _mm_srai_epi32(_mm_add_epi32(sum, _mm_set1_epi32(HALF)), FRACTION_BITS);
+    _mm_add_epi32(rounded, offset)
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn rgb_to_luma_sse2(rgb: &[u8], y: &mut [u8], matrix: &RgbToYuv) {
+    let count = y.len().min(rgb.len() / 4);
+    let blocks = blocks(count, 16);
+    let zero = _mm_setzero_si128();
+    let [first, second] = channel_weights(matrix.y);
+    let weights = _mm_set_epi32(second, first, second, first);
+    let offset = _mm_set1_epi32(matrix.luma_offset as i32);
+    for x in (0..blocks).step_by(16) {
+        let mut luma = [zero; 4];
+        for (index, luma) in luma.iter_mut().enumerate() {
+            let pixels = _mm_loadu_si128(rgb.as_ptr().add(4 * x + 16 * index) as *const __m128i);
+            let low = _mm_unpacklo_epi8(pixels, zero);
+            let high = _mm_unpackhi_epi8(pixels, zero);
+            *luma = round_sse2(weigh_sse2(low, high, weights), offset);
+        }
+        let low = _mm_packs_epi32(luma[0], luma[1]);
+        let high = _mm_packs_epi32(luma[2], luma[3]);
+        let out = y.as_mut_ptr().add(x) as *mut __m128i;
+        _mm_storeu_si128(out, _mm_packus_epi16(low, high));
+    }
+    kernels::rgb_to_luma(&rgb[4 * blocks..], &mut y[blocks..count], matrix);
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn rgb_to_chroma_sse2(
+    row0: &[u8],
+    row1: &[u8],
+    u: &mut [u8],
+    v: &mut [u8],
+    matrix: &RgbToYuv,
+) {
+    // only pairs of pixels, an odd last pixel is left to the scalar kernel
+    let count = u.len().min(v.len());
+    let blocks = blocks(count.min(row0.len().min(row1.len()) / 8), 4);
+    let zero = _mm_setzero_si128();
+    let two = _mm_set1_epi16(2);
+    let [first, second] = channel_weights(matrix.u);
+    let u_weights = _mm_set_epi32(second, first, second, first);
+    let [first, second] = channel_weights(matrix.v);
+    let v_weights = _mm_set_epi32(second, first, second, first);
+    let offset = _mm_set1_epi32(128);
+    for x in (0..blocks).step_by(4) {
+        // the average channels of two blocks of two by two pixels each
+        let mut averages = [zero; 2];
+        for (index, average) in averages.iter_mut().enumerate() {
+            let start = 8 * x + 16 * index;
+            let top = _mm_loadu_si128(row0.as_ptr().add(start) as *const __m128i);
+            let bottom = _mm_loadu_si128(row1.as_ptr().add(start) as *const __m128i);
+            let low = _mm_add_epi16(
+                _mm_unpacklo_epi8(top, zero),
+                _mm_unpacklo_epi8(bottom, zero),
+            );
+            let high = _mm_add_epi16(
+                _mm_unpackhi_epi8(top, zero),
+                _mm_unpackhi_epi8(bottom, zero),
+            );
+            let low = _mm_add_epi16(low, _mm_srli_si128(low, 8));
+            let high = _mm_add_epi16(high, _mm_srli_si128(high, 8));
+            let sums = _mm_unpacklo_epi64(low, high);
+            *average = _mm_srli_epi16(_mm_add_epi16(sums, two), 2);
+        }
+        let us = round_sse2(weigh_sse2(averages[0], averages[1], u_weights), offset);
+        let vs = round_sse2(weigh_sse2(averages[0], averages[1], v_weights), offset);
+        let packed = _mm_packus_epi16(_mm_packs_epi32(us, vs), zero);
+        (u.as_mut_ptr().add(x) as *mut i32).write_unaligned(_mm_cvtsi128_si32(packed));
+        (v.as_mut_ptr().add(x) as *mut i32)
+            .write_unaligned(_mm_cvtsi128_si32(_mm_srli_si128(packed, 4)));
+    }
+    kernels::rgb_to_chroma(
+        &row0[8 * blocks..],
+        &row1[8 * blocks..],
+        &mut u[blocks..count],
+        &mut v[blocks..count],
+        matrix,
+    );
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn deinterleave_avx2(uv: &[u8], u: &mut [u8], v: &mut [u8]) {
+    let count = u.len().min(v.len()).min(uv.len() / 2);
+    let blocks = blocks(count, 32);
+    let mask = _mm256_set1_epi16(0x00ff);
+    for x in (0..blocks).step_by(32) {
+        let a = _mm256_loadu_si256(uv.as_ptr().add(2 * x) as *const __m256i);
+        let b = _mm256_loadu_si256(uv.as_ptr().add(2 * x + 32) as *const __m256i);
+        let us = _mm256_packus_epi16(_mm256_and_si256(a, mask), _mm256_and_si256(b, mask));
+        let vs = _mm256_packus_epi16(_mm256_srli_epi16(a, 8), _mm256_srli_epi16(b, 8));
+        let us = _mm256_permute4x64_epi64(us, INTERLEAVED_LANES);
+        let vs = _mm256_permute4x64_epi64(vs, INTERLEAVED_LANES);
+        _mm256_storeu_si256(u.as_mut_ptr().add(x) as *mut __m256i, us);
+        _mm256_storeu_si256(v.as_mut_ptr().add(x) as *mut __m256i, vs);
+    }
+    kernels::deinterleave(
+        &uv[2 * blocks..],
+        &mut u[blocks..count],
+        &mut v[blocks..count],
+    );
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn interleave_avx2(u: &[u8], v: &[u8], uv: &mut [u8]) {
+    let count = u.len().min(v.len()).min(uv.len() / 2);
+    let blocks = blocks(count, 32);
+    for x in (0..blocks).step_by(32) {
+        let us = _mm256_loadu_si256(u.as_ptr().add(x) as *const __m256i);
+        let vs = _mm256_loadu_si256(v.as_ptr().add(x) as *const __m256i);
+        // samples 0 to 7 and 16 to 23, then 8 to 15 and 24 to 31
+        let low = _mm256_unpacklo_epi8(us, vs);
+        let high = _mm256_unpackhi_epi8(us, vs);
+        let out = uv.as_mut_ptr().add(2 * x) as *mut __m256i;
+        _mm256_storeu_si256(out, _mm256_permute2x128_si256(low, high, 0x20));
+        _mm256_storeu_si256(out.add(1), _mm256_permute2x128_si256(low, high, 0x31));
+    }
+    kernels::interleave(&u[blocks..count], &v[blocks..count], &mut uv[2 * blocks..]);
+}
+
+/// Like [dot_sse2] for sixteen lanes, which stay in order.
+#[target_feature(enable = "avx2")]
+unsafe fn dot_avx2(
+    a: __m256i,
+    b: __m256i,
+    ab: __m256i,
+    c: __m256i,
+    d: __m256i,
+    cd: __m256i,
+) -> __m256i {
+    let low = _mm256_add_epi32(
+        _mm256_madd_epi16(_mm256_unpacklo_epi16(a, b), ab),
+        _mm256_madd_epi16(_mm256_unpacklo_epi16(c, d), cd),
+    );
+    let high = _mm256_add_epi32(
+        _mm256_madd_epi16(_mm256_unpackhi_epi16(a, b), ab),
+        _mm256_madd_epi16(_mm256_unpackhi_epi16(c, d), cd),
+    );
+    _mm256_packs_epi32(
+        _mm256_srai_epi32(low, FRACTION_BITS),
+        _mm256_srai_epi32(high, FRACTION_BITS),
+    )
+}
+
+/// Sixteen pixels from sixteen bytes, their 16 bit channels in order.
+#[target_feature(enable = "avx2")]
+unsafe fn widen_avx2(bytes: __m128i) -> __m256i {
+    _mm256_cvtepu8_epi16(bytes)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn yuv_to_rgb_avx2(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    rgb: &mut [u8],
+    matrix: &YuvToRgb,
+    swap: bool,
+) {
+    let count = y.len().min(rgb.len() / 4);
+    let chroma = u.len().min(v.len());
+    let blocks = blocks(count.min(2 * chroma), 32);
+    let zero = _mm256_setzero_si256();
+    let one = _mm256_set1_epi16(1);
+    let luma_offset = _mm256_set1_epi16(matrix.luma_offset);
+    let chroma_offset = _mm256_set1_epi16(128);
+    let red = _mm256_set1_epi32(pair(matrix.luma, matrix.red_v));
+    let green = _mm256_set1_epi32(pair(matrix.luma, -matrix.green_u));
+    let green_v = _mm256_set1_epi32(pair(-matrix.green_v, HALF as i16));
+    let blue = _mm256_set1_epi32(pair(matrix.luma, matrix.blue_u));
+    let round = _mm256_set1_epi32(pair(HALF as i16, 0));
+    let alpha = _mm256_set1_epi8(-1);
+    for x in (0..blocks).step_by(32) {
+        // red, green and blue of the first and the second sixteen pixels
+        let mut halves = [[zero; 3]; 2];
+        for (index, channels) in halves.iter_mut().enumerate() {
+            let start = x + 16 * index;
+            let luma = _mm_loadu_si128(y.as_ptr().add(start) as *const __m128i);
+            let us = _mm_loadl_epi64(u.as_ptr().add(start / 2) as *const __m128i);
+            let vs = _mm_loadl_epi64(v.as_ptr().add(start / 2) as *const __m128i);
+            let luma = _mm256_sub_epi16(widen_avx2(luma), luma_offset);
+            let u = _mm256_sub_epi16(widen_avx2(_mm_unpacklo_epi8(us, us)), chroma_offset);
+            let v = _mm256_sub_epi16(widen_avx2(_mm_unpacklo_epi8(vs, vs)), chroma_offset);
+            *channels = [
+                dot_avx2(luma, v, red, one, zero, round),
+                dot_avx2(luma, u, green, v, one, green_v),
+                dot_avx2(luma, u, blue, one, zero, round),
+            ];
+        }
+        let [first, second] = halves;
+        let mut r = pack_avx2(first[0], second[0]);
+        let g = pack_avx2(first[1], second[1]);
+        let mut b = pack_avx2(first[2], second[2]);
+        if swap {
+            std::mem::swap(&mut r, &mut b);
+        }
+
+        // pixels 0 to 7 and 16 to 23, then 8 to 15 and 24 to 31
+        let rg_low = _mm256_unpacklo_epi8(r, g);
+        let rg_high = _mm256_unpackhi_epi8(r, g);
+        let ba_low = _mm256_unpacklo_epi8(b, alpha);
+        let ba_high = _mm256_unpackhi_epi8(b, alpha);
+        // pixels 0 to 3 and 16 to 19, 4 to 7 and 20 to 23, and so on
+        let a = _mm256_unpacklo_epi16(rg_low, ba_low);
+        let b = _mm256_unpackhi_epi16(rg_low, ba_low);
+        let c = _mm256_unpacklo_epi16(rg_high, ba_high);
+        let d = _mm256_unpackhi_epi16(rg_high, ba_high);
+        let out = rgb.as_mut_ptr().add(4 * x) as *mut __m256i;
+        _mm256_storeu_si256(out, _mm256_permute2x128_si256(a, b, 0x20));
+        _mm256_storeu_si256(out.add(1), _mm256_permute2x128_si256(c, d, 0x20));
+        _mm256_storeu_si256(out.add(2), _mm256_permute2x128_si256(a, b, 0x31));
+        _mm256_storeu_si256(out.add(3), _mm256_permute2x128_si256(c, d, 0x31));
+    }
+    kernels::yuv_to_rgb(
+        &y[blocks..count],
+        &u[blocks / 2..],
+        &v[blocks / 2..],
+        &mut rgb[4 * blocks..],
+        matrix,
+        swap,
+    );
+}
+
+/// Sixteen 16 bit lanes of each register to bytes, in order.
+#[target_feature(enable = "avx2")]
+unsafe fn pack_avx2(low: __m256i, high: __m256i) -> __m256i {
+    _mm256_permute4x64_epi64(_mm256_packus_epi16(low, high), INTERLEAVED_LANES)
+}
+
+/// Like [add_pairs_sse2] within each 128 bit lane.
+#[target_feature(enable = "avx2")]
+unsafe fn add_pairs_avx2(low: __m256i, high: __m256i) -> __m256i {
+    let low = _mm256_shuffle_epi32(low, INTERLEAVED_LANES);
+    let high = _mm256_shuffle_epi32(high, INTERLEAVED_LANES);
+    _mm256_add_epi32(
+        _mm256_unpacklo_epi64(low, high),
+        _mm256_unpackhi_epi64(low, high),
+    )
+}
+
+/// Like [round_sse2] for eight sums.
+#[target_feature(enable = "avx2")]
+unsafe fn round_avx2(sum: __m256i, offset: __m256i) -> __m256i {
+    let rounded = _mm256_srai_epi32(
+        _mm256_add_epi32(sum, _mm256_set1_epi32(HALF)),
+        FRACTION_BITS,
+    );
+    _mm256_add_epi32(rounded, offset)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn rgb_to_luma_avx2(rgb: &[u8], y: &mut [u8], matrix: &RgbToYuv) {
+    let count = y.len().min(rgb.len() / 4);
+    let blocks = blocks(count, 32);
+    let zero = _mm256_setzero_si256();
+    let [first, second] = channel_weights(matrix.y);
+    let weights = _mm256_set_epi32(second, first, second, first, second, first, second, first);
+    let offset = _mm256_set1_epi32(matrix.luma_offset as i32);
+    for x in (0..blocks).step_by(32) {
+        // eight pixels each, in order
+        let mut luma = [zero; 4];
+        for (index, luma) in luma.iter_mut().enumerate() {
+            let pixels = rgb.as_ptr().add(4 * x + 32 * index) as *const __m256i;
+            let pixels = _mm256_loadu_si256(pixels);
+            let low = _mm256_madd_epi16(_mm256_unpacklo_epi8(pixels, zero), weights);
+            let high = _mm256_madd_epi16(_mm256_unpackhi_epi8(pixels, zero), weights);
+            *luma = round_avx2(add_pairs_avx2(low, high), offset);
+        }
+        let low = _mm256_permute4x64_epi64(_mm256_packs_epi32(luma[0], luma[1]), INTERLEAVED_LANES);
+        let high =
+            _mm256_permute4x64_epi64(_mm256_packs_epi32(luma[2], luma[3]), INTERLEAVED_LANES);
+        let packed = _mm256_permute4x64_epi64(_mm256_packus_epi16(low, high), INTERLEAVED_LANES);
+        _mm256_storeu_si256(y.as_mut_ptr().add(x) as *mut __m256i, packed);
+    }
+    kernels::rgb_to_luma(&rgb[4 * blocks..], &mut y[blocks..count], matrix);
+}
+
+/// Weighs the average channels of eight blocks into eight rounded sums, in order.
+#[target_feature(enable = "avx2")]
+unsafe fn weigh_blocks_avx2(
+    averages: [__m256i; 2],
+    weights: __m256i,
+    block_order: __m256i,
+    offset: __m256i,
+) -> __m256i {
+    let low = _mm256_madd_epi16(averages[0], weights);
+    let high = _mm256_madd_epi16(averages[1], weights);
+    let sums = _mm256_permutevar8x32_epi32(add_pairs_avx2(low, high), block_order);
+    round_avx2(sums, offset)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn rgb_to_chroma_avx2(
+    row0: &[u8],
+    row1: &[u8],
+    u: &mut [u8],
+    v: &mut [u8],
+    matrix: &RgbToYuv,
+) {
+    let count = u.len().min(v.len());
+    let blocks = blocks(count.min(row0.len().min(row1.len()) / 8), 8);
+    let zero = _mm256_setzero_si256();
+    let two = _mm256_set1_epi16(2);
+    let [first, second] = channel_weights(matrix.u);
+    let u_weights = _mm256_set_epi32(second, first, second, first, second, first, second, first);
+    let [first, second] = channel_weights(matrix.v);
+    let v_weights = _mm256_set_epi32(second, first, second, first, second, first, second, first);
+    let offset = _mm256_set1_epi32(128);
+    // adding pairs within 128 bit lanes leaves the blocks as 0, 1, 4, 5, 2, 3, 6, 7
+    let block_order = _mm256_setr_epi32(0, 1, 4, 5, 2, 3, 6, 7);
+    // four U, four V, twice, for each 128 bit lane
+    let sample_order = _mm256_setr_epi32(0, 4, 1, 5, 2, 6, 3, 7);
+    for x in (0..blocks).step_by(8) {
+        // the average channels of four blocks of two by two pixels each, in order
+        let mut averages = [zero; 2];
+        for (index, average) in averages.iter_mut().enumerate() {
+            let start = 8 * x + 32 * index;
+            let top = _mm256_loadu_si256(row0.as_ptr().add(start) as *const __m256i);
+            let bottom = _mm256_loadu_si256(row1.as_ptr().add(start) as *const __m256i);
+            let low = _mm256_add_epi16(
+                _mm256_unpacklo_epi8(top, zero),
+                _mm256_unpacklo_epi8(bottom, zero),
+            );
+            let high = _mm256_add_epi16(
+                _mm256_unpackhi_epi8(top, zero),
+                _mm256_unpackhi_epi8(bottom, zero),
+            );
+            let low = _mm256_add_epi16(low, _mm256_srli_si256(low, 8));
+            let high = _mm256_add_epi16(high, _mm256_srli_si256(high, 8));
+            let sums = _mm256_unpacklo_epi64(low, high);
+            *average = _mm256_srli_epi16(_mm256_add_epi16(sums, two), 2);
+        }
+        let us = weigh_blocks_avx2(averages, u_weights, block_order, offset);
+        let vs = weigh_blocks_avx2(averages, v_weights, block_order, offset);
+        let packed = _mm256_packs_epi32(us, vs);
+        let packed = _mm256_packus_epi16(packed, packed);
+        let samples = _mm256_castsi256_si128(_mm256_permutevar8x32_epi32(packed, sample_order));
+        _mm_storel_epi64(u.as_mut_ptr().add(x) as *mut __m128i, samples);
+        _mm_storel_epi64(
+            v.as_mut_ptr().add(x) as *mut __m128i,
+            _mm_srli_si128(samples, 8),
+        );
+    }
+    kernels::rgb_to_chroma(
+        &row0[8 * blocks..],
+        &row1[8 * blocks..],
+        &mut u[blocks..count],
+        &mut v[blocks..count],
+        matrix,
+    );
+}
@@ -0,0 +1,368 @@
+use std::{
+    borrow::Cow,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+
+mod color;
+mod video_frame;
+
+pub use color::{Colorimetry, Matrix, Primaries, Range, Transfer};
+pub use video_frame::{BufferPool, PlaneMut, PooledBuffer, VideoFrame};
+
+/// Most planes a frame can have, like Y, U and V.
+pub const MAX_PLANES: usize = 3;
+
+/// A video frame of any [source](crate::source::VideoSource).
+/// You can find out about the [format](Frame::format) and get a reference to the [pixel data](Frame::pixels).
+pub trait Frame: Send + Sync {
+    fn format(&self) -> SampleFormat;
+    fn pixels(&self) -> Pixels<'_>;
+
+    /// When the frame was captured, on the clock of its source, like the host time on Mac
+    /// or the monotonic clock with V4L2. Only differences between frames of the same source are meaningful.
+    fn timestamp(&self) -> Duration;
+
+    /// How the colors are encoded, by default what the [pixel format](Colorimetry::for_format) tells.
+    fn colorimetry(&self) -> Colorimetry {
+        Colorimetry::for_format(&self.format())
+    }
+}
+
+pub type SharedFrame = Arc<dyn Frame>;
+pub type SenderSharedFrame = watch::Sender<Option<SharedFrame>>;
+pub type ReceiverSharedFrame = watch::Receiver<Option<SharedFrame>>;
+pub type FrameStream = WatchStream<Option<SharedFrame>>;
+
+impl std::fmt::Debug for dyn Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Frame")
+            .field("format", &self.format())
+            .field("timestamp", &self.timestamp())
+            .finish()
+    }
+}
+
+/// A frame which owns its pixel data, for sources which render or copy their frames.
+pub struct OwnedFrame {
+    format: SampleFormat,
+    data: Vec<u8>,
+    timestamp: Duration,
+}
+
+impl OwnedFrame {
+    pub fn new(format: SampleFormat, data: Vec<u8>, timestamp: Duration) -> Self {
+        Self {
+            format,
+            data,
+            timestamp,
+        }
+    }
+}
+
+impl Frame for OwnedFrame {
+    fn format(&self) -> SampleFormat {
+        self.format.clone()
+    }
+
+    fn pixels(&self) -> Pixels<'_> {
+        Pixels::packed(&self.data, &self.format)
+    }
+
+    fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+}
+
+/// Holds references to the pixel data of a frame, plane by plane in memory order,
+/// like Y and interleaved UV for NV12 or Y, U and V for I420.
+/// Backends which need to lock their buffers while the data is accessed unlock them upon drop.
+pub struct Pixels<'a> {
+    pub planes: Planes<'a>,
+    unlock: Option<Box<dyn FnOnce() + 'a>>,
+}
+
+impl<'a> Pixels<'a> {
+    pub fn new(planes: Planes<'a>) -> Self {
+        Self {
+            planes,
+            unlock: None,
+        }
+    }
+
+    pub fn with_unlock(planes: Planes<'a>, unlock: impl FnOnce() + 'a) -> Self {
+        Self {
+            planes,
+            unlock: Some(Box::new(unlock)),
+        }
+    }
+
+    /// Splits data without any row padding into the planes of the format.
+    /// Data of unknown formats or of the wrong size stays one plane of a single row.
+    pub fn packed(data: &'a [u8], format: &SampleFormat) -> Self {
+        if format.packed_size() != Some(data.len()) {
+            return Self::new(Planes::one(Plane::new(data, data.len(), data.len(), 1, 1)));
+        }
+        Self::split(data, format, |_, row_bytes| row_bytes)
+    }
+
+    /// Splits data with the planes one after the other and every row of the first plane `stride` bytes long.
+    /// The other planes get the stride scaled by their bytes per pixel and horizontal subsampling,
+    /// like the single plane formats of V4L2.
+    /// Data of unknown formats or too short for the strides stays one plane of a single row.
+    ///
+    /// <https://www.kernel.org/doc/html/latest/userspace-api/media/v4l/pixfmt-v4l2.html>
+    pub fn contiguous(data: &'a [u8], format: &SampleFormat, stride: usize) -> Self {
+        let Some(first) = format.plane_layout().map(|layout| layout[0]) else {
+            return Self::new(Planes::one(Plane::new(data, data.len(), data.len(), 1, 1)));
+        };
+        Self::split(data, format, |plane, row_bytes| {
+            let horizontal = plane.subsampling.0;
+            let scaled = stride * plane.bytes_per_pixel / first.bytes_per_pixel;
+            ((scaled + horizontal - 1) / horizontal).max(row_bytes)
+        })
+    }
+
+    /// Splits data into the planes of the format with the stride for the layout and row size of every plane.
+    fn split(
+        data: &'a [u8],
+        format: &SampleFormat,
+        stride: impl Fn(&PlaneLayout, usize) -> usize,
+    ) -> Self {
+        let (width, height) = (format.width as usize, format.height as usize);
+        let layout = format.plane_layout().unwrap_or_default();
+        let spans = layout.iter().map(|layout| {
+            let (width, height) = layout.size(width, height);
+            let stride = stride(layout, width * layout.bytes_per_pixel);
+            (layout, stride, width, height)
+        });
+        let size: usize = spans
+            .clone()
+            .map(|(_, stride, _, height)| stride * height)
+            .sum();
+        if layout.is_empty() || size > data.len() {
+            return Self::new(Planes::one(Plane::new(data, data.len(), data.len(), 1, 1)));
+        }
+
+        let mut rest = data;
+        let planes = spans
+            .map(|(layout, stride, width, height)| {
+                let (data, next) = rest.split_at(stride * height);
+                rest = next;
+                Plane::new(data, stride, width, height, layout.bytes_per_pixel)
+            })
+            .collect();
+        Self::new(planes)
+    }
+
+    /// Copies the planes one after the other without row padding, like the [packed](Pixels::packed) layout.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut data = vec![];
+        for plane in self.planes.iter() {
+            data.extend_from_slice(&plane.packed());
+        }
+        data
+    }
+}
+
+impl Drop for Pixels<'_> {
+    fn drop(&mut self) {
+        if let Some(unlock) = self.unlock.take() {
+            unlock();
+        }
+    }
+}
+
+/// Up to [MAX_PLANES] planes of [pixels](Pixels) in place, so getting the pixels of a frame allocates nothing.
+/// Dereferences to the slice of the planes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Planes<'a> {
+    planes: [Plane<'a>; MAX_PLANES],
+    count: usize,
+}
+
+impl<'a> Planes<'a> {
+    pub fn one(plane: Plane<'a>) -> Self {
+        let mut planes = Self::default();
+        planes.push(plane);
+        planes
+    }
+
+    /// Panics with more than [MAX_PLANES] planes.
+    pub fn push(&mut self, plane: Plane<'a>) {
+        assert!(self.count < MAX_PLANES, "more than {MAX_PLANES} planes");
+        self.planes[self.count] = plane;
+        self.count += 1;
+    }
+}
+
+impl<'a> Deref for Planes<'a> {
+    type Target = [Plane<'a>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.planes[..self.count]
+    }
+}
+
+impl DerefMut for Planes<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.planes[..self.count]
+    }
+}
+
+/// Panics with more than [MAX_PLANES] planes.
+impl<'a> FromIterator<Plane<'a>> for Planes<'a> {
+    fn from_iter<T: IntoIterator<Item = Plane<'a>>>(iter: T) -> Self {
+        let mut planes = Self::default();
+        for plane in iter {
+            planes.push(plane);
+        }
+        planes
+    }
+}
+
+/// One plane of [pixels](Pixels). Rows start every `stride` bytes and may be padded beyond their
+/// `width * bytes_per_pixel` bytes of pixel data, for alignment or because the device needs it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Plane<'a> {
+    /// From the first byte of the first row to at least the last byte of pixel data of the last row.
+    pub data: &'a [u8],
+    pub stride: usize,
+    /// Samples per row, like half the frame width for the interleaved UV plane of NV12.
+    pub width: usize,
+    pub height: usize,
+    /// Bytes of a sample, like 2 for the interleaved UV plane of NV12 or 4 for BGRA.
+    pub bytes_per_pixel: usize,
+}
+
+impl<'a> Plane<'a> {
+    pub fn new(
+        data: &'a [u8],
+        stride: usize,
+        width: usize,
+        height: usize,
+        bytes_per_pixel: usize,
+    ) -> Self {
+        debug_assert!(height == 0 || data.len() >= stride * (height - 1) + width * bytes_per_pixel);
+        Self {
+            data,
+            stride,
+            width,
+            height,
+            bytes_per_pixel,
+        }
+    }
+
+    /// The pixel data of a row without padding.
+    pub fn row(&self, y: usize) -> &'a [u8] {
+        &self.data[y * self.stride..][..self.width * self.bytes_per_pixel]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        (0..self.height).map(|y| self.row(y))
+    }
+
+    /// The pixel data without row padding, only copied when there is padding.
+    pub fn packed(&self) -> Cow<'a, [u8]> {
+        let row_bytes = self.width * self.bytes_per_pixel;
+        if self.stride == row_bytes {
+            Cow::Borrowed(&self.data[..row_bytes * self.height])
+        } else {
+            Cow::Owned(self.rows().flatten().copied().collect())
+        }
+    }
+}
+
+/// The size and pixel format of a [frame](Frame).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleFormat {
+    pub width: i32,
+    pub height: i32,
+    pub pixel_format: String,
+}
+
+impl std::fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{}x{} {}",
+            self.width, self.height, self.pixel_format
+        ))
+    }
+}
+
+impl SampleFormat {
+    /// The planes of the pixel format in memory order, `None` for unknown and compressed formats.
+    pub fn plane_layout(&self) -> Option<&'static [PlaneLayout]> {
+        const fn plane(bytes_per_pixel: usize, subsampling: (usize, usize)) -> PlaneLayout {
+            PlaneLayout {
+                bytes_per_pixel,
+                subsampling,
+            }
+        }
+        const NV12: &[PlaneLayout] = &[plane(1, (1, 1)), plane(2, (2, 2))];
+        const I420: &[PlaneLayout] = &[plane(1, (1, 1)), plane(1, (2, 2)), plane(1, (2, 2))];
+        // packed 4:2:2 formats hold two pixels in four bytes
+        const YUYV: &[PlaneLayout] = &[plane(2, (1, 1))];
+        const RGBA: &[PlaneLayout] = &[plane(4, (1, 1))];
+
+        match self.pixel_format.as_str() {
+            "420v" | "420f" | "NV12" => Some(NV12),
+            "y420" | "f420" | "I420" | "YU12" | "YV12" => Some(I420),
+            "2vuy" | "yuvs" | "YUYV" | "UYVY" => Some(YUYV),
+            "BGRA" | "RGBA" | "ARGB" => Some(RGBA),
+            _ => None,
+        }
+    }
+
+    /// The size of a frame without any row padding.
+    pub fn packed_size(&self) -> Option<usize> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let size = self
+            .plane_layout()?
+            .iter()
+            .map(|plane| {
+                let (width, height) = plane.size(width, height);
+                width * height * plane.bytes_per_pixel
+            })
+            .sum();
+        Some(size)
+    }
+
+    /// Whether there are the planes of the format, each big enough.
+    pub fn matches_planes(&self, planes: &[Plane]) -> bool {
+        let Some(layout) = self.plane_layout() else {
+            return false;
+        };
+        let (width, height) = (self.width as usize, self.height as usize);
+        planes.len() == layout.len()
+            && planes.iter().zip(layout).all(|(plane, layout)| {
+                let (layout_width, layout_height) = layout.size(width, height);
+                plane.width >= layout_width
+                    && plane.height >= layout_height
+                    && plane.bytes_per_pixel == layout.bytes_per_pixel
+            })
+    }
+}
+
+/// How a plane of a pixel format relates to the frame size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaneLayout {
+    pub bytes_per_pixel: usize,
+    /// Horizontal and vertical, like `(2, 2)` for the chroma planes of 4:2:0 formats.
+    pub subsampling: (usize, usize),
+}
+
+impl PlaneLayout {
+    /// Width and height of the plane, rounded up for odd frame sizes.
+    pub fn size(&self, width: usize, height: usize) -> (usize, usize) {
+        let (horizontal, vertical) = self.subsampling;
+        (
+            (width + horizontal - 1) / horizontal,
+            (height + vertical - 1) / vertical,
+        )
+    }
+}
@@ -0,0 +1,132 @@
+use super::SampleFormat;
+
+/// How the YCbCr values of a frame relate to RGB and to light, so consumers decode the colors the source meant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colorimetry {
+    pub matrix: Matrix,
+    pub range: Range,
+    pub primaries: Primaries,
+    pub transfer: Transfer,
+}
+
+/// The coefficients to get luma and the color differences from RGB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matrix {
+    /// ITU-R BT.601, standard definition and most webcams.
+    Bt601,
+    /// ITU-R BT.709, high definition.
+    Bt709,
+}
+
+/// Which code values black, white and the color differences use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Range {
+    /// Also called limited or TV range, Y [16, 235] and UV [16, 240].
+    Video,
+    /// Also called PC range, Y and UV [0, 255].
+    Full,
+}
+
+/// Which red, green, blue and white the RGB values mean.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primaries {
+    /// SMPTE 170M, the BT.601 primaries of 525 lines and most webcams.
+    Bt601,
+    /// ITU-R BT.709, the same as sRGB.
+    Bt709,
+}
+
+/// How the RGB values relate to linear light.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    /// ITU-R BT.709, the same as BT.601 and SMPTE 170M.
+    Bt709,
+    /// IEC 61966-2-1 of screens and JPEG.
+    Srgb,
+}
+
+impl Default for Colorimetry {
+    fn default() -> Self {
+        Self::BT601
+    }
+}
+
+impl Colorimetry {
+    /// Standard definition and most webcams, in video range.
+    pub const BT601: Self = Self {
+        matrix: Matrix::Bt601,
+        range: Range::Video,
+        primaries: Primaries::Bt601,
+        transfer: Transfer::Bt709,
+    };
+
+    /// High definition, in video range.
+    pub const BT709: Self = Self {
+        matrix: Matrix::Bt709,
+        range: Range::Video,
+        primaries: Primaries::Bt709,
+        transfer: Transfer::Bt709,
+    };
+
+    /// What the FOURCC tells about the colors. Only 420f and f420 name their range, full range,
+    /// everything else is assumed to be BT.601 video range.
+    pub fn for_format(format: &SampleFormat) -> Self {
+        let range = match format.pixel_format.as_str() {
+            "420f" | "f420" => Range::Full,
+            _ => Range::Video,
+        };
+        Self {
+            range,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the same YUV values mean the same RGB values in both, which only matrix and range decide.
+    pub fn same_encoding(&self, other: &Self) -> bool {
+        self.matrix == other.matrix && self.range == other.range
+    }
+
+    /// Rows for red, green and blue weighing Y, U, V and one, for YUV and RGB values from 0 to 1,
+    /// like 8 bit values divided by 255.
+    pub fn yuv_to_rgb(&self) -> [[f32; 4]; 3] {
+        let (kr, kb) = self.matrix.weights();
+        let kg = 1.0 - kr - kb;
+        let (luma_offset, luma_scale, chroma_scale) = self.range.scales();
+        let luma = 1.0 / luma_scale;
+        let red_v = 2.0 * (1.0 - kr) / chroma_scale;
+        let green_u = 2.0 * (1.0 - kb) * kb / kg / chroma_scale;
+        let green_v = 2.0 * (1.0 - kr) * kr / kg / chroma_scale;
+        let blue_u = 2.0 * (1.0 - kb) / chroma_scale;
+        let (black, no_color) = (luma_offset as f32 / 255.0, 128.0 / 255.0);
+        [
+            [luma, 0.0, red_v, -luma * black - red_v * no_color],
+            [
+                luma,
+                -green_u,
+                -green_v,
+                -luma * black + (green_u + green_v) * no_color,
+            ],
+            [luma, blue_u, 0.0, -luma * black - blue_u * no_color],
+        ]
+    }
+}
+
+impl Matrix {
+    /// The luma weights of red and blue, green is the rest.
+    pub fn weights(self) -> (f32, f32) {
+        match self {
+            Matrix::Bt601 => (0.299, 0.114),
+            Matrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+impl Range {
+    /// Luma offset, luma scale and chroma scale relative to full range.
+    pub fn scales(self) -> (i16, f32, f32) {
+        match self {
+            Range::Video => (16, 219.0 / 255.0, 224.0 / 255.0),
+            Range::Full => (0, 1.0, 1.0),
+        }
+    }
+}
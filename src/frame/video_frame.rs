@@ -0,0 +1,306 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+use super::{Colorimetry, Frame, Pixels, Plane, SampleFormat, MAX_PLANES};
+
+/// A frame which owns its pixel data in a [pooled buffer](BufferPool), plane after plane without row padding.
+///
+/// Other than frames holding on to a device buffer, like a camera sample buffer, it never keeps the capture
+/// waiting for consumers which are running behind. The buffer goes back to the pool when the frame is dropped,
+/// so in the steady state creating frames allocates nothing.
+pub struct VideoFrame {
+    format: SampleFormat,
+    colorimetry: Colorimetry,
+    timestamp: Duration,
+    buffer: PooledBuffer,
+    spans: [PlaneSpan; MAX_PLANES],
+    plane_count: usize,
+}
+
+/// Where a plane is in the buffer of a [video frame](VideoFrame).
+#[derive(Debug, Clone, Copy, Default)]
+struct PlaneSpan {
+    offset: usize,
+    stride: usize,
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+}
+
+impl VideoFrame {
+    /// A frame with a buffer of the pool and undefined pixels, `None` for formats without a known
+    /// [plane layout](SampleFormat::plane_layout).
+    pub fn new(pool: &BufferPool, format: SampleFormat, timestamp: Duration) -> Option<Self> {
+        let layout = format.plane_layout()?;
+        let (width, height) = (format.width as usize, format.height as usize);
+
+        let mut spans = [PlaneSpan::default(); MAX_PLANES];
+        let mut offset = 0;
+        for (span, plane) in spans.iter_mut().zip(layout) {
+            let (width, height) = plane.size(width, height);
+            let stride = width * plane.bytes_per_pixel;
+            *span = PlaneSpan {
+                offset,
+                stride,
+                width,
+                height,
+                bytes_per_pixel: plane.bytes_per_pixel,
+            };
+            offset += stride * height;
+        }
+
+        Some(Self {
+            colorimetry: Colorimetry::for_format(&format),
+            format,
+            timestamp,
+            buffer: pool.get(offset),
+            spans,
+            plane_count: layout.len(),
+        })
+    }
+
+    /// Copies the pixels and the colorimetry of any frame, row by row to leave out the padding.
+    /// `None` for unknown formats or when the planes don't match the format.
+    pub fn copy_from(pool: &BufferPool, frame: &dyn Frame) -> Option<Self> {
+        let mut copy =
+            Self::copy_from_pixels(pool, frame.format(), &frame.pixels(), frame.timestamp())?;
+        copy.colorimetry = frame.colorimetry();
+        Some(copy)
+    }
+
+    /// Copies pixels of the format, like a device buffer which is about to be reused.
+    pub fn copy_from_pixels(
+        pool: &BufferPool,
+        format: SampleFormat,
+        pixels: &Pixels,
+        timestamp: Duration,
+    ) -> Option<Self> {
+        let mut frame = Self::new(pool, format, timestamp)?;
+        if pixels.planes.len() != frame.plane_count {
+            return None;
+        }
+        for (source, mut target) in pixels.planes.iter().zip(frame.planes_mut()) {
+            if source.width < target.width
+                || source.height < target.height
+                || source.bytes_per_pixel != target.bytes_per_pixel
+            {
+                return None;
+            }
+            for y in 0..target.height {
+                let row = target.row_mut(y);
+                row.copy_from_slice(&source.row(y)[..row.len()]);
+            }
+        }
+        Some(frame)
+    }
+
+    pub fn format(&self) -> &SampleFormat {
+        &self.format
+    }
+
+    /// Overrides the colorimetry guessed from the pixel format, for sources knowing better.
+    pub fn set_colorimetry(&mut self, colorimetry: Colorimetry) {
+        self.colorimetry = colorimetry;
+    }
+
+    pub fn plane_count(&self) -> usize {
+        self.plane_count
+    }
+
+    /// A plane in memory order.
+    pub fn plane(&self, index: usize) -> Plane<'_> {
+        let span = self.spans[..self.plane_count][index];
+        Plane::new(
+            &self.buffer[span.offset..][..span.stride * span.height],
+            span.stride,
+            span.width,
+            span.height,
+            span.bytes_per_pixel,
+        )
+    }
+
+    /// Every plane in memory order, to write to several planes at once.
+    pub fn planes_mut(&mut self) -> impl Iterator<Item = PlaneMut<'_>> {
+        let mut rest: &mut [u8] = &mut self.buffer;
+        self.spans[..self.plane_count].iter().map(move |span| {
+            let (data, next) = std::mem::take(&mut rest).split_at_mut(span.stride * span.height);
+            rest = next;
+            PlaneMut {
+                data,
+                stride: span.stride,
+                width: span.width,
+                height: span.height,
+                bytes_per_pixel: span.bytes_per_pixel,
+            }
+        })
+    }
+
+    /// The whole buffer, plane after plane without row padding like [Pixels::packed].
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Frame for VideoFrame {
+    fn format(&self) -> SampleFormat {
+        self.format.clone()
+    }
+
+    fn pixels(&self) -> Pixels<'_> {
+        let planes = (0..self.plane_count).map(|index| self.plane(index));
+        Pixels::new(planes.collect())
+    }
+
+    fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    fn colorimetry(&self) -> Colorimetry {
+        self.colorimetry
+    }
+}
+
+/// A writable plane of a [video frame](VideoFrame), see [Plane].
+#[derive(Debug)]
+pub struct PlaneMut<'a> {
+    pub data: &'a mut [u8],
+    pub stride: usize,
+    pub width: usize,
+    pub height: usize,
+    pub bytes_per_pixel: usize,
+}
+
+impl<'a> PlaneMut<'a> {
+    /// The pixel data of a row without padding.
+    pub fn row_mut(&mut self, y: usize) -> &mut [u8] {
+        let start = y * self.stride;
+        &mut self.data[start..start + self.width * self.bytes_per_pixel]
+    }
+}
+
+/// Recycles frame buffers, so producing a frame doesn't allocate as long as enough frames are dropped again.
+/// Clones share the buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    shared: Arc<PoolShared>,
+}
+
+struct PoolShared {
+    idle: Mutex<Vec<Vec<u8>>>,
+    max_idle: usize,
+    allocations: AtomicUsize,
+}
+
+impl Default for BufferPool {
+    /// Enough for a frame in the channel, one each in the encoder and the GUI and one being produced.
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl BufferPool {
+    /// Keeps at most `max_idle` returned buffers, the others are freed.
+    pub fn new(max_idle: usize) -> Self {
+        Self {
+            shared: Arc::new(PoolShared {
+                idle: Mutex::new(vec![]),
+                max_idle,
+                allocations: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// A buffer of `size` bytes with undefined content. Reuses an idle buffer which is big enough.
+    pub fn get(&self, size: usize) -> PooledBuffer {
+        let reused = {
+            let mut idle = self.shared.idle.lock().unwrap();
+            idle.iter()
+                .position(|buffer| buffer.capacity() >= size)
+                .map(|index| idle.swap_remove(index))
+        };
+        let data = match reused {
+            Some(mut data) => {
+                data.resize(size, 0);
+                data
+            }
+            None => {
+                self.shared.allocations.fetch_add(1, Ordering::Relaxed);
+                vec![0; size]
+            }
+        };
+        PooledBuffer {
+            data,
+            pool: Arc::downgrade(&self.shared),
+        }
+    }
+
+    /// How many buffers the pool allocated so far, it stops growing in the steady state.
+    pub fn allocations(&self) -> usize {
+        self.shared.allocations.load(Ordering::Relaxed)
+    }
+}
+
+/// A buffer of a [pool](BufferPool), which returns to it upon drop. Outliving the pool, it is just freed.
+pub struct PooledBuffer {
+    data: Vec<u8>,
+    pool: Weak<PoolShared>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let Some(pool) = self.pool.upgrade() else {
+            return;
+        };
+        let mut idle = pool.idle.lock().unwrap();
+        if idle.len() < pool.max_idle {
+            idle.push(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_stops_allocating() {
+        let pool = BufferPool::new(4);
+        let format = SampleFormat {
+            width: 32,
+            height: 16,
+            pixel_format: "I420".to_string(),
+        };
+        let source = VideoFrame::new(&pool, format.clone(), Duration::ZERO).unwrap();
+        assert_eq!(3, source.pixels().planes.len());
+
+        // two frames in flight, like one in the channel and one being produced
+        let mut previous = VideoFrame::copy_from(&pool, &source).unwrap();
+        let allocations = pool.allocations();
+        for _ in 0..10 {
+            let frame = VideoFrame::copy_from(&pool, &source).unwrap();
+            assert_eq!(previous.pixels().to_vec(), frame.pixels().to_vec());
+            previous = frame;
+        }
+        assert_eq!(allocations + 1, pool.allocations());
+    }
+}
@@ -1,10 +1,28 @@
-use crate::camera::ReceiverSharedFrame;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use crate::{frame::ReceiverSharedFrame, rate::Counts, transform::Transform};
 
 mod stage;
 mod video_view;
 
-pub fn run_gui(camera_frame: ReceiverSharedFrame) {
+/// Shows the frames and lets the transform of them and the frame rate and bitrate of the encoder change.
+pub fn run_gui(
+    camera_frame: ReceiverSharedFrame,
+    transform: watch::Sender<Transform>,
+    frame_rate: watch::Sender<Option<f64>>,
+    bitrate: watch::Sender<u32>,
+    rate_counts: Arc<Counts>,
+) {
     miniquad::start(miniquad::conf::Conf::default(), move |ctx| {
-        Box::new(stage::Stage::new(ctx, camera_frame))
+        Box::new(stage::Stage::new(
+            ctx,
+            camera_frame,
+            transform,
+            frame_rate,
+            bitrate,
+            rate_counts,
+        ))
     });
 }
@@ -1,34 +1,189 @@
+use std::sync::Arc;
+
 use egui_miniquad::EguiMq;
 use miniquad::*;
+use tokio::sync::watch;
 
 use super::video_view::VideoView;
-use crate::camera::ReceiverSharedFrame;
+use crate::{
+    convert::{Converter, PixelFormat},
+    frame::{Colorimetry, Frame, ReceiverSharedFrame},
+    rate::Counts,
+    transform::{Crop, Rotation, Transform},
+};
 
 pub(crate) struct Stage {
     egui_mq: EguiMq,
     video_view: VideoView,
     camera_frame: ReceiverSharedFrame,
+    /// The shader decodes NV12 of any colorimetry, every other format gets converted.
+    converter: Converter,
+    /// The frames arrive transformed with it.
+    transform: watch::Sender<Transform>,
+    /// The frame rate the encoder gets, or every frame.
+    frame_rate: watch::Sender<Option<f64>>,
+    /// The target of the encoder in kbit/s.
+    bitrate: watch::Sender<u32>,
+    rate_counts: Arc<Counts>,
 }
 
 impl Stage {
-    pub(crate) fn new(ctx: &mut Context, camera_frame: ReceiverSharedFrame) -> Self {
+    pub(crate) fn new(
+        ctx: &mut Context,
+        camera_frame: ReceiverSharedFrame,
+        transform: watch::Sender<Transform>,
+        frame_rate: watch::Sender<Option<f64>>,
+        bitrate: watch::Sender<u32>,
+        rate_counts: Arc<Counts>,
+    ) -> Self {
         Self {
             egui_mq: EguiMq::new(ctx),
             video_view: VideoView::new(ctx),
             camera_frame,
+            converter: Converter::new(PixelFormat::NV12, Colorimetry::default()),
+            transform,
+            frame_rate,
+            bitrate,
+            rate_counts,
+        }
+    }
+}
+
+/// Changes the transform of the frames, whose size is shown uncropped.
+fn transform_ui(ui: &mut egui::Ui, transform_tx: &watch::Sender<Transform>, shown: (u32, u32)) {
+    let mut transform = *transform_tx.borrow();
+
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut transform.flip_horizontal, "Mirror");
+        ui.checkbox(&mut transform.flip_vertical, "Flip");
+        ui.label("Rotate");
+        let rotations = [
+            (Rotation::None, "0°"),
+            (Rotation::Degrees90, "90°"),
+            (Rotation::Degrees180, "180°"),
+            (Rotation::Degrees270, "270°"),
+        ];
+        for (rotation, label) in rotations {
+            ui.selectable_value(&mut transform.rotation, rotation, label);
         }
+    });
+
+    ui.horizontal(|ui| {
+        let mut cropping = transform.crop.is_some();
+        ui.checkbox(&mut cropping, "Crop");
+        transform.crop = match (cropping, transform.crop) {
+            (false, _) => None,
+            (true, Some(crop)) => Some(crop),
+            (true, None) => {
+                // uncropped the shown frame is the whole source frame, only rotated
+                let (width, height) = match transform.rotation {
+                    Rotation::None | Rotation::Degrees180 => shown,
+                    Rotation::Degrees90 | Rotation::Degrees270 => (shown.1, shown.0),
+                };
+                Some(Crop {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                })
+            }
+        };
+        if let Some(crop) = &mut transform.crop {
+            // even steps, the transform rounds to even anyway
+            for (value, prefix) in [
+                (&mut crop.x, "x "),
+                (&mut crop.y, "y "),
+                (&mut crop.width, "width "),
+                (&mut crop.height, "height "),
+            ] {
+                ui.add(egui::DragValue::new(value).speed(2.0).prefix(prefix));
+            }
+        }
+    });
+
+    if transform != *transform_tx.borrow() {
+        transform_tx.send_replace(transform);
+    }
+}
+
+/// Changes the frame rate of the encoder and shows how many frames got dropped and duplicated for it.
+fn frame_rate_ui(ui: &mut egui::Ui, frame_rate_tx: &watch::Sender<Option<f64>>, counts: &Counts) {
+    let mut frame_rate = *frame_rate_tx.borrow();
+
+    ui.horizontal(|ui| {
+        let mut limiting = frame_rate.is_some();
+        ui.checkbox(&mut limiting, "Encoder fps");
+        frame_rate = match (limiting, frame_rate) {
+            (false, _) => None,
+            (true, Some(fps)) => Some(fps),
+            (true, None) => Some(15.0),
+        };
+        if let Some(fps) = &mut frame_rate {
+            ui.add(
+                egui::DragValue::new(fps)
+                    .speed(0.5)
+                    .clamp_range(1.0..=120.0),
+            );
+        }
+        ui.label(format!(
+            "dropped {}, duplicated {}",
+            counts.dropped(),
+            counts.duplicated()
+        ));
+    });
+
+    if frame_rate != *frame_rate_tx.borrow() {
+        frame_rate_tx.send_replace(frame_rate);
+    }
+}
+
+/// Changes the bitrate of the encoder, which takes it from the next frame on.
+fn bitrate_ui(ui: &mut egui::Ui, bitrate_tx: &watch::Sender<u32>) {
+    let mut bitrate = *bitrate_tx.borrow();
+
+    ui.horizontal(|ui| {
+        ui.label("Encoder kbit/s");
+        ui.add(
+            egui::DragValue::new(&mut bitrate)
+                .speed(50)
+                .clamp_range(100..=50000),
+        );
+    });
+
+    if bitrate != *bitrate_tx.borrow() {
+        bitrate_tx.send_replace(bitrate);
     }
 }
 
 impl EventHandler for Stage {
     fn update(&mut self, ctx: &mut Context) {
         if let Ok(true) = self.camera_frame.has_changed() {
-            if let Some(frame) = &*self.camera_frame.borrow() {
-                let width = frame.format().width;
-                let height = frame.format().height;
-                let yuv = frame.pixels().data;
-                self.video_view.update(ctx, yuv, width as _, height as _);
-            }
+            // converting takes a while, so the channel is not borrowed meanwhile
+            let Some(frame) = self.camera_frame.borrow_and_update().clone() else {
+                return;
+            };
+            let is_nv12 =
+                PixelFormat::from_fourcc(&frame.format().pixel_format) == Some(PixelFormat::NV12);
+            let converted;
+            let frame: &dyn Frame = if !is_nv12 {
+                match self.converter.convert(&*frame) {
+                    Ok(frame) => {
+                        converted = frame;
+                        &converted
+                    }
+                    Err(err) => {
+                        log::trace!("Stage: Can not show frame. ({})", err);
+                        return;
+                    }
+                }
+            } else {
+                &*frame
+            };
+            let width = frame.format().width;
+            let height = frame.format().height;
+            let pixels = frame.pixels();
+            self.video_view
+                .update(ctx, &pixels, width as _, height as _, frame.colorimetry());
         }
     }
 
@@ -45,6 +200,11 @@ impl EventHandler for Stage {
                 let height = self.video_view.height() / 2;
                 ui.image(video_texture_id, egui::Vec2::new(width as _, height as _));
 
+                let shown = (self.video_view.width(), self.video_view.height());
+                transform_ui(ui, &self.transform, shown);
+                frame_rate_ui(ui, &self.frame_rate, &self.rate_counts);
+                bitrate_ui(ui, &self.bitrate);
+
                 #[cfg(not(target_arch = "wasm32"))]
                 if ui.button("Quit").clicked() {
                     // TODO tell the other that we are exitting
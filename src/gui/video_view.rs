@@ -1,6 +1,8 @@
 use miniquad::*;
 
-/// Takes NV21 video range frames and draws them into a RGBA texture.
+use crate::frame::{Colorimetry, Pixels};
+
+/// Takes NV12 frames and draws them into a RGBA texture, decoding the colors with the colorimetry of the frame.
 pub struct VideoView {
     pipeline: Pipeline,
     bindings: Bindings,
@@ -8,6 +10,7 @@ pub struct VideoView {
     render_texture: Texture,
     texture_y: Texture,
     texture_uv: Texture,
+    uniforms: offscreen_shader::Uniforms,
 }
 
 impl VideoView {
@@ -63,6 +66,7 @@ impl VideoView {
                 index_buffer,
                 images: vec![texture_y, texture_uv],
             },
+            uniforms: offscreen_shader::Uniforms::new(Colorimetry::default()),
         }
     }
 
@@ -74,12 +78,27 @@ impl VideoView {
         self.render_texture.height
     }
 
-    pub fn update(&mut self, ctx: &mut Context, yuv: &[u8], width: u32, height: u32) {
-        let (y, uv) = yuv.split_at((width * height) as _);
+    /// Takes the Y and the interleaved UV plane of the pixels. Padded rows get packed for the textures.
+    pub fn update(
+        &mut self,
+        ctx: &mut Context,
+        pixels: &Pixels,
+        width: u32,
+        height: u32,
+        colorimetry: Colorimetry,
+    ) {
+        let [y_plane, uv_plane] = &pixels.planes[..] else {
+            log::trace!("VideoView: Only a Y and an UV plane can be shown. Skip frame.");
+            return;
+        };
+        let (y, uv) = (y_plane.packed(), uv_plane.packed());
+        let (y, uv) = (y.as_ref(), uv.as_ref());
+        self.uniforms = offscreen_shader::Uniforms::new(colorimetry);
 
         if self.width() != width || self.height() != height {
             self.texture_y.resize(ctx, width, height, Some(y));
-            self.texture_uv.resize(ctx, width / 2, height / 2, Some(uv));
+            let (uv_width, uv_height) = (uv_plane.width as u32, uv_plane.height as u32);
+            self.texture_uv.resize(ctx, uv_width, uv_height, Some(uv));
             self.render_texture.resize(ctx, width, height, None);
             self.pass = RenderPass::new(ctx, self.render_texture, None);
         } else {
@@ -92,6 +111,7 @@ impl VideoView {
         ctx.begin_pass(self.pass, PassAction::clear_color(0.0, 1.0, 1.0, 1.));
         ctx.apply_pipeline(&self.pipeline);
         ctx.apply_bindings(&self.bindings);
+        ctx.apply_uniforms(&self.uniforms);
         ctx.draw(0, 6, 1);
         ctx.end_render_pass();
         self.pass.texture(ctx)
@@ -110,6 +130,8 @@ fn texture_params(format: TextureFormat) -> TextureParams {
 mod offscreen_shader {
     use miniquad::*;
 
+    use crate::frame::Colorimetry;
+
     pub const VERTEX: &str = r#"#version 100
     attribute vec4 pos;
     attribute vec2 uv;
@@ -128,15 +150,19 @@ mod offscreen_shader {
     uniform sampler2D tex_y;
     uniform sampler2D tex_uv;
 
+    // the colorimetry of the frame, weighing Y, U, V and one
+    uniform mediump vec4 yuv2r;
+    uniform mediump vec4 yuv2g;
+    uniform mediump vec4 yuv2b;
+
     void main() {
-        lowp vec3 yuv, rgb;
-        lowp vec3 yuv2r = vec3(1.164, 0.0, 1.596);
-        lowp vec3 yuv2g = vec3(1.164, -0.391, -0.813);
-        lowp vec3 yuv2b = vec3(1.164, 2.018, 0.0);
+        mediump vec4 yuv;
+        lowp vec3 rgb;
 
-        yuv.x = texture2D(tex_y, frag_uv).a - 0.0625;
-        yuv.y = texture2D(tex_uv, frag_uv).r - 0.5;
-        yuv.z = texture2D(tex_uv, frag_uv).a - 0.5;
+        yuv.x = texture2D(tex_y, frag_uv).a;
+        yuv.y = texture2D(tex_uv, frag_uv).r;
+        yuv.z = texture2D(tex_uv, frag_uv).a;
+        yuv.w = 1.0;
 
         rgb.x = dot(yuv, yuv2r);
         rgb.y = dot(yuv, yuv2g);
@@ -149,7 +175,31 @@ mod offscreen_shader {
     pub fn meta() -> ShaderMeta {
         ShaderMeta {
             images: vec!["tex_y".to_string(), "tex_uv".to_string()],
-            uniforms: UniformBlockLayout { uniforms: vec![] },
+            uniforms: UniformBlockLayout {
+                uniforms: vec![
+                    UniformDesc::new("yuv2r", UniformType::Float4),
+                    UniformDesc::new("yuv2g", UniformType::Float4),
+                    UniformDesc::new("yuv2b", UniformType::Float4),
+                ],
+            },
+        }
+    }
+
+    #[repr(C)]
+    pub struct Uniforms {
+        pub yuv2r: [f32; 4],
+        pub yuv2g: [f32; 4],
+        pub yuv2b: [f32; 4],
+    }
+
+    impl Uniforms {
+        pub fn new(colorimetry: Colorimetry) -> Self {
+            let [yuv2r, yuv2g, yuv2b] = colorimetry.yuv_to_rgb();
+            Self {
+                yuv2r,
+                yuv2g,
+                yuv2b,
+            }
         }
     }
 
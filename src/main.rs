@@ -1,258 +1,345 @@
 use std::{
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::*,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
 };
+
+use clap::Parser;
 use tokio::sync::{broadcast, mpsc, watch};
-use tokio_stream::{wrappers::WatchStream, StreamExt};
+
+use source::VideoSource;
 
 mod camera;
 mod codec;
+mod convert;
+mod frame;
 mod gui;
+mod overlay;
+mod pipeline;
+mod rate;
+mod scale;
+mod source;
+mod text;
+mod transform;
 mod webrtc;
 
+#[derive(Parser, Debug)]
+#[command(about = "A playground for exploring media capture, processing and publishing")]
+struct Args {
+    /// Where frames come from: camera, testsrc, raw or y4m
+    #[arg(long, default_value = "camera")]
+    source: String,
+    /// Camera to capture from by index, unique ID or part of its name, otherwise the default camera
+    #[arg(long)]
+    camera: Option<String>,
+    /// Print the video capture devices and exit
+    #[arg(long)]
+    list_cameras: bool,
+    /// Picture of the testsrc source: bars, box or gradient
+    #[arg(long, default_value = "bars")]
+    pattern: source::Pattern,
+    /// Resolution like 1280x720 of synthetic sources and raw files with --raw-format, cameras get close to it
+    #[arg(long, default_value = "1280x720", value_parser = parse_size)]
+    size: (u32, u32),
+    /// Frame rate of synthetic and raw file sources [default: 30], the minimum of cameras, y4m files keep theirs
+    #[arg(long)]
+    fps: Option<f64>,
+    /// Files of file sources, raw files are replayed in the given order and in a loop, of y4m only the first
+    #[arg(long)]
+    input: Vec<PathBuf>,
+    /// FOURCC of raw files, otherwise it is taken from names like camera_frame.<fourcc>.<width>.<height>
+    #[arg(long)]
+    raw_format: Option<String>,
+    /// Read y4m files as fast as possible instead of in real time
+    #[arg(long)]
+    fast: bool,
+    /// Start y4m files over at their end instead of ending the frames
+    #[arg(long = "loop")]
+    looping: bool,
+    /// Scale frames to this size like 640x360 for encoding, the preview keeps the size of the source
+    #[arg(long, value_parser = parse_size)]
+    scale: Option<(u32, u32)>,
+    /// Keep the aspect ratio and fit into --scale instead of stretching to it, never scaling up
+    #[arg(long)]
+    scale_fit: bool,
+    /// Filter of --scale: nearest, bilinear or box
+    #[arg(long, default_value = "box")]
+    scale_filter: scale::Filter,
+    /// Crop frames to a rectangle like 640x480+320+240 before rotating and mirroring them
+    #[arg(long)]
+    crop: Option<transform::Crop>,
+    /// Rotate frames clockwise by 0, 90, 180 or 270 degrees, for cameras mounted rotated
+    #[arg(long, default_value = "0")]
+    rotate: transform::Rotation,
+    /// Swap left and right, for cameras with a mirrored picture
+    #[arg(long)]
+    mirror: bool,
+    /// Swap top and bottom
+    #[arg(long)]
+    flip: bool,
+    /// Codec to encode with and send via WebRTC: vp8 or vp9, VP9 gets better quality per bit
+    #[arg(long, default_value = "vp8")]
+    codec: codec::Codec,
+    /// Tune the VP8 encoder: low-latency for calls, screen for screen sharing or archive for recordings
+    #[arg(long)]
+    preset: Option<codec::Vp8Preset>,
+    /// Log the PSNR of every encoded frame at trace level and their average at the end at info level
+    #[arg(long)]
+    psnr: bool,
+    /// Target bitrate of the encoder in kbit/s, changeable in the preview
+    #[arg(long, default_value_t = pipeline::EncodeFrames::DEFAULT_BITRATE)]
+    bitrate: u32,
+    /// Send the encoder this frame rate, dropping and duplicating frames by their capture time
+    #[arg(long)]
+    encode_fps: Option<f64>,
+    /// Burn lines into the encoded frames, separated by commas: time, frame and format of the capture
+    #[arg(long, value_delimiter = ',')]
+    overlay: Vec<overlay::Field>,
+    /// Burn this text into the encoded frames, below the --overlay lines
+    #[arg(long)]
+    overlay_label: Option<String>,
+    /// Make the overlay black and white in the chroma planes too, otherwise only the luma changes
+    #[arg(long)]
+    overlay_chroma: bool,
+    /// Run a pipeline like `testsrc ! scale 640x360 ! vp8enc bitrate=1500 ! webrtc` instead of the one of the other arguments
+    #[arg(long, conflicts_with = "pipeline_file")]
+    pipeline: Option<pipeline::Description>,
+    /// Run the pipeline of a TOML file with an [[elements]] table per element
+    #[arg(long)]
+    pipeline_file: Option<PathBuf>,
+    /// Do not open the preview window, for headless machines
+    #[arg(long)]
+    no_gui: bool,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     init_logging();
+    let args = Args::parse();
+
+    if args.list_cameras {
+        for device in camera::devices() {
+            println!("{device}");
+        }
+        return Ok(());
+    }
 
     /*
 
-    Several tasks get spawned communicating with each other through channels.
-    * run_camera_task gets frames from the default camera with some 4:2:0 pixel format
-    * encode_frames_task throws frames into libvpx VP8 encoder and get `EncodedFrame`s out
+    A pipeline of elements gets started, every element a task communicating with the others through channels.
+    * Source gets frames from a video source, the default camera, with some 4:2:0 pixel format
+    * TransformFrames crops, rotates and mirrors them for the preview and the encoder, changeable in the preview
+    * LimitFrames drops and duplicates frames for the encoder to send at a frame rate, changeable in the preview
+    * ScaleFrames optionally scales the frames for the encoder
+    * BurnInFrames optionally burns text like the wall-clock time into the frames for the encoder
+    * EncodeFrames throws frames into libvpx VP8 or VP9 encoder and get `EncodedFrame`s out, at a bitrate changeable in the preview
+    * WebRtcTestapp is setting up a peer connection, an output track and takes additionally encoded frames and writes them on the output track
+    * WriteFrame writes a source frame into a file
+    Next to the pipeline
     * http_testapp_task is a HTTP server serving an index.html testapp on usuall http://localhost:8080
     * http_testapp_task also provides a SDP offer answer exchange endpoint, for a single exchange though
-    * the SDP offer exchange request goes into the webrtc_testapp_task which eventually produces an SDP answer as a response
-    * webrtc_testapp_task is setting up a peer connection, an output track and takes additionally encoded frames and writes them on the output track
+    * the SDP offer exchange request goes into WebRtcTestapp which eventually produces an SDP answer as a response
 
-    On pressing Ctrl-C the camera stops.
-    When the camera task ends, the corresponding channel gets closed to, which will close the encode frames task.
-    Because every channel closes when the task ends, this closing and ending eventually propagetes through all tasks.
-    Every task can so deal with closing and shutting down.
+    A --pipeline or --pipeline-file describes another pipeline instead, without preview.
+    Each line on stdin then sets the bitrate of its encoders in kbit/s.
+
+    On pressing Ctrl-C the source stops.
+    When the source ends, the corresponding channel gets closed to, which will close the elements after it.
+    Because every channel closes when the element ends, this closing and ending eventually propagetes through all elements.
+    When every source ended or an element failed, the pipeline exits, which ends the HTTP server too.
      */
 
     let (exit_tx, exit) = broadcast::channel(1);
+    let mut pipeline = pipeline::Pipeline::new(exit_tx.clone());
 
-    let (camera_frame_tx, camera_frame) = watch::channel(None);
-
-    let (encoded_frames_tx, encoded_frames) = mpsc::channel(3);
-    let picture_loss_indicator = Arc::new(AtomicBool::new(false));
     let (exchange_tx, exchange_rx) = mpsc::channel(1);
 
-    let _ = tokio::spawn(exit_on_ctrl_c(exit_tx.clone()));
-
-    let run_camera_task = tokio::spawn(run_camera(
-        exit_tx.clone(),
-        exit.resubscribe(),
-        camera_frame_tx,
-    ));
-
-    let encode_frames_task = tokio::spawn(encode_frames(
-        camera_frame.clone(),
-        encoded_frames_tx,
-        picture_loss_indicator.clone(),
-    ));
-
-    tokio::spawn(write_frame(camera_frame.clone()));
+    let _ = tokio::spawn(exit_on_ctrl_c(exit_tx));
 
-    let http_testapp_task =
-        tokio::spawn(webrtc::http_testapp(8080, exchange_tx, exit.resubscribe()));
+    let http_testapp_task = tokio::spawn(webrtc::http_testapp(8080, exchange_tx, exit));
 
-    let webrtc_testapp_task = tokio::spawn(webrtc::webrtc_testapp(
-        exchange_rx,
-        encoded_frames,
-        picture_loss_indicator.clone(),
-    ));
-
-    // must run on main thread unfortunately
-    gui::run_gui(camera_frame.clone());
+    let description = match &args.pipeline_file {
+        Some(path) => {
+            let toml = std::fs::read_to_string(path)?;
+            Some(pipeline::Description::from_toml(&toml)?)
+        }
+        None => args.pipeline.clone(),
+    };
+    match description {
+        Some(description) => control_bitrates(description.build(&mut pipeline, exchange_rx)?),
+        None => run_default_pipeline(args, &mut pipeline, exchange_rx)?,
+    }
 
-    let _ = tokio::join!(
-        run_camera_task,
-        encode_frames_task,
-        http_testapp_task,
-        webrtc_testapp_task
-    );
+    let (supervised, _) = tokio::join!(pipeline.supervise(), http_testapp_task);
+    supervised?;
 
     Ok(())
 }
 
-async fn write_frame(mut frame: camera::ReceiverSharedFrame) {
-    for _ in 0..10 {
-        let _ = frame.changed().await;
+/// Starts the pipeline the arguments describe and shows the preview, unless there is no GUI.
+fn run_default_pipeline(
+    args: Args,
+    pipeline: &mut pipeline::Pipeline,
+    exchange_rx: mpsc::Receiver<webrtc::OfferAnswerExchange>,
+) -> anyhow::Result<()> {
+    if args.codec == codec::Codec::Vp9 && args.preset.is_some() {
+        anyhow::bail!("--preset tunes VP8, not VP9");
     }
+    let picture_loss_indicator = Arc::new(AtomicBool::new(false));
 
-    let (path, data) = {
-        let frame_borrow = frame.borrow();
-        let Some(frame) = frame_borrow.as_ref() else { return };
-
-        let format = frame.format();
-        let path = format!(
-            "camera_frame.{}.{}.{}",
-            format.pixel_format.as_str(),
-            format.width,
-            format.height
-        );
-        let data = frame.pixels().data.to_vec();
-        (path, data)
+    let source = create_source(&args)?;
+    let (width, height) = args.size;
+    let preferences = source::Preferences {
+        resolution: Some((width as i32, height as i32)),
+        min_framerate: args.fps,
+        aspect_ratio: Some((width as i32, height as i32)),
+        ..source::Preferences::encoder()
     };
+    let source_frame = pipeline.source(pipeline::Source::new(source, preferences));
+
+    let (transform_tx, transform) = watch::channel(transform::Transform {
+        crop: args.crop,
+        flip_horizontal: args.mirror,
+        flip_vertical: args.flip,
+        rotation: args.rotate,
+    });
+    let transformed_frame = pipeline.add(
+        pipeline::TransformFrames::new(transform),
+        source_frame.clone(),
+    );
 
-    let _ = tokio::fs::write(path, &data).await;
-}
+    let limiter = rate::RateLimiter::new(args.encode_fps);
+    let rate_counts = limiter.counts();
+    let (frame_rate_tx, frame_rate) = watch::channel(args.encode_fps);
+    let mut encoder_frame = pipeline.add(
+        pipeline::LimitFrames::new(frame_rate, limiter),
+        transformed_frame.clone(),
+    );
 
-async fn exit_on_ctrl_c(exit_tx: broadcast::Sender<()>) {
-    if let Err(err) = tokio::signal::ctrl_c().await {
-        log::debug!("Ctrl-C signal handler broke. Exit. ({})", err);
+    if let Some((width, height)) = args.scale {
+        let size = if args.scale_fit {
+            scale::Size::Fit(width, height)
+        } else {
+            scale::Size::Fixed(width, height)
+        };
+        let scaler = scale::Scaler::new(args.scale_filter, size);
+        encoder_frame = pipeline.add(pipeline::ScaleFrames::new(scaler), encoder_frame);
     }
-    // ignore err, because this means everybody who can exit, exitted already.
-    let _ = exit_tx.send(());
-}
 
-async fn run_camera(
-    exit_tx: broadcast::Sender<()>,
-    exit: broadcast::Receiver<()>,
-    frames_tx: camera::SenderSharedFrame,
-) {
-    let mut cam = camera::Camera::default().unwrap();
-    // searching for the biggest compatible NV21 video range format, on Mac this is 420v
-    let format = cam
-        .formats()
-        .into_iter()
-        .filter(|f| &f.pixel_format == "420v")
-        .max_by_key(|f| f.height)
-        .expect("420v format");
-    cam.set_preferred_format(Some(format));
-
-    cam.start().unwrap();
-    let mut frames = cam.frames();
-    let mut first_frame = true;
-
-    loop {
-        if !exit.is_empty() {
-            break;
-        }
-
-        if let Some(frame) = frames.next().await {
-            let Some(frame) = frame else { continue };
-
-            if first_frame {
-                first_frame = false;
-                log::debug!(
-                    "run_camera: Started receiving camera frames. {:?}",
-                    frame.format()
-                );
-            }
+    let overlay = overlay::Overlay::new(args.overlay, args.overlay_label, args.overlay_chroma);
+    if !overlay.is_empty() {
+        encoder_frame = pipeline.add(
+            pipeline::BurnInFrames::new(overlay, source_frame.clone()),
+            encoder_frame,
+        );
+    }
 
-            match frames_tx.send(Some(frame)) {
-                Ok(_) => log::trace!("run_camera: send frame"),
-                Err(_) => {
-                    log::debug!("run_camera: No camera frame receiver. End.");
-                    break;
-                }
-            }
-        } else {
-            log::debug!("run_camera: Camera frames ended. End.");
-            break;
-        }
+    let (bitrate_tx, bitrate) = watch::channel(args.bitrate);
+    let mut encoder =
+        pipeline::EncodeFrames::new(picture_loss_indicator.clone(), bitrate).psnr(args.psnr);
+    if args.codec == codec::Codec::Vp9 {
+        encoder = encoder.vp9(codec::Vp9Controls::default());
+    } else if let Some(preset) = args.preset {
+        encoder = encoder.vp8(codec::Vp8Tuning::preset(preset));
     }
+    let codec = encoder.codec();
+    let encoded_frames = pipeline.add(encoder, encoder_frame);
+    pipeline.add(
+        pipeline::WebRtcTestapp::new(exchange_rx, picture_loss_indicator, codec),
+        encoded_frames,
+    );
 
-    cam.stop();
+    pipeline.add(pipeline::WriteFrame, source_frame);
 
-    exit_tx.send(()).expect("exit");
-}
+    if !args.no_gui {
+        // must run on main thread unfortunately
+        gui::run_gui(
+            transformed_frame,
+            transform_tx,
+            frame_rate_tx,
+            bitrate_tx,
+            rate_counts,
+        );
+    }
 
-pub struct EncodedFrame {
-    pub bytes: bytes::Bytes,
-    pub keyframe: bool,
+    Ok(())
 }
 
-impl std::fmt::Debug for EncodedFrame {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("EncodedFrame")
-            .field("bytes", &self.bytes.len())
-            .finish()
+/// Sets the bitrate of every encoder of a described pipeline to each line of stdin in kbit/s,
+/// as there is no preview to change it. When stdin ends the bitrates stay as they are.
+fn control_bitrates(bitrates: Vec<watch::Sender<u32>>) {
+    if bitrates.is_empty() {
+        return;
     }
-}
-
-async fn encode_frames(
-    frame: camera::ReceiverSharedFrame,
-    packets: mpsc::Sender<EncodedFrame>,
-    picture_loss_indicator: Arc<AtomicBool>,
-) {
-    let mut start_time = None;
-    let mut encoder = None;
-    let mut frames = WatchStream::new(frame);
-
-    while let Some(frame) = frames.next().await {
-        let Some(frame) = frame else { continue };
-        log::trace!("encode_frames: recv frame");
-
-        let format = frame.format();
-        encoder = reconfigure_encoder(encoder, &format);
-        let Some(encoder) = encoder.as_mut() else { panic!("no encoder"); };
-        let start_time = start_time.get_or_insert_with(Instant::now);
-        let pts = start_time.elapsed().as_millis() as _;
-        let force_keyframe = picture_loss_indicator.load(Ordering::Relaxed);
-
-        let mut encoded_data = {
-            let image = encoder
-                .wrap_image(frame.pixels().data, codec::ImageFormat::NV12)
-                .expect("wrap image");
-            encoder
-                .encode(pts, image, force_keyframe)
-                .expect("encoded data")
-        };
-
-        // Copy each frame so we can asynchronously send them one after the other without risking getting an invalidated buffer.
-        // TODO This copy can be skipped when we check that the packets sender is not full.
-        // TODO This copy can be skipped when we control the data buffer by using vpx_codec_set_cx_data_buf.
-        let frames: Vec<_> = encoded_data
-            .frames()
-            .inspect(|frame| {
-                if frame.keyframe() {
-                    log::debug!("encode_frames: Encoded key frame: {:?}", format)
-                }
-            })
-            .map(|frame| EncodedFrame {
-                bytes: bytes::Bytes::copy_from_slice(frame.data),
-                keyframe: frame.keyframe(),
-            })
-            .collect();
-
-        for frame in frames {
-            log::trace!("encode_frames: sending frame");
-            match packets.send(frame).await {
-                Ok(_) => log::trace!("encode_frames: sent frame"),
-                Err(err) => {
-                    log::debug!(
-                        "encode_frames: No encoded frame receiver. End encoding frames. {}",
-                        err
-                    );
-                    return;
+    // a thread and not a task, because a task blocked on stdin keeps the runtime from shutting down
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            match line.trim().parse::<u32>() {
+                Ok(bitrate) if bitrate > 0 => {
+                    log::info!("Bitrate of the encoders: {} kbit/s", bitrate);
+                    for bitrate_tx in &bitrates {
+                        // an encoder which ended has no receiver anymore
+                        let _ = bitrate_tx.send(bitrate);
+                    }
                 }
+                _ => log::warn!("Bitrate {:?} is not in kbit/s, like 1500.", line),
             }
         }
-    }
+    });
+}
 
-    log::debug!("encode_frames: End.");
+fn create_source(args: &Args) -> anyhow::Result<Box<dyn VideoSource>> {
+    let fps = args.fps.unwrap_or(30.0);
+    Ok(match args.source.as_str() {
+        "camera" => Box::new(match &args.camera {
+            Some(query) => camera::find(query)?,
+            None => camera::Camera::default()?,
+        }),
+        "testsrc" => {
+            let (width, height) = args.size;
+            Box::new(source::TestPattern::new(args.pattern, width, height, fps))
+        }
+        "raw" => Box::new(match &args.raw_format {
+            Some(pixel_format) => {
+                let (width, height) = args.size;
+                let format = frame::SampleFormat {
+                    width: width as i32,
+                    height: height as i32,
+                    pixel_format: pixel_format.clone(),
+                };
+                source::RawFiles::open_with_format(&args.input, format, fps)?
+            }
+            None => source::RawFiles::open(&args.input, fps)?,
+        }),
+        "y4m" => {
+            let Some(path) = args.input.first() else {
+                anyhow::bail!("the y4m source needs an --input file");
+            };
+            Box::new(source::Y4mFile::open(path, !args.fast, args.looping)?)
+        }
+        other => anyhow::bail!("unknown source {other}, use camera, testsrc, raw or y4m"),
+    })
 }
 
-fn reconfigure_encoder(
-    encoder: Option<codec::Vp8Encoder>,
-    format: &camera::SampleFormat,
-) -> Option<codec::Vp8Encoder> {
-    let config = codec::Vp8Config::new(format.width as u32, format.height as u32, [1, 1000], 5000)
-        .expect("config");
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("size {size} is not like 1280x720"))?;
+    let width = width
+        .parse()
+        .map_err(|err| format!("width {width}: {err}"))?;
+    let height = height
+        .parse()
+        .map_err(|err| format!("height {height}: {err}"))?;
+    Ok((width, height))
+}
 
-    if let Some(encoder) = encoder {
-        if encoder.config() == &config {
-            return Some(encoder);
-        }
+async fn exit_on_ctrl_c(exit_tx: broadcast::Sender<()>) {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        log::debug!("Ctrl-C signal handler broke. Exit. ({})", err);
     }
-
-    Some(codec::Vp8Encoder::new(&config).expect("encoder"))
+    // ignore err, because this means everybody who can exit, exitted already.
+    let _ = exit_tx.send(());
 }
 
 fn init_logging() {
@@ -0,0 +1,200 @@
+//! Burns text into frames before encoding, like the wall-clock time and the frame number for latency debugging,
+//! so recordings and remote viewers show exactly which frame they are looking at.
+
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    frame::{BufferPool, Frame, SampleFormat, VideoFrame},
+    text,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("can not burn text into {0}, only 4:2:0 formats")]
+    UnsupportedFormat(String),
+    #[error("the planes of the frame do not match {0}")]
+    InvalidPlanes(SampleFormat),
+}
+
+/// A line of an [overlay](Overlay).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// The wall-clock time of burning in, in UTC with milliseconds.
+    Time,
+    /// The number of the frame, counting every frame of the overlay from 0.
+    Frame,
+    /// The format of the frames as captured, before transforming and scaling them.
+    Format,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "time" => Ok(Self::Time),
+            "frame" => Ok(Self::Frame),
+            "format" => Ok(Self::Format),
+            _ => Err(format!(
+                "unknown overlay field {s}, use time, frame or format"
+            )),
+        }
+    }
+}
+
+const WHITE: u8 = 235;
+const BLACK: u8 = 16;
+/// No color, in the chroma planes.
+const NEUTRAL: u8 = 128;
+
+/// Burns white lines of text on a black box into the bottom left corner of 4:2:0 frames, like NV12 and I420,
+/// with the embedded bitmap font. The top left corner is left to the burn-in of the test pattern.
+pub struct Overlay {
+    fields: Vec<Field>,
+    label: Option<String>,
+    /// Also makes the box black and the text white in the chroma planes. Otherwise only the luma changes,
+    /// which leaves the box and the text tinted with the colors of the picture.
+    chroma: bool,
+    pool: BufferPool,
+    frame_number: u64,
+}
+
+impl Overlay {
+    /// The label comes as last line.
+    pub fn new(fields: Vec<Field>, label: Option<String>, chroma: bool) -> Self {
+        Self {
+            fields,
+            label,
+            chroma,
+            pool: BufferPool::default(),
+            frame_number: 0,
+        }
+    }
+
+    /// Whether there is no line to burn in.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.label.is_none()
+    }
+
+    /// Burns the lines into a copy of the frame, with the format of the captured frames for [Field::Format].
+    pub fn burn_in(
+        &mut self,
+        frame: &dyn Frame,
+        capture_format: Option<&SampleFormat>,
+    ) -> Result<VideoFrame> {
+        let format = frame.format();
+        let layout = format.plane_layout().filter(|layout| {
+            layout.len() > 1 && layout[1..].iter().all(|plane| plane.subsampling == (2, 2))
+        });
+        if layout.is_none() {
+            return Err(Error::UnsupportedFormat(format.pixel_format.clone()));
+        }
+        let mut burned = VideoFrame::copy_from(&self.pool, frame)
+            .ok_or_else(|| Error::InvalidPlanes(format.clone()))?;
+
+        let lines = self.lines(capture_format);
+        self.frame_number += 1;
+        self.draw(&mut burned, &lines);
+        Ok(burned)
+    }
+
+    fn draw(&self, frame: &mut VideoFrame, lines: &[String]) {
+        let height = frame.format().height as usize;
+        let scale = (height / 180).max(1);
+        let margin = 2 * scale;
+        let line_height = text::GLYPH_HEIGHT * scale + margin;
+        let text_width = lines
+            .iter()
+            .map(|line| text::text_size(line, scale).0)
+            .max()
+            .unwrap_or_default();
+        // even box position and size keep whole chroma samples within the box
+        let box_width = (text_width + 2 * margin + 1) & !1;
+        let box_height = (lines.len() * line_height + margin + 1) & !1;
+        let box_y = height.saturating_sub(box_height) & !1;
+
+        let mut planes = frame.planes_mut();
+        let luma = planes.next().expect("4:2:0 formats have a luma plane");
+        let (stride, plane_width, plane_height) = (luma.stride, luma.width, luma.height);
+        text::fill_rect(
+            luma.data,
+            stride,
+            plane_width,
+            plane_height,
+            0,
+            box_y,
+            box_width,
+            box_height,
+            BLACK,
+        );
+        for (index, line) in lines.iter().enumerate() {
+            let y = box_y + margin + index * line_height;
+            text::draw_text(
+                luma.data,
+                stride,
+                plane_width,
+                plane_height,
+                margin,
+                y,
+                scale,
+                line,
+                WHITE,
+            );
+        }
+
+        if self.chroma {
+            for chroma in planes {
+                // interleaved U and V are both neutral, so samples of several bytes are filled byte by byte
+                let bytes = chroma.bytes_per_pixel;
+                text::fill_rect(
+                    chroma.data,
+                    chroma.stride,
+                    chroma.width * bytes,
+                    chroma.height,
+                    0,
+                    box_y / 2,
+                    box_width / 2 * bytes,
+                    box_height / 2,
+                    NEUTRAL,
+                );
+            }
+        }
+    }
+
+    fn lines(&self, capture_format: Option<&SampleFormat>) -> Vec<String> {
+        let mut lines: Vec<_> = self
+            .fields
+            .iter()
+            .map(|field| match field {
+                Field::Time => wall_clock(SystemTime::now()),
+                Field::Frame => format!("#{:08}", self.frame_number),
+                Field::Format => match capture_format {
+                    Some(format) => format.to_string(),
+                    None => "unknown format".to_string(),
+                },
+            })
+            .collect();
+        lines.extend(self.label.clone());
+        lines
+    }
+}
+
+/// The time of day in UTC like `12:34:56.789 UTC`.
+fn wall_clock(time: SystemTime) -> String {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03} UTC",
+        millis / 3_600_000 % 24,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
@@ -0,0 +1,207 @@
+//! Chains sources, filters, encoders and sinks into a pipeline of tasks, which end together.
+//!
+//! Every [element](Element) runs as a task of its own and talks to the others only through the channels
+//! of its input and output [port](Port). Raw [frames](Frames) and encoded [packets](Packets) flow differently:
+//! * raw frames go through a watch channel of the latest frame, so consumers slower than the producer
+//!   skip frames instead of holding it up, and any number of consumers can share the output of an element
+//! * encoded frames go through a channel of a few frames, so the producer waits for a slower consumer,
+//!   because decoders need every frame, and only one consumer gets them
+//!
+//! An element ends when its input ends or when nobody receives its output anymore, and dropping its output
+//! then ends the elements after it. Sources end on [exit](Pipeline::exit), so ending them ends the pipeline.
+
+use std::{future::Future, pin::Pin, time::Duration};
+
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    task::JoinSet,
+};
+
+use crate::frame::{ReceiverSharedFrame, SenderSharedFrame};
+
+mod description;
+mod elements;
+
+pub use description::Description;
+pub use elements::*;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{name} failed: {source}")]
+    Failed {
+        name: &'static str,
+        source: anyhow::Error,
+    },
+    #[error("{0} panicked")]
+    Panicked(&'static str),
+}
+
+/// What flows between elements and the channel it flows through.
+pub trait Port: 'static {
+    type Sender: Send + 'static;
+    type Receiver: Send + 'static;
+
+    fn channel() -> (Self::Sender, Self::Receiver);
+}
+
+/// Raw frames, of which consumers only get the latest.
+pub enum Frames {}
+
+impl Port for Frames {
+    type Sender = SenderSharedFrame;
+    type Receiver = ReceiverSharedFrame;
+
+    fn channel() -> (Self::Sender, Self::Receiver) {
+        watch::channel(None)
+    }
+}
+
+/// Encoded frames, of which the consumer gets every one.
+pub enum Packets {}
+
+impl Port for Packets {
+    type Sender = mpsc::Sender<EncodedFrame>;
+    type Receiver = mpsc::Receiver<EncodedFrame>;
+
+    fn channel() -> (Self::Sender, Self::Receiver) {
+        mpsc::channel(3)
+    }
+}
+
+/// Nothing, the input of sources and the output of sinks.
+impl Port for () {
+    type Sender = ();
+    type Receiver = ();
+
+    fn channel() -> (Self::Sender, Self::Receiver) {
+        ((), ())
+    }
+}
+
+pub struct EncodedFrame {
+    pub bytes: bytes::Bytes,
+    pub keyframe: bool,
+    /// Capture time of the source frame relative to the first encoded frame.
+    pub pts: Duration,
+    pub duration: Duration,
+}
+
+impl std::fmt::Debug for EncodedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncodedFrame")
+            .field("bytes", &self.bytes.len())
+            .field("pts", &self.pts)
+            .finish()
+    }
+}
+
+/// The running of an element until it ends.
+pub type Run = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// The receiver of the input of an element.
+pub type Input<E> = <<E as Element>::Input as Port>::Receiver;
+/// The sender of the output of an element.
+pub type Output<E> = <<E as Element>::Output as Port>::Sender;
+
+/// A source, filter, encoder or sink of a [Pipeline].
+pub trait Element: Send + 'static {
+    type Input: Port;
+    type Output: Port;
+
+    /// The name in logs and errors.
+    fn name(&self) -> &'static str;
+
+    /// Runs until the input ends, nobody receives the output anymore or, for sources, until exit.
+    /// An error stops the whole pipeline.
+    fn run(self, input: Input<Self>, output: Output<Self>, exit: broadcast::Receiver<()>) -> Run;
+}
+
+/// Starts elements as tasks and supervises them. The first error of an element exits the pipeline,
+/// like ending every source does.
+pub struct Pipeline {
+    exit_tx: broadcast::Sender<()>,
+    tasks: JoinSet<(&'static str, bool, Result<()>)>,
+    sources: usize,
+}
+
+impl Pipeline {
+    /// Exits together with everything else using the exit channel.
+    pub fn new(exit_tx: broadcast::Sender<()>) -> Self {
+        Self {
+            exit_tx,
+            tasks: JoinSet::new(),
+            sources: 0,
+        }
+    }
+
+    /// Starts a source and returns the receiver of its output.
+    pub fn source<E>(&mut self, element: E) -> <E::Output as Port>::Receiver
+    where
+        E: Element<Input = ()>,
+    {
+        self.sources += 1;
+        self.start(element, (), true)
+    }
+
+    /// Starts an element with the receiver of the output of another one and returns the receiver of its output.
+    pub fn add<E: Element>(
+        &mut self,
+        element: E,
+        input: Input<E>,
+    ) -> <E::Output as Port>::Receiver {
+        self.start(element, input, false)
+    }
+
+    fn start<E: Element>(
+        &mut self,
+        element: E,
+        input: Input<E>,
+        source: bool,
+    ) -> <E::Output as Port>::Receiver {
+        let name = element.name();
+        let (output, receiver) = E::Output::channel();
+        let run = tokio::spawn(element.run(input, output, self.exit_tx.subscribe()));
+        self.tasks.spawn(async move {
+            let result = match run.await {
+                Ok(result) => result.map_err(|source| Error::Failed { name, source }),
+                Err(_) => Err(Error::Panicked(name)),
+            };
+            (name, source, result)
+        });
+        receiver
+    }
+
+    /// Tells every source to end.
+    pub fn exit(&self) {
+        // an error means everybody who can exit, exitted already
+        let _ = self.exit_tx.send(());
+    }
+
+    /// Waits until every element ended and returns the first error.
+    pub async fn supervise(mut self) -> Result<()> {
+        let mut first_error = None;
+        while let Some(joined) = self.tasks.join_next().await {
+            let Ok((name, source, result)) = joined else {
+                continue;
+            };
+            match result {
+                Ok(()) => log::debug!("pipeline: {} ended.", name),
+                Err(err) => {
+                    log::error!("pipeline: {}. Exit.", err);
+                    self.exit();
+                    first_error.get_or_insert(err);
+                }
+            }
+            if source {
+                self.sources -= 1;
+                if self.sources == 0 {
+                    log::debug!("pipeline: Every source ended. Exit.");
+                    self.exit();
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
@@ -0,0 +1,1249 @@
+//! Describes a pipeline as text like `testsrc ! scale 1280x720 ! vp8enc bitrate=1500 ! webrtc`,
+//! or as a TOML file with a table per element, so configurations can be tried without recompiling.
+//!
+//! ```toml
+//! [[elements]]
+//! type = "camera"
+//! name = "FaceTime"
+//!
+//! [[elements]]
+//! type = "vp8enc"
+//! bitrate = 1500
+//!
+//! [[elements]]
+//! type = "ivf"
+//! file = "out.ivf"
+//! ```
+//!
+//! Every element is checked before any starts: its properties, and whether it takes what the element before
+//! it outputs.
+
+use std::{
+    fmt::Display,
+    ops::RangeInclusive,
+    path::PathBuf,
+    str::FromStr,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use tokio::sync::{mpsc, watch};
+
+use super::{
+    BurnInFrames, DecodeFrames, EncodeFrames, LimitFrames, Pipeline, ScaleFrames, Source,
+    TransformFrames, WebRtcTestapp, WriteIvf,
+};
+use crate::{
+    camera, codec, frame, overlay, rate, scale,
+    source::{self, VideoSource},
+    transform, webrtc,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("the pipeline has no elements, describe one like `testsrc ! vp8enc ! webrtc`")]
+    Empty,
+    #[error("element {0} of the pipeline is missing, between two `!` or at an end")]
+    MissingElement(usize),
+    #[error("a quote is not closed")]
+    UnclosedQuote,
+    #[error("unknown element {name}, use one of {known}")]
+    UnknownElement { name: String, known: String },
+    #[error("{element} has no property {property}, use one of {known}")]
+    UnknownProperty {
+        element: &'static str,
+        property: String,
+        known: String,
+    },
+    #[error("{0} takes no properties")]
+    NoProperties(&'static str),
+    #[error("{element} takes no value without a name like {value}, use one of {known}")]
+    UnnamedValue {
+        element: &'static str,
+        value: String,
+        known: String,
+    },
+    #[error("{element} has {property} twice")]
+    DuplicateProperty {
+        element: &'static str,
+        property: String,
+    },
+    #[error("{element} needs {property}")]
+    MissingProperty {
+        element: &'static str,
+        property: &'static str,
+    },
+    #[error("{element} {property}={value}: {reason}")]
+    InvalidValue {
+        element: &'static str,
+        property: &'static str,
+        value: String,
+        reason: String,
+    },
+    #[error(
+        "the pipeline starts with {element}, which takes {input}, start with a source: {sources}"
+    )]
+    NoSource {
+        element: &'static str,
+        input: Caps,
+        sources: String,
+    },
+    #[error("{upstream} outputs {output}, but {downstream} takes {input}")]
+    CapsMismatch {
+        upstream: &'static str,
+        output: Caps,
+        downstream: &'static str,
+        input: Caps,
+    },
+    #[error("the pipeline ends with {element}, which outputs {output}, end with a sink: {sinks}")]
+    NoSink {
+        element: &'static str,
+        output: Caps,
+        sinks: String,
+    },
+    #[error("invalid TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("element {index} of the TOML file: {reason}")]
+    InvalidToml { index: usize, reason: String },
+    #[error("reading the statistics of a first pass from {path}: {source}")]
+    Stats {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// What an element takes or outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Caps {
+    Nothing,
+    Frames,
+    Vp8,
+    Vp9,
+    /// Either VP8 or VP9 frames, only taken by sinks.
+    Packets,
+}
+
+impl Caps {
+    /// Whether an element which takes these takes the output of another.
+    fn takes(self, output: Caps) -> bool {
+        self == output || self == Caps::Packets && matches!(output, Caps::Vp8 | Caps::Vp9)
+    }
+}
+
+impl Display for Caps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Caps::Nothing => "nothing",
+            Caps::Frames => "raw frames",
+            Caps::Vp8 => "VP8 frames",
+            Caps::Vp9 => "VP9 frames",
+            Caps::Packets => "VP8 or VP9 frames",
+        })
+    }
+}
+
+/// An element which can be described, with the property which can be given without name.
+struct Kind {
+    name: &'static str,
+    input: Caps,
+    output: Caps,
+    properties: &'static [&'static str],
+    unnamed: Option<&'static str>,
+}
+
+const KINDS: &[Kind] = &[
+    Kind::source("testsrc", &["pattern", "size", "fps"], Some("pattern")),
+    Kind::source("camera", &["name", "size", "fps"], Some("name")),
+    Kind::source("raw", &["file", "format", "size", "fps"], Some("file")),
+    Kind::source("y4m", &["file", "loop", "fast"], Some("file")),
+    Kind::filter("transform", &["crop", "rotate", "mirror", "flip"], None),
+    Kind::filter("rate", &["fps"], Some("fps")),
+    Kind::filter("scale", &["size", "fit", "filter"], Some("size")),
+    Kind::filter("overlay", &["fields", "label", "chroma"], Some("fields")),
+    Kind {
+        name: "vp8enc",
+        input: Caps::Frames,
+        output: Caps::Vp8,
+        properties: &[
+            "bitrate",
+            "preset",
+            "rate-control",
+            "cq-level",
+            "min-q",
+            "max-q",
+            "undershoot",
+            "overshoot",
+            "buffer",
+            "buffer-initial",
+            "buffer-optimal",
+            "kf-min",
+            "kf-max",
+            "cpu-used",
+            "threads",
+            "lag",
+            "noise-sensitivity",
+            "static-threshold",
+            "sharpness",
+            "error-resilient",
+            "deadline",
+            "psnr",
+            "pass",
+            "stats",
+        ],
+        unnamed: None,
+    },
+    Kind {
+        name: "vp9enc",
+        input: Caps::Frames,
+        output: Caps::Vp9,
+        properties: &[
+            "bitrate",
+            "rate-control",
+            "speed",
+            "threads",
+            "tile-columns",
+            "row-mt",
+            "aq-mode",
+            "tune",
+            "error-resilient",
+            "deadline",
+            "psnr",
+        ],
+        unnamed: None,
+    },
+    Kind {
+        name: "vp8dec",
+        input: Caps::Vp8,
+        output: Caps::Frames,
+        properties: &[],
+        unnamed: None,
+    },
+    Kind::sink("webrtc", &[], None),
+    Kind::sink("ivf", &["file"], Some("file")),
+];
+
+impl Kind {
+    const fn source(
+        name: &'static str,
+        properties: &'static [&'static str],
+        unnamed: Option<&'static str>,
+    ) -> Self {
+        Self {
+            name,
+            input: Caps::Nothing,
+            output: Caps::Frames,
+            properties,
+            unnamed,
+        }
+    }
+
+    const fn filter(
+        name: &'static str,
+        properties: &'static [&'static str],
+        unnamed: Option<&'static str>,
+    ) -> Self {
+        Self {
+            name,
+            input: Caps::Frames,
+            output: Caps::Frames,
+            properties,
+            unnamed,
+        }
+    }
+
+    const fn sink(
+        name: &'static str,
+        properties: &'static [&'static str],
+        unnamed: Option<&'static str>,
+    ) -> Self {
+        Self {
+            name,
+            input: Caps::Packets,
+            output: Caps::Nothing,
+            properties,
+            unnamed,
+        }
+    }
+
+    fn find(name: &str) -> Result<&'static Kind> {
+        KINDS
+            .iter()
+            .find(|kind| kind.name == name)
+            .ok_or_else(|| Error::UnknownElement {
+                name: name.to_string(),
+                known: names(|_| true),
+            })
+    }
+}
+
+/// The names of the kinds for which the filter is true, separated by commas.
+fn names(filter: impl Fn(&Kind) -> bool) -> String {
+    let names: Vec<_> = KINDS
+        .iter()
+        .filter(|kind| filter(kind))
+        .map(|kind| kind.name)
+        .collect();
+    names.join(", ")
+}
+
+/// A checked description of a pipeline, a chain of elements from a source to a sink.
+#[derive(Debug, Clone)]
+pub struct Description {
+    elements: Vec<Spec>,
+}
+
+/// An element with its properties.
+#[derive(Debug, Clone)]
+enum Spec {
+    TestSrc {
+        pattern: source::Pattern,
+        size: (u32, u32),
+        fps: f64,
+    },
+    Camera {
+        name: Option<String>,
+        size: Option<(u32, u32)>,
+        fps: Option<f64>,
+    },
+    Raw {
+        files: Vec<PathBuf>,
+        format: Option<String>,
+        size: (u32, u32),
+        fps: f64,
+    },
+    Y4m {
+        file: PathBuf,
+        looping: bool,
+        fast: bool,
+    },
+    Transform(transform::Transform),
+    Rate(f64),
+    Scale(scale::Filter, scale::Size),
+    Overlay {
+        fields: Vec<overlay::Field>,
+        label: Option<String>,
+        chroma: bool,
+    },
+    Vp8Enc {
+        bitrate: u32,
+        tuning: codec::Vp8Tuning,
+        psnr: bool,
+        pass: Option<Pass>,
+    },
+    Vp9Enc {
+        bitrate: u32,
+        controls: codec::Vp9Controls,
+        psnr: bool,
+    },
+    Vp8Dec,
+    WebRtc,
+    Ivf(PathBuf),
+}
+
+/// Of a two-pass encoding, with the file of the statistics the first writes and the second reads.
+#[derive(Clone, Debug)]
+enum Pass {
+    First(PathBuf),
+    Second(PathBuf),
+}
+
+impl FromStr for Description {
+    type Err = Error;
+
+    /// Elements separated by `!`, every one its name followed by properties like `name=value`,
+    /// in double quotes for values with spaces or `!`.
+    fn from_str(s: &str) -> Result<Self> {
+        let words = split(s)?;
+        if words.iter().all(|words| words.is_empty()) {
+            return Err(Error::Empty);
+        }
+        let elements = words
+            .into_iter()
+            .enumerate()
+            .map(|(index, words)| {
+                let (name, properties) = words
+                    .split_first()
+                    .ok_or(Error::MissingElement(index + 1))?;
+                let properties = properties
+                    .iter()
+                    .map(|word| match word.split_once('=') {
+                        Some((name, value)) => (Some(name.to_string()), value.to_string()),
+                        None => (None, word.clone()),
+                    })
+                    .collect();
+                Ok((name.clone(), properties))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(elements)
+    }
+}
+
+/// Splits the text into the words of every element, at `!` and whitespace outside of double quotes.
+fn split(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut elements = vec![vec![]];
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            c if quoted => word.get_or_insert_with(String::new).push(c),
+            '!' => {
+                elements.last_mut().unwrap().extend(word.take());
+                elements.push(vec![]);
+            }
+            c if c.is_whitespace() => elements.last_mut().unwrap().extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::UnclosedQuote);
+    }
+    elements.last_mut().unwrap().extend(word.take());
+    Ok(elements)
+}
+
+impl Description {
+    /// Reads a TOML document with an `[[elements]]` table per element, with the name of the element as `type`
+    /// and its properties. Lists become values separated by commas, like the overlay fields.
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let document: toml::Value = toml.parse()?;
+        let tables = match document.get("elements") {
+            Some(toml::Value::Array(tables)) => tables.as_slice(),
+            _ => &[],
+        };
+        if tables.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        let elements = tables
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                let invalid = |reason: &str| Error::InvalidToml {
+                    index: index + 1,
+                    reason: reason.to_string(),
+                };
+                let table = table.as_table().ok_or_else(|| invalid("not a table"))?;
+                let name = table
+                    .get("type")
+                    .and_then(|name| name.as_str())
+                    .ok_or_else(|| invalid("type is missing, like type = \"testsrc\""))?;
+                let properties = table
+                    .iter()
+                    .filter(|(key, _)| *key != "type")
+                    .map(|(key, value)| {
+                        let value = toml_value(value)
+                            .ok_or_else(|| invalid(&format!("{key} is no text, number or list")))?;
+                        Ok((Some(key.clone()), value))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((name.to_string(), properties))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(elements)
+    }
+
+    /// Checks the elements, every one with properties with or without name.
+    fn new(elements: Vec<(String, Vec<Property>)>) -> Result<Self> {
+        let elements = elements
+            .into_iter()
+            .map(|(name, properties)| {
+                let kind = Kind::find(&name)?;
+                let properties = Properties::new(kind, properties)?;
+                Ok((kind, properties.spec()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (Some((first, _)), Some((last, _))) = (elements.first(), elements.last()) else {
+            return Err(Error::Empty);
+        };
+        if first.input != Caps::Nothing {
+            return Err(Error::NoSource {
+                element: first.name,
+                input: first.input,
+                sources: names(|kind| kind.input == Caps::Nothing),
+            });
+        }
+        for pair in elements.windows(2) {
+            let (upstream, downstream) = (pair[0].0, pair[1].0);
+            if !downstream.input.takes(upstream.output) {
+                return Err(Error::CapsMismatch {
+                    upstream: upstream.name,
+                    output: upstream.output,
+                    downstream: downstream.name,
+                    input: downstream.input,
+                });
+            }
+        }
+        if last.output != Caps::Nothing {
+            return Err(Error::NoSink {
+                element: last.name,
+                output: last.output,
+                sinks: names(|kind| kind.output == Caps::Nothing),
+            });
+        }
+        Ok(Self {
+            elements: elements.into_iter().map(|(_, spec)| spec).collect(),
+        })
+    }
+
+    /// Starts the elements in the pipeline, the webrtc element with the offer answer exchange of the HTTP test app.
+    /// Only opening the source and reading the statistics of a second pass can fail, before any element starts.
+    ///
+    /// Returns a sender for the bitrate of every encoder in the order of the description,
+    /// to change it while running. Dropping one keeps the bitrate of its encoder as it is.
+    pub fn build(
+        self,
+        pipeline: &mut Pipeline,
+        exchange_rx: mpsc::Receiver<webrtc::OfferAnswerExchange>,
+    ) -> anyhow::Result<Vec<watch::Sender<u32>>> {
+        let mut stats = self
+            .elements
+            .iter()
+            .filter_map(|spec| match spec {
+                Spec::Vp8Enc {
+                    pass: Some(Pass::Second(path)),
+                    ..
+                } => Some(path),
+                _ => None,
+            })
+            .map(|path| match std::fs::read(path) {
+                Ok(data) => Ok(codec::FirstPassStats::from(data)),
+                Err(source) => Err(Error::Stats {
+                    path: path.clone(),
+                    source,
+                }),
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter();
+        let mut elements = self.elements.into_iter();
+        let (source, preferences) = match elements.next() {
+            Some(spec) => spec.open_source()?,
+            None => return Err(Error::Empty.into()),
+        };
+        let source_frame = pipeline.source(Source::new(source, preferences));
+
+        let mut exchange_rx = Some(exchange_rx);
+        let picture_loss_indicator = Arc::new(AtomicBool::new(false));
+        // of the encoder, for the sink
+        let mut codec = codec::Codec::Vp8;
+        let mut frames = Some(source_frame.clone());
+        let mut packets = None;
+        let mut bitrates = vec![];
+        for spec in elements {
+            match (spec, frames.take(), packets.take()) {
+                (Spec::Transform(transform), Some(input), _) => {
+                    let element = TransformFrames::new(watch::channel(transform).1);
+                    frames = Some(pipeline.add(element, input));
+                }
+                (Spec::Rate(fps), Some(input), _) => {
+                    let limiter = rate::RateLimiter::new(Some(fps));
+                    let element = LimitFrames::new(watch::channel(Some(fps)).1, limiter);
+                    frames = Some(pipeline.add(element, input));
+                }
+                (Spec::Scale(filter, size), Some(input), _) => {
+                    let element = ScaleFrames::new(scale::Scaler::new(filter, size));
+                    frames = Some(pipeline.add(element, input));
+                }
+                (
+                    Spec::Overlay {
+                        fields,
+                        label,
+                        chroma,
+                    },
+                    Some(input),
+                    _,
+                ) => {
+                    let overlay = overlay::Overlay::new(fields, label, chroma);
+                    let element = BurnInFrames::new(overlay, source_frame.clone());
+                    frames = Some(pipeline.add(element, input));
+                }
+                (
+                    Spec::Vp8Enc {
+                        bitrate,
+                        tuning,
+                        psnr,
+                        pass,
+                    },
+                    Some(input),
+                    _,
+                ) => {
+                    let (bitrate_tx, bitrate) = watch::channel(bitrate);
+                    bitrates.push(bitrate_tx);
+                    let mut element = EncodeFrames::new(picture_loss_indicator.clone(), bitrate)
+                        .vp8(tuning)
+                        .psnr(psnr);
+                    match pass {
+                        Some(Pass::First(path)) => element = element.first_pass(path),
+                        Some(Pass::Second(_)) => {
+                            let stats = stats.next().expect("read for every second pass");
+                            element = element.second_pass(stats);
+                        }
+                        None => {}
+                    }
+                    codec = element.codec();
+                    packets = Some(pipeline.add(element, input));
+                }
+                (
+                    Spec::Vp9Enc {
+                        bitrate,
+                        controls,
+                        psnr,
+                    },
+                    Some(input),
+                    _,
+                ) => {
+                    let (bitrate_tx, bitrate) = watch::channel(bitrate);
+                    bitrates.push(bitrate_tx);
+                    let element = EncodeFrames::new(picture_loss_indicator.clone(), bitrate)
+                        .vp9(controls)
+                        .psnr(psnr);
+                    codec = element.codec();
+                    packets = Some(pipeline.add(element, input));
+                }
+                (Spec::Vp8Dec, _, Some(input)) => {
+                    frames = Some(pipeline.add(DecodeFrames, input));
+                }
+                (Spec::WebRtc, _, Some(input)) => {
+                    let exchange_rx = exchange_rx.take().expect("only the last element is a sink");
+                    let element =
+                        WebRtcTestapp::new(exchange_rx, picture_loss_indicator.clone(), codec);
+                    pipeline.add(element, input);
+                }
+                (Spec::Ivf(path), _, Some(input)) => {
+                    pipeline.add(WriteIvf::new(path, codec), input)
+                }
+                (spec, _, _) => unreachable!("the caps of {spec:?} are checked"),
+            }
+        }
+        Ok(bitrates)
+    }
+}
+
+/// A TOML value as the text of a property.
+fn toml_value(value: &toml::Value) -> Option<String> {
+    Some(match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(toml_value)
+            .collect::<Option<Vec<_>>>()?
+            .join(","),
+        _ => return None,
+    })
+}
+
+/// A property of an element as written, with or without name, and its value.
+type Property = (Option<String>, String);
+
+/// The properties of an element, every one known to the element and given only once.
+struct Properties {
+    kind: &'static Kind,
+    values: Vec<(&'static str, String)>,
+}
+
+impl Properties {
+    fn new(kind: &'static Kind, properties: Vec<Property>) -> Result<Self> {
+        if kind.properties.is_empty() && !properties.is_empty() {
+            return Err(Error::NoProperties(kind.name));
+        }
+        let known = || kind.properties.join(", ");
+        let mut values: Vec<(&'static str, String)> = vec![];
+        for (name, value) in properties {
+            let name = match name {
+                Some(name) => kind
+                    .properties
+                    .iter()
+                    .find(|property| **property == name)
+                    .ok_or_else(|| Error::UnknownProperty {
+                        element: kind.name,
+                        property: name.clone(),
+                        known: known(),
+                    })?,
+                None => kind.unnamed.as_ref().ok_or_else(|| Error::UnnamedValue {
+                    element: kind.name,
+                    value: value.clone(),
+                    known: known(),
+                })?,
+            };
+            if values.iter().any(|(property, _)| property == name) {
+                return Err(Error::DuplicateProperty {
+                    element: kind.name,
+                    property: name.to_string(),
+                });
+            }
+            values.push((name, value));
+        }
+        Ok(Self { kind, values })
+    }
+
+    fn spec(&self) -> Result<Spec> {
+        Ok(match self.kind.name {
+            "testsrc" => Spec::TestSrc {
+                pattern: self.get("pattern")?.unwrap_or(source::Pattern::Bars),
+                size: self.size("size")?.unwrap_or((1280, 720)),
+                fps: self.get("fps")?.unwrap_or(30.0),
+            },
+            "camera" => Spec::Camera {
+                name: self.get("name")?,
+                size: self.size("size")?,
+                fps: self.get("fps")?,
+            },
+            "raw" => Spec::Raw {
+                files: self
+                    .required::<String>("file")?
+                    .split(',')
+                    .map(PathBuf::from)
+                    .collect(),
+                format: self.get("format")?,
+                size: self.size("size")?.unwrap_or((1280, 720)),
+                fps: self.get("fps")?.unwrap_or(30.0),
+            },
+            "y4m" => Spec::Y4m {
+                file: self.required("file")?,
+                looping: self.get("loop")?.unwrap_or(false),
+                fast: self.get("fast")?.unwrap_or(false),
+            },
+            "transform" => Spec::Transform(transform::Transform {
+                crop: self.get("crop")?,
+                flip_horizontal: self.get("mirror")?.unwrap_or(false),
+                flip_vertical: self.get("flip")?.unwrap_or(false),
+                rotation: self.get("rotate")?.unwrap_or_default(),
+            }),
+            "rate" => {
+                let fps: f64 = self.required("fps")?;
+                // otherwise the limiter sends every frame
+                if !(fps > 0.0 && fps.is_finite()) {
+                    let reason = "use a frame rate above 0".to_string();
+                    return Err(self.invalid("fps", &fps.to_string(), reason));
+                }
+                Spec::Rate(fps)
+            }
+            "scale" => {
+                let (width, height) = self.size("size")?.ok_or(Error::MissingProperty {
+                    element: self.kind.name,
+                    property: "size",
+                })?;
+                let size = if self.get("fit")?.unwrap_or(false) {
+                    scale::Size::Fit(width, height)
+                } else {
+                    scale::Size::Fixed(width, height)
+                };
+                Spec::Scale(self.get("filter")?.unwrap_or(scale::Filter::Box), size)
+            }
+            "overlay" => Spec::Overlay {
+                fields: match self.value("fields") {
+                    Some(fields) => fields
+                        .split(',')
+                        .map(|field| self.parse("fields", field))
+                        .collect::<Result<_>>()?,
+                    None => vec![],
+                },
+                label: self.get("label")?,
+                chroma: self.get("chroma")?.unwrap_or(false),
+            },
+            "vp8enc" => {
+                // the properties change what the preset tunes
+                let defaults = match self.get("preset")? {
+                    Some(preset) => codec::Vp8Tuning::preset(preset),
+                    None => codec::Vp8Tuning::default(),
+                };
+                let min_quantizer = self.ranged("min-q", defaults.min_quantizer, 0..=63)?;
+                let max_quantizer = self.ranged("max-q", defaults.max_quantizer, 0..=63)?;
+                if min_quantizer > max_quantizer {
+                    let reason = format!("above max-q={max_quantizer}");
+                    return Err(self.invalid("min-q", &min_quantizer.to_string(), reason));
+                }
+                let keyframe_min_interval = self
+                    .get("kf-min")?
+                    .unwrap_or(defaults.keyframe_min_interval);
+                let keyframe_max_interval = self
+                    .get("kf-max")?
+                    .unwrap_or(defaults.keyframe_max_interval);
+                if keyframe_min_interval > keyframe_max_interval {
+                    let reason = format!("above kf-max={keyframe_max_interval}");
+                    return Err(self.invalid("kf-min", &keyframe_min_interval.to_string(), reason));
+                }
+                Spec::Vp8Enc {
+                    bitrate: self
+                        .get("bitrate")?
+                        .unwrap_or(EncodeFrames::DEFAULT_BITRATE),
+                    tuning: codec::Vp8Tuning {
+                        rate_control: self.get("rate-control")?.unwrap_or(defaults.rate_control),
+                        cq_level: self.ranged("cq-level", defaults.cq_level, 0..=63)?,
+                        min_quantizer,
+                        max_quantizer,
+                        undershoot_pct: self.ranged(
+                            "undershoot",
+                            defaults.undershoot_pct,
+                            0..=100,
+                        )?,
+                        overshoot_pct: self.ranged(
+                            "overshoot",
+                            defaults.overshoot_pct,
+                            0..=1000,
+                        )?,
+                        buffer_size: self.get("buffer")?.unwrap_or(defaults.buffer_size),
+                        buffer_initial_size: self
+                            .get("buffer-initial")?
+                            .unwrap_or(defaults.buffer_initial_size),
+                        buffer_optimal_size: self
+                            .get("buffer-optimal")?
+                            .unwrap_or(defaults.buffer_optimal_size),
+                        keyframe_min_interval,
+                        keyframe_max_interval,
+                        cpu_used: self.ranged("cpu-used", defaults.cpu_used, -16..=16)?,
+                        threads: self.ranged("threads", defaults.threads, 1..=64)?,
+                        lag_in_frames: self.ranged("lag", defaults.lag_in_frames, 0..=25)?,
+                        noise_sensitivity: self.ranged(
+                            "noise-sensitivity",
+                            defaults.noise_sensitivity,
+                            0..=6,
+                        )?,
+                        static_threshold: self
+                            .get("static-threshold")?
+                            .unwrap_or(defaults.static_threshold),
+                        sharpness: self.ranged("sharpness", defaults.sharpness, 0..=7)?,
+                        error_resilient: self
+                            .get("error-resilient")?
+                            .unwrap_or(defaults.error_resilient),
+                        deadline: self.get("deadline")?.unwrap_or(defaults.deadline),
+                    },
+                    psnr: self.get("psnr")?.unwrap_or(false),
+                    pass: match self.get::<u8>("pass")? {
+                        Some(1) => Some(Pass::First(self.required("stats")?)),
+                        Some(2) => Some(Pass::Second(self.required("stats")?)),
+                        Some(pass) => {
+                            let reason = "use 1 or 2".to_string();
+                            return Err(self.invalid("pass", &pass.to_string(), reason));
+                        }
+                        // stats without a pass would silently do nothing
+                        None if self.value("stats").is_some() => {
+                            return Err(Error::MissingProperty {
+                                element: self.kind.name,
+                                property: "pass",
+                            })
+                        }
+                        None => None,
+                    },
+                }
+            }
+            "vp9enc" => {
+                let defaults = codec::Vp9Controls::default();
+                let speed = self.ranged("speed", defaults.speed, -9..=9)?;
+                let tile_columns = self.get("tile-columns")?.unwrap_or(defaults.tile_columns);
+                if tile_columns > 6 {
+                    let reason = "use the log2 of the columns, 0 to 6".to_string();
+                    return Err(self.invalid("tile-columns", &tile_columns.to_string(), reason));
+                }
+                Spec::Vp9Enc {
+                    bitrate: self
+                        .get("bitrate")?
+                        .unwrap_or(EncodeFrames::DEFAULT_BITRATE),
+                    controls: codec::Vp9Controls {
+                        rate_control: self.get("rate-control")?.unwrap_or(defaults.rate_control),
+                        speed,
+                        threads: self.ranged("threads", defaults.threads, 1..=64)?,
+                        tile_columns,
+                        row_mt: self.get("row-mt")?.unwrap_or(defaults.row_mt),
+                        aq_mode: self.get("aq-mode")?.unwrap_or(defaults.aq_mode),
+                        tune: self.get("tune")?.unwrap_or(defaults.tune),
+                        error_resilient: self
+                            .get("error-resilient")?
+                            .unwrap_or(defaults.error_resilient),
+                        deadline: self.get("deadline")?.unwrap_or(defaults.deadline),
+                    },
+                    psnr: self.get("psnr")?.unwrap_or(false),
+                }
+            }
+            "vp8dec" => Spec::Vp8Dec,
+            "webrtc" => Spec::WebRtc,
+            "ivf" => Spec::Ivf(self.required("file")?),
+            name => unreachable!("every kind has a spec, but not {name}"),
+        })
+    }
+
+    fn value(&self, property: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(name, _)| *name == property)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn get<T>(&self, property: &'static str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.value(property)
+            .map(|value| self.parse(property, value))
+            .transpose()
+    }
+
+    fn required<T>(&self, property: &'static str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.get(property)?.ok_or(Error::MissingProperty {
+            element: self.kind.name,
+            property,
+        })
+    }
+
+    /// The value of the property or the default, either in the range.
+    fn ranged<T>(&self, property: &'static str, default: T, range: RangeInclusive<T>) -> Result<T>
+    where
+        T: FromStr + PartialOrd + Display,
+        T::Err: Display,
+    {
+        let value = self.get(property)?.unwrap_or(default);
+        if !range.contains(&value) {
+            let reason = format!("use {} to {}", range.start(), range.end());
+            return Err(self.invalid(property, &value.to_string(), reason));
+        }
+        Ok(value)
+    }
+
+    fn size(&self, property: &'static str) -> Result<Option<(u32, u32)>> {
+        self.value(property)
+            .map(|value| {
+                crate::parse_size(value).map_err(|reason| self.invalid(property, value, reason))
+            })
+            .transpose()
+    }
+
+    fn parse<T>(&self, property: &'static str, value: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        value
+            .parse()
+            .map_err(|err: T::Err| self.invalid(property, value, err.to_string()))
+    }
+
+    fn invalid(&self, property: &'static str, value: &str, reason: String) -> Error {
+        Error::InvalidValue {
+            element: self.kind.name,
+            property,
+            value: value.to_string(),
+            reason,
+        }
+    }
+}
+
+impl Spec {
+    /// Opens the source of a source element with the preferences for its format.
+    fn open_source(self) -> anyhow::Result<(Box<dyn VideoSource>, source::Preferences)> {
+        let preferences = |size: Option<(u32, u32)>, fps: Option<f64>| {
+            let size = size.map(|(width, height)| (width as i32, height as i32));
+            source::Preferences {
+                resolution: size,
+                min_framerate: fps,
+                aspect_ratio: size,
+                ..source::Preferences::encoder()
+            }
+        };
+        Ok(match self {
+            Spec::TestSrc { pattern, size, fps } => (
+                Box::new(source::TestPattern::new(pattern, size.0, size.1, fps)),
+                preferences(Some(size), Some(fps)),
+            ),
+            Spec::Camera { name, size, fps } => {
+                let camera = match name {
+                    Some(name) => camera::find(&name)?,
+                    None => camera::Camera::default()?,
+                };
+                (Box::new(camera), preferences(size, fps))
+            }
+            Spec::Raw {
+                files,
+                format,
+                size,
+                fps,
+            } => {
+                let raw = match format {
+                    Some(pixel_format) => {
+                        let format = frame::SampleFormat {
+                            width: size.0 as i32,
+                            height: size.1 as i32,
+                            pixel_format,
+                        };
+                        source::RawFiles::open_with_format(&files, format, fps)?
+                    }
+                    None => source::RawFiles::open(&files, fps)?,
+                };
+                (Box::new(raw), preferences(Some(size), Some(fps)))
+            }
+            Spec::Y4m {
+                file,
+                looping,
+                fast,
+            } => (
+                Box::new(source::Y4mFile::open(&file, !fast, looping)?),
+                preferences(None, None),
+            ),
+            spec => unreachable!("the first element is checked to be a source, not {spec:?}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Description> {
+        text.parse()
+    }
+
+    #[test]
+    fn examples() {
+        let description = parse("testsrc ! scale 1280x720 ! vp8enc bitrate=1500 ! webrtc").unwrap();
+        assert!(matches!(
+            description.elements[..],
+            [
+                Spec::TestSrc { .. },
+                Spec::Scale(scale::Filter::Box, scale::Size::Fixed(1280, 720)),
+                Spec::Vp8Enc { bitrate: 1500, .. },
+                Spec::WebRtc,
+            ]
+        ));
+
+        let description = parse("camera name=FaceTime ! vp8enc ! ivf file=out.ivf").unwrap();
+        match &description.elements[..] {
+            [Spec::Camera { name, .. }, Spec::Vp8Enc { bitrate, .. }, Spec::Ivf(file)] => {
+                assert_eq!(name.as_deref(), Some("FaceTime"));
+                assert_eq!(*bitrate, EncodeFrames::DEFAULT_BITRATE);
+                assert_eq!(file, &PathBuf::from("out.ivf"));
+            }
+            elements => panic!("{elements:?}"),
+        }
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            split(r#"camera "Face Time" ! overlay label="a ! b" ! ivf "#).unwrap(),
+            [
+                vec!["camera", "Face Time"],
+                vec!["overlay", "label=a ! b"],
+                vec!["ivf"],
+            ]
+        );
+        assert_eq!(split(r#"ivf """#).unwrap(), [vec!["ivf", ""]]);
+        assert!(matches!(
+            parse(r#"camera "FaceTime ! vp8enc ! webrtc"#),
+            Err(Error::UnclosedQuote)
+        ));
+    }
+
+    #[test]
+    fn missing_elements() {
+        assert!(matches!(parse(""), Err(Error::Empty)));
+        assert!(matches!(parse(" ! "), Err(Error::Empty)));
+        assert!(matches!(
+            parse("testsrc ! ! webrtc"),
+            Err(Error::MissingElement(2))
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8enc !"),
+            Err(Error::MissingElement(3))
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp10enc ! webrtc"),
+            Err(Error::UnknownElement { name, .. }) if name == "vp10enc"
+        ));
+    }
+
+    #[test]
+    fn caps() {
+        assert!(matches!(
+            parse("vp8enc ! webrtc"),
+            Err(Error::NoSource {
+                element: "vp8enc",
+                input: Caps::Frames,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8enc"),
+            Err(Error::NoSink {
+                element: "vp8enc",
+                output: Caps::Vp8,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8dec ! webrtc"),
+            Err(Error::CapsMismatch {
+                upstream: "testsrc",
+                output: Caps::Frames,
+                downstream: "vp8dec",
+                input: Caps::Vp8,
+            })
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp9enc ! vp8dec ! vp8enc ! webrtc"),
+            Err(Error::CapsMismatch {
+                upstream: "vp9enc",
+                downstream: "vp8dec",
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8enc ! scale 640x360 ! webrtc"),
+            Err(Error::CapsMismatch {
+                upstream: "vp8enc",
+                downstream: "scale",
+                ..
+            })
+        ));
+        parse("testsrc ! vp8enc ! vp8dec ! vp9enc ! ivf out.ivf").unwrap();
+    }
+
+    #[test]
+    fn properties() {
+        assert!(matches!(
+            parse("testsrc fps=30 fps=25 ! vp8enc ! webrtc"),
+            Err(Error::DuplicateProperty { element: "testsrc", property }) if property == "fps"
+        ));
+        assert!(matches!(
+            parse("testsrc pattern=bars bars ! vp8enc ! webrtc"),
+            Err(Error::DuplicateProperty { property, .. }) if property == "pattern"
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8enc speed=8 ! webrtc"),
+            Err(Error::UnknownProperty { element: "vp8enc", property, .. }) if property == "speed"
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8enc 1500 ! webrtc"),
+            Err(Error::UnnamedValue {
+                element: "vp8enc",
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8dec=1 ! webrtc"),
+            Err(Error::UnknownElement { .. })
+        ));
+        assert!(matches!(
+            parse("testsrc ! rate ! vp8enc ! webrtc"),
+            Err(Error::MissingProperty {
+                element: "rate",
+                property: "fps",
+            })
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8enc bitrate=fast ! webrtc"),
+            Err(Error::InvalidValue {
+                element: "vp8enc",
+                property: "bitrate",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(matches!(
+            parse("testsrc ! vp8enc min-q=40 max-q=30 ! webrtc"),
+            Err(Error::InvalidValue { property: "min-q", value, .. }) if value == "40"
+        ));
+        assert!(matches!(
+            // above the min-q of the preset
+            parse("testsrc ! vp8enc max-q=2 ! webrtc"),
+            Err(Error::InvalidValue {
+                property: "min-q",
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("testsrc ! vp8enc cq-level=64 ! webrtc"),
+            Err(Error::InvalidValue {
+                property: "cq-level",
+                ..
+            })
+        ));
+        parse("testsrc ! vp8enc min-q=30 max-q=30 ! webrtc").unwrap();
+    }
+
+    #[test]
+    fn frame_rates_above_0() {
+        for fps in ["0", "-15", "nan", "inf"] {
+            let text = format!("testsrc ! rate {fps} ! vp8enc ! webrtc");
+            assert!(
+                matches!(
+                    parse(&text),
+                    Err(Error::InvalidValue {
+                        element: "rate",
+                        property: "fps",
+                        ..
+                    })
+                ),
+                "{fps}"
+            );
+        }
+        let description = parse("testsrc ! rate 7.5 ! vp8enc ! webrtc").unwrap();
+        assert!(matches!(description.elements[1], Spec::Rate(fps) if fps == 7.5));
+    }
+
+    #[test]
+    fn toml_like_text() {
+        let toml = r#"
+            [[elements]]
+            type = "testsrc"
+            pattern = "box"
+            size = "640x360"
+            fps = 25
+
+            [[elements]]
+            type = "overlay"
+            fields = ["time", "frame"]
+            label = "a ! b"
+
+            [[elements]]
+            type = "vp8enc"
+            bitrate = 1500
+            psnr = true
+
+            [[elements]]
+            type = "ivf"
+            file = "out.ivf"
+        "#;
+        let text = r#"testsrc box size=640x360 fps=25 ! overlay time,frame label="a ! b"
+            ! vp8enc bitrate=1500 psnr=true ! ivf out.ivf"#;
+        assert_eq!(
+            format!("{:?}", Description::from_toml(toml).unwrap()),
+            format!("{:?}", parse(text).unwrap())
+        );
+    }
+
+    #[test]
+    fn invalid_toml() {
+        assert!(matches!(Description::from_toml(""), Err(Error::Empty)));
+        assert!(matches!(
+            Description::from_toml("elements = 1"),
+            Err(Error::Empty)
+        ));
+        assert!(matches!(
+            Description::from_toml("[[elements]]\nname = \"FaceTime\""),
+            Err(Error::InvalidToml { index: 1, .. })
+        ));
+        assert!(matches!(
+            Description::from_toml("[[elements]]\ntype = \"testsrc\"\nsize = { width = 1 }"),
+            Err(Error::InvalidToml { index: 1, .. })
+        ));
+        assert!(matches!(
+            Description::from_toml("[[elements]\n"),
+            Err(Error::Toml(_))
+        ));
+    }
+}
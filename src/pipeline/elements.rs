@@ -0,0 +1,1086 @@
+use std::{
+    io::SeekFrom,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::*,
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt, BufWriter},
+    sync::{broadcast, mpsc, watch},
+};
+use tokio_stream::{wrappers::WatchStream, StreamExt};
+
+use super::{Element, EncodedFrame, Frames, Input, Output, Packets, Run};
+use crate::{
+    codec, convert, frame, overlay, rate, scale,
+    source::{self, VideoSource},
+    transform, webrtc,
+};
+
+/// Frames of a video source, started with the format negotiated for the preferences.
+pub struct Source {
+    source: Box<dyn VideoSource>,
+    preferences: source::Preferences,
+}
+
+impl Source {
+    pub fn new(source: Box<dyn VideoSource>, preferences: source::Preferences) -> Self {
+        Self {
+            source,
+            preferences,
+        }
+    }
+}
+
+impl Element for Source {
+    type Input = ();
+    type Output = Frames;
+
+    fn name(&self) -> &'static str {
+        "run_source"
+    }
+
+    fn run(self, _: (), output: Output<Self>, exit: broadcast::Receiver<()>) -> Run {
+        Box::pin(run_source(exit, self.source, self.preferences, output))
+    }
+}
+
+async fn run_source(
+    exit: broadcast::Receiver<()>,
+    mut source: Box<dyn VideoSource>,
+    preferences: source::Preferences,
+    frames_tx: frame::SenderSharedFrame,
+) -> anyhow::Result<()> {
+    log::debug!("run_source: Using source {}.", source.name());
+    let formats = source.formats();
+    let format = source::negotiate(&formats, &preferences).map(|negotiated| {
+        log::info!("run_source: Chose {}.", negotiated);
+        negotiated.format
+    });
+    if format.is_none() && !formats.is_empty() {
+        log::warn!("run_source: No format of the source is taken, so it picks one itself.");
+    }
+    source.set_preferred_format(format);
+
+    source.start()?;
+    let mut frames = source.frames();
+    let mut first_frame = true;
+
+    loop {
+        if !exit.is_empty() {
+            break;
+        }
+
+        if let Some(frame) = frames.next().await {
+            let Some(frame) = frame else { continue };
+
+            if first_frame {
+                first_frame = false;
+                log::debug!(
+                    "run_source: Started receiving source frames. {:?}",
+                    frame.format()
+                );
+            }
+
+            match frames_tx.send(Some(frame)) {
+                Ok(_) => log::trace!("run_source: send frame"),
+                Err(_) => {
+                    log::debug!("run_source: No source frame receiver. End.");
+                    break;
+                }
+            }
+        } else {
+            log::debug!("run_source: Source frames ended. End.");
+            break;
+        }
+    }
+
+    source.stop();
+    Ok(())
+}
+
+/// Transforms the frames as the transform currently is, so changes apply from the next frame on.
+/// Frames which can not be transformed pass as they are.
+pub struct TransformFrames {
+    transform: watch::Receiver<transform::Transform>,
+}
+
+impl TransformFrames {
+    pub fn new(transform: watch::Receiver<transform::Transform>) -> Self {
+        Self { transform }
+    }
+}
+
+impl Element for TransformFrames {
+    type Input = Frames;
+    type Output = Frames;
+
+    fn name(&self) -> &'static str {
+        "transform_frames"
+    }
+
+    fn run(self, input: Input<Self>, output: Output<Self>, _: broadcast::Receiver<()>) -> Run {
+        Box::pin(transform_frames(input, output, self.transform))
+    }
+}
+
+async fn transform_frames(
+    frame: frame::ReceiverSharedFrame,
+    transformed_frames_tx: frame::SenderSharedFrame,
+    transform: watch::Receiver<transform::Transform>,
+) -> anyhow::Result<()> {
+    let mut frames = WatchStream::new(frame);
+    let pool = frame::BufferPool::default();
+    let mut warned = false;
+
+    while let Some(frame) = frames.next().await {
+        let Some(frame) = frame else { continue };
+
+        let transform = *transform.borrow();
+        let transformed: frame::SharedFrame = if transform.is_identity() {
+            frame
+        } else {
+            match transform.apply(&pool, &*frame) {
+                Ok(transformed) => Arc::new(transformed),
+                Err(err) => {
+                    if !warned {
+                        warned = true;
+                        log::warn!(
+                            "transform_frames: Pass {} untransformed. ({})",
+                            frame.format(),
+                            err
+                        );
+                    }
+                    frame
+                }
+            }
+        };
+
+        if transformed_frames_tx.send(Some(transformed)).is_err() {
+            log::debug!("transform_frames: No transformed frame receiver. End.");
+            return Ok(());
+        }
+    }
+
+    log::debug!("transform_frames: End.");
+    Ok(())
+}
+
+/// Sends the frames at the frame rate as it currently is, or every frame without frame rate.
+/// When no frame comes in time for the next slot, the last frame gets sent again.
+pub struct LimitFrames {
+    frame_rate: watch::Receiver<Option<f64>>,
+    limiter: rate::RateLimiter,
+}
+
+impl LimitFrames {
+    pub fn new(frame_rate: watch::Receiver<Option<f64>>, limiter: rate::RateLimiter) -> Self {
+        Self {
+            frame_rate,
+            limiter,
+        }
+    }
+}
+
+impl Element for LimitFrames {
+    type Input = Frames;
+    type Output = Frames;
+
+    fn name(&self) -> &'static str {
+        "limit_frames"
+    }
+
+    fn run(self, input: Input<Self>, output: Output<Self>, _: broadcast::Receiver<()>) -> Run {
+        Box::pin(limit_frames(input, output, self.frame_rate, self.limiter))
+    }
+}
+
+async fn limit_frames(
+    frame: frame::ReceiverSharedFrame,
+    limited_frames_tx: frame::SenderSharedFrame,
+    mut frame_rate: watch::Receiver<Option<f64>>,
+    mut limiter: rate::RateLimiter,
+) -> anyhow::Result<()> {
+    let mut frames = WatchStream::new(frame);
+    let mut last_frame: Option<frame::SharedFrame> = None;
+    // when the last frame of the source was received, to know when a capture time is due
+    let mut received = Instant::now();
+
+    loop {
+        limiter.set_fps(*frame_rate.borrow_and_update());
+        let due = limiter
+            .due()
+            .zip(last_frame.as_ref())
+            .map(|(due, frame)| received + due.saturating_sub(frame.timestamp()));
+
+        let limited: frame::SharedFrame = tokio::select! {
+            frame = frames.next() => {
+                let Some(frame) = frame else { break };
+                let Some(frame) = frame else { continue };
+                received = Instant::now();
+                last_frame = Some(frame.clone());
+                if !limiter.admit(frame.timestamp()) {
+                    continue;
+                }
+                frame
+            }
+            _ = tokio::time::sleep_until(due.unwrap_or(received).into()), if due.is_some() => {
+                match (&last_frame, limiter.duplicate()) {
+                    (Some(frame), Some(timestamp)) => Arc::new(rate::Duplicate::new(frame.clone(), timestamp)),
+                    _ => continue,
+                }
+            }
+            Ok(()) = frame_rate.changed() => continue,
+        };
+
+        if limited_frames_tx.send(Some(limited)).is_err() {
+            log::debug!("limit_frames: No limited frame receiver. End.");
+            return Ok(());
+        }
+    }
+
+    let counts = limiter.counts();
+    log::debug!(
+        "limit_frames: Dropped {} and duplicated {} frames. End.",
+        counts.dropped(),
+        counts.duplicated()
+    );
+    Ok(())
+}
+
+/// Scales the frames for the encoder, while the preview gets them as they are.
+/// Frames which can not be scaled pass unscaled.
+pub struct ScaleFrames {
+    scaler: scale::Scaler,
+}
+
+impl ScaleFrames {
+    pub fn new(scaler: scale::Scaler) -> Self {
+        Self { scaler }
+    }
+}
+
+impl Element for ScaleFrames {
+    type Input = Frames;
+    type Output = Frames;
+
+    fn name(&self) -> &'static str {
+        "scale_frames"
+    }
+
+    fn run(self, input: Input<Self>, output: Output<Self>, _: broadcast::Receiver<()>) -> Run {
+        Box::pin(scale_frames(input, output, self.scaler))
+    }
+}
+
+async fn scale_frames(
+    frame: frame::ReceiverSharedFrame,
+    scaled_frames_tx: frame::SenderSharedFrame,
+    mut scaler: scale::Scaler,
+) -> anyhow::Result<()> {
+    let mut frames = WatchStream::new(frame);
+    let mut warned = false;
+
+    while let Some(frame) = frames.next().await {
+        let Some(frame) = frame else { continue };
+
+        let format = frame.format();
+        let size = (format.width as u32, format.height as u32);
+        let scaled: frame::SharedFrame = if scaler.target_size(&format) == size {
+            frame
+        } else {
+            match scaler.scale(&*frame) {
+                Ok(scaled) => Arc::new(scaled),
+                Err(err) => {
+                    if !warned {
+                        warned = true;
+                        log::warn!("scale_frames: Pass {} unscaled. ({})", format, err);
+                    }
+                    frame
+                }
+            }
+        };
+
+        if scaled_frames_tx.send(Some(scaled)).is_err() {
+            log::debug!("scale_frames: No scaled frame receiver. End.");
+            return Ok(());
+        }
+    }
+
+    log::debug!("scale_frames: End.");
+    Ok(())
+}
+
+/// Burns the overlay into the frames for the encoder, with the format of the source frames as captured.
+/// Frames which can not get the overlay pass without it.
+pub struct BurnInFrames {
+    overlay: overlay::Overlay,
+    source_frame: frame::ReceiverSharedFrame,
+}
+
+impl BurnInFrames {
+    pub fn new(overlay: overlay::Overlay, source_frame: frame::ReceiverSharedFrame) -> Self {
+        Self {
+            overlay,
+            source_frame,
+        }
+    }
+}
+
+impl Element for BurnInFrames {
+    type Input = Frames;
+    type Output = Frames;
+
+    fn name(&self) -> &'static str {
+        "burn_in_frames"
+    }
+
+    fn run(self, input: Input<Self>, output: Output<Self>, _: broadcast::Receiver<()>) -> Run {
+        Box::pin(burn_in_frames(
+            input,
+            self.source_frame,
+            output,
+            self.overlay,
+        ))
+    }
+}
+
+async fn burn_in_frames(
+    frame: frame::ReceiverSharedFrame,
+    source_frame: frame::ReceiverSharedFrame,
+    burned_frames_tx: frame::SenderSharedFrame,
+    mut overlay: overlay::Overlay,
+) -> anyhow::Result<()> {
+    let mut frames = WatchStream::new(frame);
+    let mut warned = false;
+
+    while let Some(frame) = frames.next().await {
+        let Some(frame) = frame else { continue };
+
+        let capture_format = source_frame.borrow().as_ref().map(|frame| frame.format());
+        let burned: frame::SharedFrame = match overlay.burn_in(&*frame, capture_format.as_ref()) {
+            Ok(burned) => Arc::new(burned),
+            Err(err) => {
+                if !warned {
+                    warned = true;
+                    log::warn!(
+                        "burn_in_frames: Pass {} without overlay. ({})",
+                        frame.format(),
+                        err
+                    );
+                }
+                frame
+            }
+        };
+
+        if burned_frames_tx.send(Some(burned)).is_err() {
+            log::debug!("burn_in_frames: No burned frame receiver. End.");
+            return Ok(());
+        }
+    }
+
+    log::debug!("burn_in_frames: End.");
+    Ok(())
+}
+
+/// Encodes the frames with VP8 or VP9, converting those the encoder does not take as they are,
+/// and forces a key frame when the picture loss indicator gets set.
+/// The bitrate follows the channel from the next frame on, without a new encoder or key frame.
+pub struct EncodeFrames {
+    picture_loss_indicator: Arc<AtomicBool>,
+    bitrate: watch::Receiver<u32>,
+    tuning: Tuning,
+    psnr: bool,
+    pass: Pass,
+}
+
+/// The codec to encode with and how, which stays the same for all frames unlike their size and bitrate.
+#[derive(Clone, Copy)]
+enum Tuning {
+    Vp8(codec::Vp8Tuning),
+    Vp9(codec::Vp9Controls),
+}
+
+/// Which pass of a two-pass encoding with VP8, otherwise the only one.
+/// Both passes need one encoder for all frames, so the frames can not change in a way the encoder does not take.
+enum Pass {
+    One,
+    /// Stores the statistics in the file at the end, instead of putting out frames.
+    First(PathBuf),
+    Second(codec::FirstPassStats),
+}
+
+impl EncodeFrames {
+    /// In kbit/s.
+    pub const DEFAULT_BITRATE: u32 = 5000;
+
+    /// Encodes with VP8 at the bitrate as it currently is, in kbit/s.
+    pub fn new(picture_loss_indicator: Arc<AtomicBool>, bitrate: watch::Receiver<u32>) -> Self {
+        Self {
+            picture_loss_indicator,
+            bitrate,
+            tuning: Tuning::Vp8(codec::Vp8Tuning::default()),
+            psnr: false,
+            pass: Pass::One,
+        }
+    }
+
+    /// Measures the PSNR of every frame, which the log shows with its average at the end.
+    pub fn psnr(self, psnr: bool) -> Self {
+        Self { psnr, ..self }
+    }
+
+    /// Encodes with VP8 tuned like this, like by a [preset](codec::Vp8Preset).
+    pub fn vp8(self, tuning: codec::Vp8Tuning) -> Self {
+        Self {
+            tuning: Tuning::Vp8(tuning),
+            ..self
+        }
+    }
+
+    /// Encodes with VP9 instead, set up with the controls.
+    pub fn vp9(self, controls: codec::Vp9Controls) -> Self {
+        Self {
+            tuning: Tuning::Vp9(controls),
+            ..self
+        }
+    }
+
+    /// Encodes the [first pass](codec::Vp8Encoder::first_pass) of VP8, which puts out no frames,
+    /// and stores the statistics in the file at the end.
+    pub fn first_pass(self, stats: PathBuf) -> Self {
+        Self {
+            pass: Pass::First(stats),
+            ..self
+        }
+    }
+
+    /// Encodes the [second pass](codec::Vp8Encoder::second_pass) of VP8 over the same frames as the first.
+    pub fn second_pass(self, stats: codec::FirstPassStats) -> Self {
+        Self {
+            pass: Pass::Second(stats),
+            ..self
+        }
+    }
+
+    pub fn codec(&self) -> codec::Codec {
+        match self.tuning {
+            Tuning::Vp8(_) => codec::Codec::Vp8,
+            Tuning::Vp9(_) => codec::Codec::Vp9,
+        }
+    }
+}
+
+impl Element for EncodeFrames {
+    type Input = Frames;
+    type Output = Packets;
+
+    fn name(&self) -> &'static str {
+        "encode_frames"
+    }
+
+    fn run(self, input: Input<Self>, output: Output<Self>, _: broadcast::Receiver<()>) -> Run {
+        Box::pin(encode_frames(
+            input,
+            output,
+            self.picture_loss_indicator,
+            self.bitrate,
+            self.tuning,
+            self.psnr,
+            self.pass,
+        ))
+    }
+}
+
+async fn encode_frames(
+    frame: frame::ReceiverSharedFrame,
+    packets: mpsc::Sender<EncodedFrame>,
+    picture_loss_indicator: Arc<AtomicBool>,
+    mut bitrate: watch::Receiver<u32>,
+    tuning: Tuning,
+    psnr: bool,
+    pass: Pass,
+) -> anyhow::Result<()> {
+    let mut metrics = codec::EncoderMetrics::default();
+    let mut start_time = None;
+    let mut last_pts = None;
+    let mut encoder = None;
+    let mut frames = WatchStream::new(frame);
+    // for every format and colorimetry the encoder doesn't take as it is
+    let mut converter =
+        convert::Converter::new(convert::PixelFormat::I420, codec::Vp8Encoder::COLORIMETRY);
+
+    while let Some(frame) = frames.next().await {
+        let Some(frame) = frame else { continue };
+        log::trace!("encode_frames: recv frame");
+
+        let format = frame.format();
+        // VP9 signals the matrix and the range of every frame, VP8 has only BT.601 in video range
+        let colorimetry = match tuning {
+            Tuning::Vp8(_) => codec::Vp8Encoder::COLORIMETRY,
+            Tuning::Vp9(_) => frame.colorimetry(),
+        };
+        converter.set_colorimetry(colorimetry);
+        let encodable = frame.colorimetry().same_encoding(&colorimetry);
+        let converted;
+        let (frame, image_format): (&dyn frame::Frame, _) =
+            match codec::ImageFormat::from_fourcc(&format.pixel_format).filter(|_| encodable) {
+                Some(image_format) => (&*frame, image_format),
+                None => match converter.convert(&*frame) {
+                    Ok(frame) => {
+                        converted = frame;
+                        (&converted, codec::ImageFormat::I420)
+                    }
+                    Err(err) => {
+                        log::warn!(
+                            "encode_frames: Can not encode {}. Skip frame. ({})",
+                            format,
+                            err
+                        );
+                        continue;
+                    }
+                },
+            };
+        if let Ok(true) = bitrate.has_changed() {
+            log::debug!("encode_frames: Bitrate {} kbit/s.", *bitrate.borrow());
+        }
+        let bitrate = *bitrate.borrow_and_update();
+        if let Some(mut replaced) =
+            reconfigure_encoder(&mut encoder, &format, bitrate, tuning, psnr, &pass)?
+        {
+            // the frames the old encoder held back to look ahead come before the key frame of the new one
+            let frames = flush_frames(&mut replaced, &mut metrics)?;
+            if !send_frames(&packets, frames).await {
+                return Ok(());
+            }
+        }
+        let encoder = encoder.as_mut().expect("reconfigured");
+        // pts and duration in milliseconds, the timebase of the encoder
+        let timestamp = frame.timestamp();
+        let start_time = *start_time.get_or_insert(timestamp);
+        let mut pts = timestamp.saturating_sub(start_time).as_millis() as i64;
+        if let Some(last_pts) = last_pts.filter(|last_pts| pts <= *last_pts) {
+            log::debug!(
+                "encode_frames: Frame at {}ms is not after {}ms.",
+                pts,
+                last_pts
+            );
+            pts = last_pts + 1;
+        }
+        // the time to the next frame is unknown yet, at a steady frame rate the time since the last frame is as good
+        let duration = last_pts
+            .map(|last_pts| (pts - last_pts) as u64)
+            .unwrap_or(33);
+        last_pts = Some(pts);
+        let force_keyframe = picture_loss_indicator.load(Ordering::Relaxed);
+
+        let mut encoded_data = {
+            // the image points into the pixels, which stay locked until encoded
+            let pixels = frame.pixels();
+            let colorimetry = frame.colorimetry();
+            encoder.encode(
+                pts,
+                duration,
+                &pixels,
+                image_format,
+                &colorimetry,
+                force_keyframe,
+            )?
+        };
+
+        let frames = copy_frames(&mut encoded_data, &mut metrics);
+        // the encoder measures each frame it puts out
+        if let Some(last) = metrics.last_psnr().filter(|_| !frames.is_empty()) {
+            log::trace!(
+                "encode_frames: PSNR {:.2} dB (Y {:.2}, U {:.2}, V {:.2}).",
+                last.psnr[0],
+                last.psnr[1],
+                last.psnr[2],
+                last.psnr[3]
+            );
+        }
+        if !send_frames(&packets, frames).await {
+            return Ok(());
+        }
+    }
+
+    // the frames held back to look ahead, which would get lost with the encoder
+    if let Some(encoder) = &mut encoder {
+        let frames = flush_frames(encoder, &mut metrics)?;
+        if !send_frames(&packets, frames).await {
+            return Ok(());
+        }
+    }
+
+    if let Pass::First(path) = &pass {
+        let stats = metrics.first_pass_stats().as_bytes();
+        tokio::fs::write(path, stats).await?;
+        log::debug!(
+            "encode_frames: Stored {} bytes of first pass statistics in {}.",
+            stats.len(),
+            path.display()
+        );
+    }
+    if let Some((average, overall)) = metrics.average_psnr().zip(metrics.overall_psnr()) {
+        log::info!(
+            "encode_frames: PSNR average {:.2} dB (Y {:.2}, U {:.2}, V {:.2}), overall {:.2} dB.",
+            average[0],
+            average[1],
+            average[2],
+            average[3],
+            overall[0]
+        );
+    }
+    log::debug!(
+        "encode_frames: Encoded {} frames, {} key frames, {} bytes, {} bytes of statistics. End.",
+        metrics.frames,
+        metrics.keyframes,
+        metrics.bytes,
+        metrics.stats_bytes
+    );
+    Ok(())
+}
+
+/// The [copies](copy_frame) of the frames the encoder made of an image.
+fn copy_frames(
+    encoded_data: &mut codec::EncoderData,
+    metrics: &mut codec::EncoderMetrics,
+) -> Vec<EncodedFrame> {
+    encoded_data
+        .packets()
+        .filter_map(|packet| copy_frame(packet, metrics))
+        .collect()
+}
+
+/// The frames the encoder holds back to look ahead, which would get lost with the encoder.
+fn flush_frames(
+    encoder: &mut Encoder,
+    metrics: &mut codec::EncoderMetrics,
+) -> codec::Result<Vec<EncodedFrame>> {
+    let mut frames = vec![];
+    encoder.flush(|packet| frames.extend(copy_frame(packet, metrics)))?;
+    Ok(frames)
+}
+
+// TODO This copy can be skipped when we check that the packets sender is not full.
+// TODO This copy can be skipped when we control the data buffer by using vpx_codec_set_cx_data_buf.
+/// Copy each frame so we can asynchronously send them one after the other without risking getting an invalidated buffer.
+/// The other packets of the encoder only go into the metrics.
+fn copy_frame(
+    packet: codec::EncoderPacket,
+    metrics: &mut codec::EncoderMetrics,
+) -> Option<EncodedFrame> {
+    metrics.add(&packet);
+    let codec::EncoderPacket::Frame(frame) = packet else {
+        return None;
+    };
+    if frame.keyframe() {
+        log::debug!(
+            "encode_frames: Encoded key frame: {}x{}",
+            frame.width,
+            frame.height
+        )
+    }
+    Some(EncodedFrame {
+        bytes: bytes::Bytes::copy_from_slice(frame.data),
+        keyframe: frame.keyframe(),
+        pts: Duration::from_millis(frame.pts as u64),
+        duration: Duration::from_millis(frame.duration),
+    })
+}
+
+/// False when nobody receives the frames anymore.
+async fn send_frames(packets: &mpsc::Sender<EncodedFrame>, frames: Vec<EncodedFrame>) -> bool {
+    for frame in frames {
+        log::trace!("encode_frames: sending frame");
+        match packets.send(frame).await {
+            Ok(_) => log::trace!("encode_frames: sent frame"),
+            Err(err) => {
+                log::debug!(
+                    "encode_frames: No encoded frame receiver. End encoding frames. {}",
+                    err
+                );
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// The encoder of either codec, which both take images and make frames the same way.
+enum Encoder {
+    Vp8(codec::Vp8Encoder),
+    Vp9(codec::Vp9Encoder),
+}
+
+impl Encoder {
+    fn encode(
+        &mut self,
+        pts: i64,
+        duration: u64,
+        pixels: &frame::Pixels,
+        format: codec::ImageFormat,
+        colorimetry: &frame::Colorimetry,
+        force_keyframe: bool,
+    ) -> codec::Result<codec::EncoderData<'_>> {
+        match self {
+            // the frames are converted to the one colorimetry of VP8
+            Encoder::Vp8(encoder) => {
+                let image = encoder.wrap_image(pixels, format)?;
+                encoder.encode(pts, duration, image, force_keyframe)
+            }
+            Encoder::Vp9(encoder) => {
+                let image = encoder.wrap_image(pixels, format, colorimetry)?;
+                encoder.encode(pts, duration, image, force_keyframe)
+            }
+        }
+    }
+
+    fn flush(&mut self, each: impl FnMut(codec::EncoderPacket)) -> codec::Result<()> {
+        match self {
+            Encoder::Vp8(encoder) => encoder.flush(each),
+            Encoder::Vp9(encoder) => encoder.flush(each),
+        }
+    }
+}
+
+/// Keeps the encoder when it takes the config as it is running, like another bitrate or a smaller size,
+/// and creates a new one otherwise, which starts with a key frame.
+/// Returns the replaced encoder, to [flush](flush_frames) it.
+fn reconfigure_encoder(
+    encoder: &mut Option<Encoder>,
+    format: &frame::SampleFormat,
+    bitrate: u32,
+    tuning: Tuning,
+    psnr: bool,
+    pass: &Pass,
+) -> codec::Result<Option<Encoder>> {
+    let (width, height) = (format.width as u32, format.height as u32);
+    let timebase = [1, 1000];
+
+    match tuning {
+        Tuning::Vp8(tuning) => {
+            let config = codec::Vp8Config {
+                psnr,
+                ..codec::Vp8Config::new(width, height, timebase, bitrate, tuning)?
+            };
+            if let Some(Encoder::Vp8(current)) = encoder {
+                if current.config() == &config || current.update_config(&config).is_ok() {
+                    return Ok(None);
+                }
+                // a new encoder starts its pass over, so the statistics would not match the frames
+                if !matches!(pass, Pass::One) {
+                    return Err(codec::Error::InvalidParam(
+                        "two passes need one encoder for all frames",
+                    ));
+                }
+            }
+            let created = match pass {
+                Pass::One => codec::Vp8Encoder::new(&config)?,
+                Pass::First(_) => codec::Vp8Encoder::first_pass(&config)?,
+                Pass::Second(stats) => codec::Vp8Encoder::second_pass(&config, stats.clone())?,
+            };
+            Ok(encoder.replace(Encoder::Vp8(created)))
+        }
+        Tuning::Vp9(controls) => {
+            let config = codec::Vp9Config {
+                psnr,
+                ..codec::Vp9Config::new(width, height, timebase, bitrate, controls)?
+            };
+            if let Some(Encoder::Vp9(current)) = encoder {
+                if current.config() == &config || current.update_config(&config).is_ok() {
+                    return Ok(None);
+                }
+            }
+            Ok(encoder.replace(Encoder::Vp9(codec::Vp9Encoder::new(&config)?)))
+        }
+    }
+}
+
+/// Decodes VP8 frames into I420 frames, like for looking at what the encoder makes of the frames.
+/// Corrupt frames get skipped.
+pub struct DecodeFrames;
+
+impl Element for DecodeFrames {
+    type Input = Packets;
+    type Output = Frames;
+
+    fn name(&self) -> &'static str {
+        "decode_frames"
+    }
+
+    fn run(self, input: Input<Self>, output: Output<Self>, _: broadcast::Receiver<()>) -> Run {
+        Box::pin(decode_frames(input, output))
+    }
+}
+
+async fn decode_frames(
+    mut packets: mpsc::Receiver<EncodedFrame>,
+    decoded_frames_tx: frame::SenderSharedFrame,
+) -> anyhow::Result<()> {
+    let mut decoder = codec::Vp8Decoder::new()?;
+    let pool = frame::BufferPool::default();
+
+    while let Some(packet) = packets.recv().await {
+        // the images point into the decoder, so they get copied before the next frame gets decoded
+        let decoded = match decoder.decode(&packet.bytes, packet.pts) {
+            Ok(mut data) => data
+                .images()
+                .filter_map(|image| frame::VideoFrame::copy_from(&pool, &image))
+                .last(),
+            Err(err) => {
+                log::warn!(
+                    "decode_frames: Can not decode frame at {:?}. Skip frame. ({})",
+                    packet.pts,
+                    err
+                );
+                continue;
+            }
+        };
+        let Some(decoded) = decoded else { continue };
+
+        if decoded_frames_tx.send(Some(Arc::new(decoded))).is_err() {
+            log::debug!("decode_frames: No decoded frame receiver. End.");
+            return Ok(());
+        }
+    }
+
+    log::debug!("decode_frames: End.");
+    Ok(())
+}
+
+/// Sends the encoded frames to the browser test app, once an offer came through the exchange,
+/// and sets the picture loss indicator on picture loss indications of the browser.
+pub struct WebRtcTestapp {
+    exchange_rx: mpsc::Receiver<webrtc::OfferAnswerExchange>,
+    picture_loss_indicator: Arc<AtomicBool>,
+    codec: codec::Codec,
+}
+
+impl WebRtcTestapp {
+    /// The track sends frames of the codec, which the browser needs to support.
+    pub fn new(
+        exchange_rx: mpsc::Receiver<webrtc::OfferAnswerExchange>,
+        picture_loss_indicator: Arc<AtomicBool>,
+        codec: codec::Codec,
+    ) -> Self {
+        Self {
+            exchange_rx,
+            picture_loss_indicator,
+            codec,
+        }
+    }
+}
+
+impl Element for WebRtcTestapp {
+    type Input = Packets;
+    type Output = ();
+
+    fn name(&self) -> &'static str {
+        "webrtc_testapp"
+    }
+
+    fn run(self, input: Input<Self>, _: (), _: broadcast::Receiver<()>) -> Run {
+        Box::pin(async move {
+            webrtc::webrtc_testapp(
+                self.exchange_rx,
+                input,
+                self.picture_loss_indicator,
+                self.codec,
+            )
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+/// Writes the encoded frames into an IVF file, the simplest container of VP8 and VP9, which players like ffplay play.
+pub struct WriteIvf {
+    path: PathBuf,
+    codec: codec::Codec,
+}
+
+impl WriteIvf {
+    pub fn new(path: PathBuf, codec: codec::Codec) -> Self {
+        Self { path, codec }
+    }
+}
+
+impl Element for WriteIvf {
+    type Input = Packets;
+    type Output = ();
+
+    fn name(&self) -> &'static str {
+        "write_ivf"
+    }
+
+    fn run(self, input: Input<Self>, _: (), _: broadcast::Receiver<()>) -> Run {
+        Box::pin(write_ivf(self.path, self.codec, input))
+    }
+}
+
+/// The header and every frame header of IVF are little endian.
+/// <https://wiki.multimedia.cx/index.php/IVF>
+async fn write_ivf(
+    path: PathBuf,
+    codec: codec::Codec,
+    mut packets: mpsc::Receiver<EncodedFrame>,
+) -> anyhow::Result<()> {
+    let mut file = BufWriter::new(File::create(&path).await?);
+    // the header gets written again at the end, with the size of the first key frame and the number of frames
+    file.write_all(&ivf_header(codec, (0, 0), 0)).await?;
+    let mut size = None;
+    let mut frames = 0;
+
+    while let Some(frame) = packets.recv().await {
+        size = size.or_else(|| match codec {
+            codec::Codec::Vp8 => vp8_key_frame_size(&frame.bytes),
+            codec::Codec::Vp9 => vp9_key_frame_size(&frame.bytes),
+        });
+        // pts in milliseconds, the timebase of the header
+        file.write_all(&(frame.bytes.len() as u32).to_le_bytes())
+            .await?;
+        file.write_all(&(frame.pts.as_millis() as u64).to_le_bytes())
+            .await?;
+        file.write_all(&frame.bytes).await?;
+        frames += 1;
+    }
+
+    file.seek(SeekFrom::Start(0)).await?;
+    file.write_all(&ivf_header(codec, size.unwrap_or_default(), frames))
+        .await?;
+    file.flush().await?;
+    log::debug!(
+        "write_ivf: Wrote {} frames into {}. End.",
+        frames,
+        path.display()
+    );
+    Ok(())
+}
+
+fn ivf_header(codec: codec::Codec, (width, height): (u16, u16), frames: u32) -> [u8; 32] {
+    let mut header = [0; 32];
+    header[0..4].copy_from_slice(b"DKIF");
+    header[6..8].copy_from_slice(&32u16.to_le_bytes());
+    header[8..12].copy_from_slice(match codec {
+        codec::Codec::Vp8 => b"VP80",
+        codec::Codec::Vp9 => b"VP90",
+    });
+    header[12..14].copy_from_slice(&width.to_le_bytes());
+    header[14..16].copy_from_slice(&height.to_le_bytes());
+    // a timebase of 1/1000 s
+    header[16..20].copy_from_slice(&1000u32.to_le_bytes());
+    header[20..24].copy_from_slice(&1u32.to_le_bytes());
+    header[24..28].copy_from_slice(&frames.to_le_bytes());
+    header
+}
+
+/// The size of a VP8 key frame, which follows the start code after the frame tag.
+/// The frame tag of key frames has the lowest bit unset.
+/// <https://datatracker.ietf.org/doc/html/rfc6386#section-9.1>
+fn vp8_key_frame_size(frame: &[u8]) -> Option<(u16, u16)> {
+    if frame.len() < 10 || frame[0] & 1 != 0 || frame[3..6] != [0x9d, 0x01, 0x2a] {
+        return None;
+    }
+    let width = u16::from_le_bytes([frame[6], frame[7]]) & 0x3fff;
+    let height = u16::from_le_bytes([frame[8], frame[9]]) & 0x3fff;
+    Some((width, height))
+}
+
+/// The size of a VP9 key frame, which follows the sync code and the color config in the uncompressed header.
+/// Section 6.2 of <https://www.webmproject.org/vp9/>
+fn vp9_key_frame_size(frame: &[u8]) -> Option<(u16, u16)> {
+    let mut position = 0;
+    let mut bits = |count: usize| {
+        let mut value = 0u32;
+        for _ in 0..count {
+            let byte = frame.get(position / 8)?;
+            value = value << 1 | (byte >> (7 - position % 8) & 1) as u32;
+            position += 1;
+        }
+        Some(value)
+    };
+
+    // the frame marker
+    if bits(2)? != 2 {
+        return None;
+    }
+    let profile = bits(1)? | bits(1)? << 1;
+    if profile == 3 {
+        bits(1)?;
+    }
+    // show existing frame, then the frame type, 0 for key frames
+    if bits(1)? != 0 || bits(1)? != 0 {
+        return None;
+    }
+    // show frame and error resilient mode
+    bits(2)?;
+    if bits(24)? != 0x49_83_42 {
+        return None;
+    }
+    if profile >= 2 {
+        // the bit depth
+        bits(1)?;
+    }
+    // subsampling is only signalled in profile 1 and 3, after the range of YUV or a reserved bit of sRGB
+    let color_space = bits(3)?;
+    let subsampling = profile == 1 || profile == 3;
+    match (color_space, subsampling) {
+        (7, true) => bits(1)?,
+        (7, false) => 0,
+        (_, true) => bits(4)?,
+        (_, false) => bits(1)?,
+    };
+    let width = bits(16)? + 1;
+    let height = bits(16)? + 1;
+    Some((width as u16, height as u16))
+}
+
+/// Writes the tenth frame into a file named by its format, like `camera_frame.NV12.1280.720`,
+/// so it can be replayed with the raw source.
+pub struct WriteFrame;
+
+impl Element for WriteFrame {
+    type Input = Frames;
+    type Output = ();
+
+    fn name(&self) -> &'static str {
+        "write_frame"
+    }
+
+    fn run(self, input: Input<Self>, _: (), _: broadcast::Receiver<()>) -> Run {
+        Box::pin(write_frame(input))
+    }
+}
+
+async fn write_frame(mut frame: frame::ReceiverSharedFrame) -> anyhow::Result<()> {
+    for _ in 0..10 {
+        let _ = frame.changed().await;
+    }
+
+    let (path, data) = {
+        let frame_borrow = frame.borrow();
+        let Some(frame) = frame_borrow.as_ref() else {
+            return Ok(());
+        };
+
+        let format = frame.format();
+        let path = format!(
+            "camera_frame.{}.{}.{}",
+            format.pixel_format.as_str(),
+            format.width,
+            format.height
+        );
+        // without row padding, so it can be replayed with the raw source
+        let data = frame.pixels().to_vec();
+        (path, data)
+    };
+
+    let _ = tokio::fs::write(path, &data).await;
+    Ok(())
+}
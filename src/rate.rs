@@ -0,0 +1,246 @@
+//! Resamples frames to a target frame rate by their capture time, like sending 15 fps of a 30 fps camera
+//! on weak links. Frames coming too often get dropped, and when they come too rarely the last one gets
+//! sent again, so the frames sent are evenly spaced at the target rate.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::frame::{Colorimetry, Frame, Pixels, SampleFormat, SharedFrame};
+
+/// The last frame is not sent again when the source has not sent any frame for this long,
+/// so a stalled source is not hidden.
+const STALL: Duration = Duration::from_secs(1);
+
+/// How many frames a [RateLimiter] dropped and duplicated, readable while it runs.
+#[derive(Debug, Default)]
+pub struct Counts {
+    dropped: AtomicU64,
+    duplicated: AtomicU64,
+}
+
+impl Counts {
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn duplicated(&self) -> u64 {
+        self.duplicated.load(Ordering::Relaxed)
+    }
+}
+
+/// Decides which frames to send at the target frame rate and when to send the last frame again.
+///
+/// The frames get sent in slots of one frame interval. A frame from a quarter interval before its slot
+/// to three quarters after it fills it, earlier frames get dropped. Without a frame by then the last frame
+/// fills the slot again. A later frame, like after a pause of the source, starts the slots over.
+pub struct RateLimiter {
+    fps: Option<f64>,
+    /// The slot of the last frame sent.
+    slot: Option<Duration>,
+    /// The capture time of the last frame of the source.
+    last_frame: Option<Duration>,
+    counts: Arc<Counts>,
+}
+
+impl RateLimiter {
+    /// Without frame rate every frame gets sent as it is.
+    pub fn new(fps: Option<f64>) -> Self {
+        Self {
+            fps,
+            slot: None,
+            last_frame: None,
+            counts: Arc::default(),
+        }
+    }
+
+    pub fn counts(&self) -> Arc<Counts> {
+        self.counts.clone()
+    }
+
+    /// Changes the frame rate from the next slot on.
+    pub fn set_fps(&mut self, fps: Option<f64>) {
+        self.fps = fps;
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        self.fps
+            .filter(|fps| *fps > 0.0 && fps.is_finite())
+            .map(|fps| Duration::from_secs_f64(1.0 / fps))
+    }
+
+    /// Whether to send a frame captured at this time, otherwise it counts as dropped.
+    pub fn admit(&mut self, timestamp: Duration) -> bool {
+        let previous = self.last_frame.replace(timestamp);
+        let (Some(interval), Some(slot)) = (self.interval(), self.slot) else {
+            self.slot = Some(timestamp);
+            return true;
+        };
+        // not against the slot, which can be ahead of the frames still to drop
+        if matches!(previous, Some(previous) if timestamp < previous) {
+            // the source started over, like a looping file
+            self.slot = Some(timestamp);
+            return true;
+        }
+
+        let next = slot + interval;
+        if timestamp + interval / 4 < next {
+            self.counts.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.slot = Some(if timestamp > late(next, interval) {
+            timestamp
+        } else {
+            next
+        });
+        true
+    }
+
+    /// The capture time after which the last frame gets sent again, when no frame came for the next slot by then.
+    pub fn due(&self) -> Option<Duration> {
+        let (interval, slot, last_frame) = (self.interval()?, self.slot?, self.last_frame?);
+        let next = slot + interval;
+        if next > last_frame + STALL {
+            return None;
+        }
+        Some(late(next, interval))
+    }
+
+    /// Fills the next slot with the last frame again and returns its capture time, the time of the slot.
+    pub fn duplicate(&mut self) -> Option<Duration> {
+        self.due()?;
+        let next = self.slot? + self.interval()?;
+        self.slot = Some(next);
+        self.counts.duplicated.fetch_add(1, Ordering::Relaxed);
+        Some(next)
+    }
+}
+
+/// The capture time after which a frame is too late for a slot.
+fn late(slot: Duration, interval: Duration) -> Duration {
+    slot + interval * 3 / 4
+}
+
+/// A frame sent again, with the capture time of the slot it fills.
+pub struct Duplicate {
+    frame: SharedFrame,
+    timestamp: Duration,
+}
+
+impl Duplicate {
+    pub fn new(frame: SharedFrame, timestamp: Duration) -> Self {
+        Self { frame, timestamp }
+    }
+}
+
+impl Frame for Duplicate {
+    fn format(&self) -> SampleFormat {
+        self.frame.format()
+    }
+
+    fn pixels(&self) -> Pixels<'_> {
+        self.frame.pixels()
+    }
+
+    fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    fn colorimetry(&self) -> Colorimetry {
+        self.frame.colorimetry()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds the capture times like the element does, where the last frame gets sent again when its slot
+    /// is due before the next frame comes. Returns the capture times sent in ms.
+    fn limit(limiter: &mut RateLimiter, timestamps: &[Duration]) -> Vec<u128> {
+        let mut sent = vec![];
+        for &timestamp in timestamps {
+            while let Some(due) = limiter.due() {
+                if due >= timestamp {
+                    break;
+                }
+                sent.extend(limiter.duplicate());
+            }
+            if limiter.admit(timestamp) {
+                sent.push(timestamp);
+            }
+        }
+        sent.iter().map(Duration::as_millis).collect()
+    }
+
+    fn frames(fps: u64, indices: impl IntoIterator<Item = u64>) -> Vec<Duration> {
+        indices
+            .into_iter()
+            .map(|index| Duration::from_nanos(index * 1_000_000_000 / fps))
+            .collect()
+    }
+
+    fn counts(limiter: &RateLimiter) -> (u64, u64) {
+        let counts = limiter.counts();
+        (counts.dropped(), counts.duplicated())
+    }
+
+    #[test]
+    fn halves_30_to_15() {
+        let mut limiter = RateLimiter::new(Some(15.0));
+        let sent = limit(&mut limiter, &frames(30, 0..30));
+        let expected: Vec<u128> = (0..15).map(|index| index * 2000 / 30).collect();
+        assert_eq!(sent, expected);
+        assert_eq!(counts(&limiter), (15, 0));
+    }
+
+    #[test]
+    fn sends_one_of_12_from_120_to_10() {
+        let mut limiter = RateLimiter::new(Some(10.0));
+        let sent = limit(&mut limiter, &frames(120, 0..120));
+        // the first frame a quarter interval before each slot fills it
+        let expected: Vec<u128> = [0]
+            .into_iter()
+            .chain((0..10).map(|slot| 75 + slot * 100))
+            .collect();
+        assert_eq!(sent, expected);
+        assert_eq!(counts(&limiter), (109, 0));
+    }
+
+    #[test]
+    fn repeats_the_last_frame_until_the_source_stalls() {
+        let mut limiter = RateLimiter::new(Some(10.0));
+        let timestamps = [frames(20, 0..10), frames(20, 60..65)].concat();
+        let sent = limit(&mut limiter, &timestamps);
+        let expected: Vec<u128> = (0..5)
+            .map(|slot| slot * 100)
+            // up to a second after the frame of 450 ms
+            .chain((5..15).map(|slot| slot * 100))
+            .chain([3000, 3100, 3200])
+            .collect();
+        assert_eq!(sent, expected);
+        assert_eq!(counts(&limiter), (7, 10));
+        assert_eq!(limiter.due(), Some(Duration::from_millis(3375)));
+    }
+
+    #[test]
+    fn starts_over_with_a_looping_source() {
+        let mut limiter = RateLimiter::new(Some(10.0));
+        let timestamps = [frames(20, 0..6), frames(20, 0..6)].concat();
+        let sent = limit(&mut limiter, &timestamps);
+        assert_eq!(sent, [0, 100, 200, 0, 100, 200]);
+        assert_eq!(counts(&limiter), (6, 0));
+    }
+
+    #[test]
+    fn sends_every_frame_without_frame_rate() {
+        let mut limiter = RateLimiter::new(None);
+        assert_eq!(limit(&mut limiter, &frames(30, 0..3)), [0, 33, 66]);
+        assert_eq!(limiter.due(), None);
+        assert_eq!(counts(&limiter), (0, 0));
+    }
+}
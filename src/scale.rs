@@ -0,0 +1,451 @@
+//! Resizes 4:2:0 frames, like camera frames for an encoding smaller than the preview.
+//!
+//! Every plane goes through a separable filter, first along the rows, then along the columns.
+//! The filters are lists of weighted source samples for every target sample, so nearest, bilinear
+//! and box filtering only differ in how these weights are made.
+
+use std::{collections::HashMap, ops::Range, str::FromStr};
+
+use crate::frame::{BufferPool, Frame, Plane, PlaneMut, SampleFormat, VideoFrame};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("can not scale {0}, only 4:2:0 formats")]
+    UnsupportedFormat(String),
+    #[error("the planes of the frame do not match {0}")]
+    InvalidPlanes(SampleFormat),
+}
+
+/// How a [scaler](Scaler) gets a target pixel from the source pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// The closest source pixel. Fast, but aliasing.
+    Nearest,
+    /// Interpolates between the two closest source pixels in each direction.
+    /// Smooth, but aliasing when shrinking to less than half.
+    Bilinear,
+    /// Averages the source pixels the target pixel covers, weighted by how much it covers them.
+    /// Best for downscaling.
+    Box,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "bilinear" => Ok(Self::Bilinear),
+            "box" => Ok(Self::Box),
+            _ => Err(format!("unknown filter {s}, use nearest, bilinear or box")),
+        }
+    }
+}
+
+/// The size a [scaler](Scaler) scales to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    /// Exactly this size, stretching frames of another aspect ratio.
+    Fixed(u32, u32),
+    /// The largest size within this size with the aspect ratio of the frame, but never larger than the frame.
+    Fit(u32, u32),
+}
+
+impl Size {
+    /// The size of a scaled frame, even in both directions for the 4:2:0 chroma subsampling.
+    pub fn of(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = match *self {
+            Size::Fixed(width, height) => (width, height),
+            Size::Fit(max_width, max_height) => {
+                let scale = (max_width as f64 / width as f64)
+                    .min(max_height as f64 / height as f64)
+                    .min(1.0);
+                (
+                    (width as f64 * scale).round() as u32,
+                    (height as f64 * scale).round() as u32,
+                )
+            }
+        };
+        ((width & !1).max(2), (height & !1).max(2))
+    }
+}
+
+/// Scales 4:2:0 frames, like NV12 and I420, to a [size](Size), into frames of the same format
+/// with buffers of its own pool.
+pub struct Scaler {
+    filter: Filter,
+    size: Size,
+    pool: BufferPool,
+    /// The taps by source and target length. They only change with the frame size.
+    taps: HashMap<(usize, usize), Taps>,
+    /// The rows after filtering along them.
+    rows: Vec<u16>,
+    /// A target row while filtering along the columns.
+    sums: Vec<i32>,
+}
+
+impl Scaler {
+    pub fn new(filter: Filter, size: Size) -> Self {
+        Self {
+            filter,
+            size,
+            pool: BufferPool::default(),
+            taps: HashMap::new(),
+            rows: vec![],
+            sums: vec![],
+        }
+    }
+
+    /// The size frames of this format get scaled to.
+    pub fn target_size(&self, format: &SampleFormat) -> (u32, u32) {
+        self.size.of(format.width as u32, format.height as u32)
+    }
+
+    pub fn scale(&mut self, frame: &dyn Frame) -> Result<VideoFrame> {
+        let format = frame.format();
+        let layout = format
+            .plane_layout()
+            .filter(|layout| {
+                layout.len() > 1 && layout[1..].iter().all(|plane| plane.subsampling == (2, 2))
+            })
+            .ok_or_else(|| Error::UnsupportedFormat(format.pixel_format.clone()))?;
+        let pixels = frame.pixels();
+        if !format.matches_planes(&pixels.planes) {
+            return Err(Error::InvalidPlanes(format));
+        }
+
+        let (width, height) = self.target_size(&format);
+        let target_format = SampleFormat {
+            width: width as i32,
+            height: height as i32,
+            pixel_format: format.pixel_format.clone(),
+        };
+        let mut scaled = VideoFrame::new(&self.pool, target_format, frame.timestamp())
+            .expect("4:2:0 formats have a plane layout");
+        scaled.set_colorimetry(frame.colorimetry());
+
+        let Self {
+            filter,
+            taps,
+            rows,
+            sums,
+            ..
+        } = self;
+        let planes = pixels.planes.iter().zip(layout).zip(scaled.planes_mut());
+        for ((source, layout), mut target) in planes {
+            // only the pixels of the format, not any extra width or height of the buffer
+            let (source_width, source_height) =
+                layout.size(format.width as usize, format.height as usize);
+            let horizontal = (source_width, target.width);
+            let vertical = (source_height, target.height);
+            for (source, target) in [horizontal, vertical] {
+                taps.entry((source, target))
+                    .or_insert_with(|| Taps::new(*filter, source, target));
+            }
+            let source = Plane::new(
+                source.data,
+                source.stride,
+                source_width,
+                source_height,
+                source.bytes_per_pixel,
+            );
+            scale_plane(
+                &source,
+                &mut target,
+                &taps[&horizontal],
+                &taps[&vertical],
+                rows,
+                sums,
+            );
+        }
+        Ok(scaled)
+    }
+}
+
+/// Fractional bits of the fixed point weights.
+const FRACTION_BITS: i32 = 14;
+const ONE: i32 = 1 << FRACTION_BITS;
+/// Bits more than 8 bits the rows keep after filtering along them.
+const ROW_BITS: i32 = 6;
+
+/// The weighted source samples of every target sample along rows or columns.
+struct Taps {
+    targets: Vec<Tap>,
+    /// The weights of all targets, each adding up to [ONE].
+    weights: Vec<i32>,
+}
+
+/// Source samples from `first` on, one per weight.
+struct Tap {
+    first: usize,
+    weights: Range<usize>,
+}
+
+impl Taps {
+    fn new(filter: Filter, source: usize, target: usize) -> Self {
+        let mut taps = Self {
+            targets: vec![],
+            weights: vec![],
+        };
+        for index in 0..target {
+            match filter {
+                Filter::Nearest => {
+                    let nearest = ((2 * index + 1) * source / (2 * target)).min(source - 1);
+                    taps.push(nearest, &[1.0]);
+                }
+                Filter::Bilinear => {
+                    // the centers of samples line up, not their edges
+                    let center = (index as f64 + 0.5) * source as f64 / target as f64 - 0.5;
+                    let center = center.clamp(0.0, (source - 1) as f64);
+                    let first = center.floor() as usize;
+                    let fraction = center - first as f64;
+                    if first + 1 < source {
+                        taps.push(first, &[1.0 - fraction, fraction]);
+                    } else {
+                        taps.push(first, &[1.0]);
+                    }
+                }
+                Filter::Box => {
+                    // in units of 1 / target source samples, the target sample covers start..end
+                    let (start, end) = (index * source, (index + 1) * source);
+                    let (first, last) = (start / target, (end - 1) / target);
+                    let weights: Vec<_> = (first..=last)
+                        .map(|sample| {
+                            let covered =
+                                end.min((sample + 1) * target) - start.max(sample * target);
+                            covered as f64 / source as f64
+                        })
+                        .collect();
+                    taps.push(first, &weights);
+                }
+            }
+        }
+        taps
+    }
+
+    fn push(&mut self, first: usize, weights: &[f64]) {
+        let start = self.weights.len();
+        self.weights.extend(
+            weights
+                .iter()
+                .map(|weight| (weight * ONE as f64).round() as i32),
+        );
+        // rounding must not change the brightness
+        let weights = &mut self.weights[start..];
+        let error = ONE - weights.iter().sum::<i32>();
+        if let Some(largest) = weights.iter_mut().max_by_key(|weight| **weight) {
+            *largest += error;
+        }
+        self.targets.push(Tap {
+            first,
+            weights: start..self.weights.len(),
+        });
+    }
+}
+
+/// Filters the source plane along its rows into `rows` and then along the columns through `sums` into the target plane.
+/// Interleaved channels, like U and V of NV12, get filtered each on its own.
+fn scale_plane(
+    source: &Plane,
+    target: &mut PlaneMut,
+    horizontal: &Taps,
+    vertical: &Taps,
+    rows: &mut Vec<u16>,
+    sums: &mut Vec<i32>,
+) {
+    let channels = source.bytes_per_pixel;
+    let row_length = target.width * channels;
+    rows.resize(row_length * source.height, 0);
+
+    for (y, row) in rows.chunks_exact_mut(row_length).enumerate() {
+        let source_row = source.row(y);
+        for (samples, tap) in row.chunks_exact_mut(channels).zip(&horizontal.targets) {
+            let weights = &horizontal.weights[tap.weights.clone()];
+            let source_samples = &source_row[tap.first * channels..][..weights.len() * channels];
+            for (channel, sample) in samples.iter_mut().enumerate() {
+                let mut sum = 0;
+                for (weight, pixel) in weights.iter().zip(source_samples.chunks_exact(channels)) {
+                    sum += weight * pixel[channel] as i32;
+                }
+                let shift = FRACTION_BITS - ROW_BITS;
+                *sample = ((sum + (1 << (shift - 1))) >> shift) as u16;
+            }
+        }
+    }
+
+    sums.resize(row_length, 0);
+    for (y, tap) in vertical.targets.iter().enumerate() {
+        sums.fill(0);
+        let weights = &vertical.weights[tap.weights.clone()];
+        for (index, weight) in weights.iter().enumerate() {
+            let row = &rows[(tap.first + index) * row_length..][..row_length];
+            for (sum, sample) in sums.iter_mut().zip(row) {
+                *sum += weight * *sample as i32;
+            }
+        }
+        let shift = FRACTION_BITS + ROW_BITS;
+        for (value, sum) in target.row_mut(y).iter_mut().zip(sums.iter()) {
+            // the weights add up to one, so the sum stays within 8 bits
+            *value = ((sum + (1 << (shift - 1))) >> shift) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn no_allocations_after_warm_up() {
+        let pool = BufferPool::new(2);
+        let format = SampleFormat {
+            width: 64,
+            height: 48,
+            pixel_format: "NV12".to_string(),
+        };
+        let mut scaler = Scaler::new(Filter::Box, Size::Fixed(40, 30));
+
+        let frame = VideoFrame::new(&pool, format.clone(), Duration::ZERO).unwrap();
+        let scaled = scaler.scale(&frame).unwrap();
+        assert_eq!((40, 30), (scaled.format().width, scaled.format().height));
+        drop((frame, scaled));
+        let allocations = (pool.allocations(), scaler.pool.allocations());
+        let capacities = (scaler.rows.capacity(), scaler.sums.capacity());
+
+        for index in 1..10 {
+            let frame =
+                VideoFrame::new(&pool, format.clone(), Duration::from_millis(index)).unwrap();
+            scaler.scale(&frame).unwrap();
+        }
+        assert_eq!(allocations, (pool.allocations(), scaler.pool.allocations()));
+        assert_eq!(capacities, (scaler.rows.capacity(), scaler.sums.capacity()));
+    }
+
+    /// A frame with every sample given by its plane, its index in the row and its row.
+    fn frame(
+        pixel_format: &str,
+        width: i32,
+        height: i32,
+        sample: impl Fn(usize, usize, usize) -> u8,
+    ) -> VideoFrame {
+        let format = SampleFormat {
+            width,
+            height,
+            pixel_format: pixel_format.to_string(),
+        };
+        let mut frame = VideoFrame::new(&BufferPool::default(), format, Duration::ZERO).unwrap();
+        for (index, mut plane) in frame.planes_mut().enumerate() {
+            for y in 0..plane.height {
+                for (x, value) in plane.row_mut(y).iter_mut().enumerate() {
+                    *value = sample(index, x, y);
+                }
+            }
+        }
+        frame
+    }
+
+    fn scale(frame: &VideoFrame, filter: Filter, width: u32, height: u32) -> Vec<Vec<Vec<u8>>> {
+        let scaled = Scaler::new(filter, Size::Fixed(width, height))
+            .scale(frame)
+            .unwrap();
+        let planes = scaled
+            .pixels()
+            .planes
+            .iter()
+            .map(|plane| plane.rows().map(<[u8]>::to_vec).collect())
+            .collect();
+        planes
+    }
+
+    #[test]
+    fn nearest_picks_the_centered_samples() {
+        let frame = frame("I420", 8, 2, |plane, x, y| (plane * 100 + x * 10 + y) as u8);
+        let planes = scale(&frame, Filter::Nearest, 4, 2);
+        assert_eq!(planes[0], [[10, 30, 50, 70], [11, 31, 51, 71]]);
+        assert_eq!(planes[1], [[110, 130]]);
+        assert_eq!(planes[2], [[210, 230]]);
+    }
+
+    #[test]
+    fn bilinear_interpolates_a_ramp() {
+        let frame = frame("I420", 4, 2, |_, x, _| (x * 40) as u8);
+        let planes = scale(&frame, Filter::Bilinear, 8, 2);
+        // the outer samples keep the edges, the centers of the others fall between two source samples
+        let ramp = [0, 10, 30, 50, 70, 90, 110, 120];
+        assert_eq!(planes[0], [ramp, ramp]);
+        assert_eq!(planes[1], [[0, 10, 30, 40]]);
+    }
+
+    #[test]
+    fn box_averages_pairs() {
+        let frame = frame("I420", 8, 4, |_, x, y| (x * x * 3 + y * 7) as u8);
+        let planes = scale(&frame, Filter::Box, 4, 2);
+        let average = |x: usize, y: usize| {
+            let sum: usize = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                .iter()
+                .map(|(x, y)| x * x * 3 + y * 7)
+                .sum();
+            ((sum + 2) / 4) as u8
+        };
+        for (y, row) in planes[0].iter().enumerate() {
+            let expected: Vec<_> = (0..4).map(|x| average(2 * x, 2 * y)).collect();
+            assert_eq!(row, &expected, "row {y}");
+        }
+        assert_eq!(planes[1], [[average(0, 0), average(2, 0)]]);
+    }
+
+    #[test]
+    fn flat_planes_stay_flat() {
+        // weights like thirds do not add up to one after rounding, which the fix-up makes up for,
+        // and over the hundreds of samples of a box the error adds up to more than one step
+        let sizes = [
+            (30, 18, 10, 6),
+            (30, 18, 14, 10),
+            (30, 18, 44, 26),
+            (600, 4, 2, 2),
+        ];
+        for filter in [Filter::Nearest, Filter::Bilinear, Filter::Box] {
+            for (width, height, target_width, target_height) in sizes {
+                let frame = frame("I420", width, height, |plane, _, _| [77, 200, 3][plane]);
+                let planes = scale(&frame, filter, target_width, target_height);
+                for (plane, value) in planes.iter().zip([77, 200, 3]) {
+                    let flat = plane.iter().flatten().all(|sample| *sample == value);
+                    assert!(
+                        flat,
+                        "{filter:?} from {width}x{height} to {target_width}x{target_height}: {plane:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nv12_chroma_stays_apart() {
+        // U counts up and V down along the row
+        let frame = frame("NV12", 8, 4, |plane, x, _| match (plane, x % 2) {
+            (0, _) => 16,
+            (_, 0) => (x / 2 * 10) as u8,
+            _ => (200 - x / 2 * 10) as u8,
+        });
+        let planes = scale(&frame, Filter::Box, 4, 2);
+        assert_eq!(planes[1], [[5, 195, 25, 175]]);
+    }
+
+    #[test]
+    fn fit_sizes() {
+        // keeps the aspect ratio
+        assert_eq!(Size::Fit(640, 640).of(1280, 720), (640, 360));
+        assert_eq!(Size::Fit(1280, 360).of(1280, 720), (640, 360));
+        // rounds odd sizes down to even ones
+        assert_eq!(Size::Fit(101, 1000).of(1000, 1000), (100, 100));
+        assert_eq!(Size::Fixed(641, 361).of(1280, 720), (640, 360));
+        // at least 2 by 2
+        assert_eq!(Size::Fit(1, 1).of(1000, 500), (2, 2));
+        // never larger than the frame
+        assert_eq!(Size::Fit(3840, 2160).of(1280, 720), (1280, 720));
+        assert_eq!(Size::Fit(3840, 2160).of(1281, 721), (1280, 720));
+    }
+}
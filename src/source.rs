@@ -0,0 +1,60 @@
+use std::io::Result;
+
+use crate::frame::FrameStream;
+
+mod negotiation;
+mod producer;
+mod raw_file;
+mod test_pattern;
+mod y4m;
+
+pub use negotiation::{
+    negotiate, Negotiated, Preferences, Weights, CONVERTIBLE_PIXEL_FORMATS, ENCODER_PIXEL_FORMATS,
+};
+pub use raw_file::RawFiles;
+pub use test_pattern::{Pattern, TestPattern};
+pub use y4m::Y4mFile;
+
+/// Something that produces video frames, like a [camera](crate::camera::Camera).
+///
+/// Choose one of its [formats](VideoSource::formats), [start](VideoSource::start) it
+/// and receive its [frames](VideoSource::frames) until you [stop](VideoSource::stop) it.
+pub trait VideoSource: Send {
+    fn name(&self) -> &str;
+
+    /// Every resolution, framerate and pixel format combination the source can produce.
+    fn formats(&self) -> Vec<DeviceFormat>;
+
+    /// The format to use on the next [start](VideoSource::start). `None` lets the source decide.
+    fn set_preferred_format(&mut self, format: Option<DeviceFormat>);
+
+    fn start(&mut self) -> Result<()>;
+
+    fn stop(&mut self);
+
+    /// The latest frame and all following. The stream ends when the source can not produce frames anymore.
+    fn frames(&self) -> FrameStream;
+}
+
+/// A specific resolution, framerate and pixel format supported by a [source](VideoSource).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceFormat {
+    pub width: i32,
+    pub height: i32,
+    pub max_framerate: f64,
+    pub pixel_format: String,
+}
+
+impl std::fmt::Display for DeviceFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            width,
+            height,
+            max_framerate,
+            pixel_format,
+        } = self;
+        f.write_fmt(format_args!(
+            "{width}x{height} @ {max_framerate:.2} {pixel_format}"
+        ))
+    }
+}
@@ -0,0 +1,337 @@
+use super::DeviceFormat;
+
+/// The FOURCCs of the 4:2:0 formats the VP8 encoder takes without conversion, NV12 video range first.
+pub const ENCODER_PIXEL_FORMATS: [&str; 8] = [
+    "420v", "NV12", "420f", "y420", "I420", "YU12", "f420", "YV12",
+];
+
+/// The FOURCCs of the formats the [converter](crate::convert::Converter) turns into encoder formats, 4:2:2 first.
+pub const CONVERTIBLE_PIXEL_FORMATS: [&str; 6] = ["2vuy", "UYVY", "yuvs", "YUYV", "BGRA", "RGBA"];
+
+/// What a consumer wants from a [source](super::VideoSource). Every preference is scored from 0 to 1
+/// per format and weighted against the others by [negotiate].
+#[derive(Debug, Clone)]
+pub struct Preferences {
+    /// FOURCCs the consumer takes without conversion, the first the most preferred.
+    pub pixel_formats: Vec<String>,
+    /// Width and height to get as close as possible to, otherwise the bigger the better.
+    pub resolution: Option<(i32, i32)>,
+    /// Frame rate the format should reach at least, otherwise the higher the better.
+    pub min_framerate: Option<f64>,
+    /// Width to height, like `(16, 9)`.
+    pub aspect_ratio: Option<(i32, i32)>,
+    pub weights: Weights,
+}
+
+/// How much each [preference](Preferences) counts.
+#[derive(Debug, Clone, Copy)]
+pub struct Weights {
+    pub pixel_format: f64,
+    pub resolution: f64,
+    pub framerate: f64,
+    pub aspect_ratio: f64,
+}
+
+impl Default for Weights {
+    /// A format the encoder takes counts the most, because converting costs on every frame.
+    fn default() -> Self {
+        Self {
+            pixel_format: 4.0,
+            resolution: 2.0,
+            framerate: 2.0,
+            aspect_ratio: 1.0,
+        }
+    }
+}
+
+impl Preferences {
+    /// Prefers the [formats of the encoder](ENCODER_PIXEL_FORMATS), then the ones which
+    /// [need a conversion](CONVERTIBLE_PIXEL_FORMATS) and nothing else.
+    pub fn encoder() -> Self {
+        Self {
+            pixel_formats: ENCODER_PIXEL_FORMATS
+                .iter()
+                .chain(&CONVERTIBLE_PIXEL_FORMATS)
+                .map(|fourcc| fourcc.to_string())
+                .collect(),
+            resolution: None,
+            min_framerate: None,
+            aspect_ratio: None,
+            weights: Weights::default(),
+        }
+    }
+}
+
+/// The best [format](DeviceFormat) for some [preferences](Preferences) and why.
+#[derive(Debug, Clone)]
+pub struct Negotiated {
+    pub format: DeviceFormat,
+    /// Weighted sum of the scores of all preferences.
+    pub score: f64,
+    /// The score of every preference, like `pixel format 420v 1.00, resolution 1280x720 1.00, ...`.
+    pub reason: String,
+}
+
+impl std::fmt::Display for Negotiated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            format,
+            score,
+            reason,
+        } = self;
+        f.write_fmt(format_args!("{format} scored {score:.2}: {reason}"))
+    }
+}
+
+/// Scores every format of a preferred pixel format and returns the best one, `None` without any,
+/// because the consumer can not take the others, like MJPG.
+/// On equal scores the bigger and then the faster format wins.
+pub fn negotiate(formats: &[DeviceFormat], preferences: &Preferences) -> Option<Negotiated> {
+    let formats: Vec<_> = formats
+        .iter()
+        .filter(|format| preferences.pixel_formats.contains(&format.pixel_format))
+        .collect();
+    let max_area = formats.iter().copied().map(area).max().unwrap_or(0);
+    let max_framerate = formats
+        .iter()
+        .map(|format| format.max_framerate)
+        .max_by(f64::total_cmp)
+        .unwrap_or(0.0);
+
+    let mut best: Option<Negotiated> = None;
+    for format in formats {
+        let scores = [
+            (
+                "pixel format",
+                format.pixel_format.clone(),
+                pixel_format_score(format, preferences),
+                preferences.weights.pixel_format,
+            ),
+            (
+                "resolution",
+                format!("{}x{}", format.width, format.height),
+                resolution_score(format, preferences, max_area),
+                preferences.weights.resolution,
+            ),
+            (
+                "framerate",
+                format!("{:.2}", format.max_framerate),
+                framerate_score(format, preferences, max_framerate),
+                preferences.weights.framerate,
+            ),
+            (
+                "aspect ratio",
+                format!("{:.2}", aspect(format.width, format.height)),
+                aspect_ratio_score(format, preferences),
+                preferences.weights.aspect_ratio,
+            ),
+        ];
+
+        let score = scores
+            .iter()
+            .map(|(_, _, score, weight)| score * weight)
+            .sum();
+        let is_better = match &best {
+            None => true,
+            Some(best) => {
+                score > best.score
+                    || score == best.score
+                        && (area(format), format.max_framerate)
+                            > (area(&best.format), best.format.max_framerate)
+            }
+        };
+        if is_better {
+            let reason = scores
+                .iter()
+                .map(|(name, value, score, _)| format!("{name} {value} {score:.2}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            best = Some(Negotiated {
+                format: format.clone(),
+                score,
+                reason,
+            });
+        }
+    }
+    best
+}
+
+/// 1 for the most preferred pixel format, decreasing along the list to above 0.5 for the last.
+fn pixel_format_score(format: &DeviceFormat, preferences: &Preferences) -> f64 {
+    let count = preferences.pixel_formats.len();
+    preferences
+        .pixel_formats
+        .iter()
+        .position(|pixel_format| *pixel_format == format.pixel_format)
+        .map(|index| 1.0 - index as f64 / (2 * count) as f64)
+        .unwrap_or(0.0)
+}
+
+/// 1 for the target resolution, 0 for four times or a quarter of its pixels and beyond.
+fn resolution_score(format: &DeviceFormat, preferences: &Preferences, max_area: i64) -> f64 {
+    match preferences.resolution {
+        Some((width, height)) => {
+            let target = (width as i64 * height as i64).max(1) as f64;
+            let ratio = area(format).max(1) as f64 / target;
+            (1.0 - ratio.log2().abs() / 2.0).max(0.0)
+        }
+        None if max_area > 0 => area(format) as f64 / max_area as f64,
+        None => 0.0,
+    }
+}
+
+/// 1 when reaching the minimum frame rate, proportionally less below it.
+fn framerate_score(format: &DeviceFormat, preferences: &Preferences, max_framerate: f64) -> f64 {
+    match preferences.min_framerate {
+        Some(min) if min > 0.0 => (format.max_framerate / min).min(1.0),
+        _ if max_framerate > 0.0 => format.max_framerate / max_framerate,
+        _ => 0.0,
+    }
+}
+
+/// 1 for the exact aspect ratio, 0 for one twice as wide or as high and beyond. Always 1 without a preference.
+fn aspect_ratio_score(format: &DeviceFormat, preferences: &Preferences) -> f64 {
+    let Some((width, height)) = preferences.aspect_ratio else {
+        return 1.0;
+    };
+    let ratio = aspect(format.width, format.height) / aspect(width, height);
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return 0.0;
+    }
+    (1.0 - ratio.log2().abs()).max(0.0)
+}
+
+fn area(format: &DeviceFormat) -> i64 {
+    format.width as i64 * format.height as i64
+}
+
+fn aspect(width: i32, height: i32) -> f64 {
+    width as f64 / height.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(pixel_format: &str, width: i32, height: i32, max_framerate: f64) -> DeviceFormat {
+        DeviceFormat {
+            width,
+            height,
+            max_framerate,
+            pixel_format: pixel_format.to_string(),
+        }
+    }
+
+    fn best(formats: &[DeviceFormat], preferences: &Preferences) -> DeviceFormat {
+        negotiate(formats, preferences).unwrap().format
+    }
+
+    #[test]
+    fn nothing_to_take() {
+        let preferences = Preferences::encoder();
+        assert!(negotiate(&[], &preferences).is_none());
+        let formats = [
+            format("MJPG", 1920, 1080, 30.0),
+            format("H264", 1280, 720, 60.0),
+        ];
+        assert!(negotiate(&formats, &preferences).is_none());
+    }
+
+    #[test]
+    fn native_format_beats_bigger_conversion() {
+        let formats = [
+            format("YUYV", 1920, 1080, 30.0),
+            format("NV12", 1280, 720, 30.0),
+        ];
+        assert_eq!(best(&formats, &Preferences::encoder()), formats[1]);
+    }
+
+    #[test]
+    fn conversion_without_native_format() {
+        let formats = [
+            format("MJPG", 1920, 1080, 30.0),
+            format("BGRA", 1280, 720, 30.0),
+            format("YUYV", 1280, 720, 30.0),
+        ];
+        assert_eq!(best(&formats, &Preferences::encoder()), formats[2]);
+    }
+
+    #[test]
+    fn min_framerate() {
+        let preferences = Preferences {
+            min_framerate: Some(24.0),
+            ..Preferences::encoder()
+        };
+        let too_slow = [
+            format("NV12", 1920, 1080, 5.0),
+            format("NV12", 1280, 720, 30.0),
+        ];
+        assert_eq!(best(&too_slow, &preferences), too_slow[1]);
+
+        // Beyond the minimum a higher frame rate counts no more.
+        let preferences = Preferences {
+            min_framerate: Some(30.0),
+            ..preferences
+        };
+        let fast_enough = [
+            format("NV12", 1920, 1080, 30.0),
+            format("NV12", 1280, 720, 60.0),
+        ];
+        assert_eq!(best(&fast_enough, &preferences), fast_enough[0]);
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        let preferences = Preferences {
+            resolution: Some((1280, 800)),
+            aspect_ratio: Some((16, 9)),
+            ..Preferences::encoder()
+        };
+        let formats = [
+            format("NV12", 1280, 960, 30.0),
+            format("NV12", 1280, 720, 30.0),
+        ];
+        assert_eq!(best(&formats, &preferences), formats[1]);
+    }
+
+    #[test]
+    fn weights_decide_between_preferences() {
+        let formats = [
+            format("NV12", 1280, 960, 30.0),
+            format("NV12", 1280, 720, 30.0),
+        ];
+        let preferences = Preferences {
+            aspect_ratio: Some((16, 9)),
+            ..Preferences::encoder()
+        };
+        assert_eq!(best(&formats, &preferences), formats[0]);
+
+        let preferences = Preferences {
+            weights: Weights {
+                aspect_ratio: 4.0,
+                ..Weights::default()
+            },
+            ..preferences
+        };
+        assert_eq!(best(&formats, &preferences), formats[1]);
+    }
+
+    #[test]
+    fn ties_go_to_bigger_then_faster() {
+        let preferences = Preferences {
+            resolution: Some((1280, 720)),
+            min_framerate: Some(30.0),
+            ..Preferences::encoder()
+        };
+        let formats = [
+            format("NV12", 640, 360, 30.0),
+            format("NV12", 2560, 1440, 30.0),
+        ];
+        assert_eq!(best(&formats, &preferences), formats[1]);
+        let formats = [
+            format("NV12", 1280, 720, 30.0),
+            format("NV12", 1280, 720, 60.0),
+        ];
+        assert_eq!(best(&formats, &preferences), formats[1]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+
+use crate::frame::{FrameStream, ReceiverSharedFrame, SenderSharedFrame, SharedFrame};
+
+/// Runs a frame producing function on its own thread, paced to a frame rate, and publishes its frames.
+/// Shared by the sources which render or read their frames themselves.
+pub(crate) struct Producer {
+    /// Moved into the thread while running and handed back on [stop](Producer::stop).
+    sender: Option<SenderSharedFrame>,
+    receiver: ReceiverSharedFrame,
+    thread: Option<ProducerThread>,
+}
+
+struct ProducerThread {
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<Option<SenderSharedFrame>>,
+}
+
+impl Producer {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(None);
+        Self {
+            sender: Some(sender),
+            receiver,
+            thread: None,
+        }
+    }
+
+    /// Calls `produce` with the frame number, once per frame interval measured from the start,
+    /// so a slow frame does not delay all following frames. Without a `framerate` it is called
+    /// as fast as possible, so receivers slower than that only see the latest frames.
+    ///
+    /// When `produce` returns `None` the sender gets dropped, which ends every [frame stream](Producer::frames).
+    pub fn start<F>(&mut self, name: &str, framerate: Option<f64>, produce: F) -> Result<()>
+    where
+        F: FnMut(u64) -> Option<SharedFrame> + Send + 'static,
+    {
+        if self.thread.is_some() {
+            return Ok(());
+        }
+
+        let sender = self.sender.take().ok_or_else(|| {
+            Error::new(ErrorKind::Other, format!("frames of {name} ended already"))
+        })?;
+        let interval = framerate.map(|framerate| Duration::from_secs_f64(1.0 / framerate.max(1.0)));
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let handle = std::thread::Builder::new()
+            .name(name.into())
+            .spawn(move || produce_frames(produce, interval, sender, thread_running))?;
+
+        self.thread = Some(ProducerThread { running, handle });
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        thread.running.store(false, Ordering::Relaxed);
+        match thread.handle.join() {
+            Ok(sender) => self.sender = sender,
+            Err(_) => log::error!("Producer: Thread panicked."),
+        }
+    }
+
+    pub fn frames(&self) -> FrameStream {
+        WatchStream::new(self.receiver.clone())
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The time of a frame in a stream of a constant frame rate, starting at zero.
+pub fn stream_time(number: u64, framerate: f64) -> Duration {
+    Duration::from_secs_f64(number as f64 / framerate.max(1.0))
+}
+
+fn produce_frames<F>(
+    mut produce: F,
+    interval: Option<Duration>,
+    sender: SenderSharedFrame,
+    running: Arc<AtomicBool>,
+) -> Option<SenderSharedFrame>
+where
+    F: FnMut(u64) -> Option<SharedFrame>,
+{
+    let start = Instant::now();
+    let mut number = 0u64;
+
+    while running.load(Ordering::Relaxed) {
+        let frame = produce(number)?;
+        let _ = sender.send(Some(frame));
+
+        number += 1;
+        let Some(interval) = interval else {
+            std::thread::yield_now();
+            continue;
+        };
+        let deadline = start + interval.mul_f64(number as f64);
+        if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    Some(sender)
+}
@@ -0,0 +1,192 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use super::producer::{self, Producer};
+use crate::{
+    frame::{Frame, FrameStream, OwnedFrame, Pixels, SampleFormat, SharedFrame},
+    source::{DeviceFormat, VideoSource},
+};
+
+/// Replays raw frame dumps, like the `camera_frame.<fourcc>.<width>.<height>` files written by `write_frame`,
+/// in a loop at a fixed frame rate.
+///
+/// All files are read upfront, so the replay does not depend on disk speed.
+/// A file may hold several frames back to back, which get replayed in order.
+pub struct RawFiles {
+    name: String,
+    frames: Vec<SharedFrame>,
+    formats: Vec<SampleFormat>,
+    framerate: f64,
+    producer: Producer,
+}
+
+impl RawFiles {
+    /// Takes the format of every file from its name, see [parse_dump_name].
+    pub fn open(paths: &[PathBuf], framerate: f64) -> Result<Self> {
+        let formats = paths
+            .iter()
+            .map(|path| {
+                parse_dump_name(path).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "{} is not named like camera_frame.<fourcc>.<width>.<height>",
+                            path.display()
+                        ),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::read(paths, formats, framerate)
+    }
+
+    /// Uses the same given format for every file, regardless of its name.
+    pub fn open_with_format(
+        paths: &[PathBuf],
+        format: SampleFormat,
+        framerate: f64,
+    ) -> Result<Self> {
+        let formats = vec![format; paths.len()];
+        Self::read(paths, formats, framerate)
+    }
+
+    fn read(paths: &[PathBuf], formats: Vec<SampleFormat>, framerate: f64) -> Result<Self> {
+        if paths.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no raw frame files"));
+        }
+
+        let mut frames: Vec<SharedFrame> = vec![];
+        for (path, format) in paths.iter().zip(&formats) {
+            let data = std::fs::read(path)?;
+            let Some(size) = format.packed_size() else {
+                log::warn!("RawFiles: Unknown size of {}.", format);
+                frames.push(Arc::new(OwnedFrame::new(
+                    format.clone(),
+                    data,
+                    Duration::ZERO,
+                )));
+                continue;
+            };
+            // a partial frame would replay as garbage
+            if data.is_empty() || data.len() % size != 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} has {} bytes, which is no whole number of {} frames of {} bytes",
+                        path.display(),
+                        data.len(),
+                        format,
+                        size
+                    ),
+                ));
+            }
+            for frame in data.chunks_exact(size) {
+                frames.push(Arc::new(OwnedFrame::new(
+                    format.clone(),
+                    frame.to_vec(),
+                    Duration::ZERO,
+                )));
+            }
+        }
+
+        Ok(Self {
+            name: format!("raw files {}", paths[0].display()),
+            frames,
+            formats,
+            framerate,
+            producer: Producer::new(),
+        })
+    }
+}
+
+impl VideoSource for RawFiles {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formats(&self) -> Vec<DeviceFormat> {
+        let mut formats: Vec<DeviceFormat> = vec![];
+        for format in &self.formats {
+            let known = formats.iter().any(|known| {
+                known.width == format.width
+                    && known.height == format.height
+                    && known.pixel_format == format.pixel_format
+            });
+            if !known {
+                formats.push(DeviceFormat {
+                    width: format.width,
+                    height: format.height,
+                    max_framerate: self.framerate,
+                    pixel_format: format.pixel_format.clone(),
+                });
+            }
+        }
+        formats
+    }
+
+    /// The files are replayed as they are, only the frame rate of the preferred format is taken.
+    fn set_preferred_format(&mut self, format: Option<DeviceFormat>) {
+        if let Some(format) = format.filter(|format| format.max_framerate > 0.0) {
+            self.framerate = format.max_framerate;
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let frames = self.frames.clone();
+        let framerate = self.framerate;
+        self.producer
+            .start("raw files", Some(framerate), move |number| {
+                Some(Arc::new(Replayed {
+                    frame: frames[number as usize % frames.len()].clone(),
+                    timestamp: producer::stream_time(number, framerate),
+                }))
+            })
+    }
+
+    fn stop(&mut self) {
+        self.producer.stop();
+    }
+
+    fn frames(&self) -> FrameStream {
+        self.producer.frames()
+    }
+}
+
+/// A frame of the files at its time of the replay, without copying the pixel data.
+struct Replayed {
+    frame: SharedFrame,
+    timestamp: Duration,
+}
+
+impl Frame for Replayed {
+    fn format(&self) -> SampleFormat {
+        self.frame.format()
+    }
+
+    fn pixels(&self) -> Pixels<'_> {
+        self.frame.pixels()
+    }
+
+    fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+}
+
+/// Parses file names like `camera_frame.420v.1280.720` into the [format](SampleFormat) `1280x720 420v`.
+/// Only the last three dot separated parts matter, so any prefix or none works.
+pub fn parse_dump_name(path: &Path) -> Option<SampleFormat> {
+    let name = path.file_name()?.to_str()?;
+    let mut parts = name.rsplitn(4, '.');
+    let height = parts.next()?.parse().ok()?;
+    let width = parts.next()?.parse().ok()?;
+    let pixel_format = parts.next()?.to_string();
+    Some(SampleFormat {
+        width,
+        height,
+        pixel_format,
+    })
+}
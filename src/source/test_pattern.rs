@@ -0,0 +1,449 @@
+use std::{io::Result, str::FromStr, sync::Arc};
+
+use super::producer::{self, Producer};
+use crate::{
+    frame::{BufferPool, FrameStream, SampleFormat, VideoFrame},
+    source::{DeviceFormat, VideoSource},
+    text,
+};
+
+/// The picture a [test pattern source](TestPattern) renders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// SMPTE color bars with castellations, -I, +Q and PLUGE.
+    Bars,
+    /// A white box moving over a gray background and bouncing off the edges.
+    MovingBox,
+    /// Luma rising from left to right, blue difference from top to bottom and red difference from left to right.
+    Gradient,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bars" => Ok(Self::Bars),
+            "box" => Ok(Self::MovingBox),
+            "gradient" => Ok(Self::Gradient),
+            _ => Err(format!("unknown pattern {s}, use bars, box or gradient")),
+        }
+    }
+}
+
+/// Produces NV12 or I420 test pattern frames without any camera.
+///
+/// The frame number and the stream time are burned in at the top left corner.
+/// Both are derived from the frame number only, so the same frame number always renders the same picture.
+pub struct TestPattern {
+    name: String,
+    pattern: Pattern,
+    width: i32,
+    height: i32,
+    framerate: f64,
+    producer: Producer,
+    prefererred_format: Option<DeviceFormat>,
+}
+
+impl TestPattern {
+    /// Width and height need to be even, because of the 4:2:0 chroma subsampling.
+    pub fn new(pattern: Pattern, width: u32, height: u32, framerate: f64) -> Self {
+        Self {
+            name: format!("test pattern {pattern:?}"),
+            pattern,
+            width: (width & !1) as i32,
+            height: (height & !1) as i32,
+            framerate,
+            producer: Producer::new(),
+            prefererred_format: None,
+        }
+    }
+}
+
+impl VideoSource for TestPattern {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formats(&self) -> Vec<DeviceFormat> {
+        [PixelLayout::NV12, PixelLayout::I420]
+            .iter()
+            .map(|layout| DeviceFormat {
+                width: self.width,
+                height: self.height,
+                max_framerate: self.framerate,
+                pixel_format: layout.fourcc().to_string(),
+            })
+            .collect()
+    }
+
+    fn set_preferred_format(&mut self, format: Option<DeviceFormat>) {
+        self.prefererred_format = format;
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let format = self
+            .prefererred_format
+            .clone()
+            .unwrap_or_else(|| self.formats().remove(0));
+        let layout = PixelLayout::from_fourcc(&format.pixel_format).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("test pattern can not render {}", format.pixel_format),
+            )
+        })?;
+        let framerate = if format.max_framerate > 0.0 {
+            format.max_framerate
+        } else {
+            self.framerate
+        };
+        let renderer = Renderer {
+            pattern: self.pattern,
+            layout,
+            width: (format.width & !1) as usize,
+            height: (format.height & !1) as usize,
+            framerate,
+            pool: BufferPool::default(),
+        };
+
+        self.producer
+            .start("test pattern", Some(framerate), move |number| {
+                Some(Arc::new(renderer.render(number)))
+            })
+    }
+
+    fn stop(&mut self) {
+        self.producer.stop();
+    }
+
+    fn frames(&self) -> FrameStream {
+        self.producer.frames()
+    }
+}
+
+/// How the planes of a 4:2:0 frame are laid out in memory. Strides equal the plane widths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PixelLayout {
+    /// Y plane followed by one plane with interleaved U and V.
+    NV12,
+    /// Y plane followed by a U plane and a V plane.
+    I420,
+}
+
+impl PixelLayout {
+    fn fourcc(&self) -> &'static str {
+        match self {
+            PixelLayout::NV12 => "NV12",
+            PixelLayout::I420 => "I420",
+        }
+    }
+
+    fn from_fourcc(fourcc: &str) -> Option<Self> {
+        match fourcc {
+            "NV12" => Some(PixelLayout::NV12),
+            "I420" => Some(PixelLayout::I420),
+            _ => None,
+        }
+    }
+}
+
+/// A color in BT.601 video range YCbCr.
+#[derive(Debug, Clone, Copy)]
+struct Yuv(u8, u8, u8);
+
+// 75% color bars
+const GRAY: Yuv = Yuv(180, 128, 128);
+const YELLOW: Yuv = Yuv(162, 44, 142);
+const CYAN: Yuv = Yuv(131, 156, 44);
+const GREEN: Yuv = Yuv(112, 72, 58);
+const MAGENTA: Yuv = Yuv(84, 184, 198);
+const RED: Yuv = Yuv(65, 100, 212);
+const BLUE: Yuv = Yuv(35, 212, 114);
+// 100% white, black and the bottom row signals
+const WHITE: Yuv = Yuv(235, 128, 128);
+const BLACK: Yuv = Yuv(16, 128, 128);
+const NEG_I: Yuv = Yuv(16, 158, 95);
+const POS_Q: Yuv = Yuv(16, 174, 149);
+const SUPER_BLACK: Yuv = Yuv(7, 128, 128);
+const NEAR_BLACK: Yuv = Yuv(25, 128, 128);
+
+struct Renderer {
+    pattern: Pattern,
+    layout: PixelLayout,
+    width: usize,
+    height: usize,
+    framerate: f64,
+    pool: BufferPool,
+}
+
+impl Renderer {
+    /// Renders into a frame of the pool, so rendering allocates nothing once the first frames are dropped.
+    fn render(&self, number: u64) -> VideoFrame {
+        let Self { width, height, .. } = *self;
+        let chroma_width = width / 2;
+        let chroma_height = height / 2;
+        let format = SampleFormat {
+            width: width as i32,
+            height: height as i32,
+            pixel_format: self.layout.fourcc().to_string(),
+        };
+        let timestamp = producer::stream_time(number, self.framerate);
+        let mut frame = VideoFrame::new(&self.pool, format, timestamp)
+            .expect("NV12 and I420 have a plane layout");
+        // every byte gets rendered, the planes follow each other like in the pixel layout
+        let data = frame.data_mut();
+
+        {
+            let (y_plane, chroma) = data.split_at_mut(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    y_plane[y * width + x] = self.color(number, x, y).0;
+                }
+            }
+            for cy in 0..chroma_height {
+                for cx in 0..chroma_width {
+                    let Yuv(_, u, v) = self.color(number, cx * 2, cy * 2);
+                    self.put_chroma(chroma, cx, cy, u, v);
+                }
+            }
+        }
+
+        self.burn_in(data, number);
+        frame
+    }
+
+    fn color(&self, number: u64, x: usize, y: usize) -> Yuv {
+        match self.pattern {
+            Pattern::Bars => self.bars(x, y),
+            Pattern::MovingBox => self.moving_box(number, x, y),
+            Pattern::Gradient => self.gradient(x, y),
+        }
+    }
+
+    /// SMPTE ECR 1-1978 layout: 7 bars on the top two thirds, castellations below and
+    /// -I, white, +Q, black and PLUGE on the bottom quarter.
+    fn bars(&self, x: usize, y: usize) -> Yuv {
+        const TOP: [Yuv; 7] = [GRAY, YELLOW, CYAN, GREEN, MAGENTA, RED, BLUE];
+        const MIDDLE: [Yuv; 7] = [BLUE, BLACK, MAGENTA, BLACK, CYAN, BLACK, GRAY];
+
+        let bar = (x * 7 / self.width).min(6);
+        if y * 3 < self.height * 2 {
+            TOP[bar]
+        } else if y * 4 < self.height * 3 {
+            MIDDLE[bar]
+        } else {
+            // bottom row is divided in 28 quarter bars, PLUGE in 3 twelfth bars
+            let quarter = x * 28 / self.width;
+            let twelfth = x * 84 / self.width;
+            match quarter {
+                0..=4 => NEG_I,
+                5..=9 => WHITE,
+                10..=14 => POS_Q,
+                15..=19 => BLACK,
+                20..=23 => match twelfth {
+                    60..=63 => SUPER_BLACK,
+                    64..=67 => BLACK,
+                    _ => NEAR_BLACK,
+                },
+                _ => BLACK,
+            }
+        }
+    }
+
+    /// A white box of a quarter of the frame height, moving 4 pixels per frame diagonally.
+    fn moving_box(&self, number: u64, x: usize, y: usize) -> Yuv {
+        let size = (self.height / 4).max(2);
+        let left = bounce(number * 4, self.width.saturating_sub(size));
+        let top = bounce(number * 4, self.height.saturating_sub(size));
+        if (left..left + size).contains(&x) && (top..top + size).contains(&y) {
+            WHITE
+        } else {
+            Yuv(64, 128, 128)
+        }
+    }
+
+    fn gradient(&self, x: usize, y: usize) -> Yuv {
+        let luma = 16 + x * 219 / self.width.max(1);
+        let u = 16 + y * 224 / self.height.max(1);
+        let v = 16 + x * 224 / self.width.max(1);
+        Yuv(luma as u8, u as u8, v as u8)
+    }
+
+    fn put_chroma(&self, chroma: &mut [u8], cx: usize, cy: usize, u: u8, v: u8) {
+        let chroma_width = self.width / 2;
+        let chroma_size = chroma_width * (self.height / 2);
+        match self.layout {
+            PixelLayout::NV12 => {
+                let index = (cy * chroma_width + cx) * 2;
+                chroma[index] = u;
+                chroma[index + 1] = v;
+            }
+            PixelLayout::I420 => {
+                let index = cy * chroma_width + cx;
+                chroma[index] = u;
+                chroma[chroma_size + index] = v;
+            }
+        }
+    }
+
+    /// Burns the frame number and the stream time as white text on a black box into the top left corner.
+    fn burn_in(&self, data: &mut [u8], number: u64) {
+        let millis = producer::stream_time(number, self.framerate).as_millis() as u64;
+        let counter = format!("{number:08}");
+        let clock = format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000
+        );
+
+        let scale = (self.height / 180).max(1);
+        let margin = 2 * scale;
+        let (text_width, text_height) = text::text_size(&clock, scale);
+        // even box position and size keep the chroma samples below the box completely black
+        let box_width = (text_width + 2 * margin + 1) & !1;
+        let box_height = (2 * text_height + 3 * margin + 1) & !1;
+
+        let Self { width, height, .. } = *self;
+        let (y_plane, chroma) = data.split_at_mut(width * height);
+        text::fill_rect(
+            y_plane, width, width, height, 0, 0, box_width, box_height, BLACK.0,
+        );
+        text::draw_text(
+            y_plane, width, width, height, margin, margin, scale, &counter, WHITE.0,
+        );
+        let clock_y = 2 * margin + text_height;
+        text::draw_text(
+            y_plane, width, width, height, margin, clock_y, scale, &clock, WHITE.0,
+        );
+
+        for cy in 0..(box_height / 2).min(height / 2) {
+            for cx in 0..(box_width / 2).min(width / 2) {
+                self.put_chroma(chroma, cx, cy, 128, 128);
+            }
+        }
+    }
+}
+
+/// Moves back and forth between 0 and `max`.
+fn bounce(position: u64, max: usize) -> usize {
+    if max == 0 {
+        return 0;
+    }
+    let max = max as u64;
+    let position = position % (2 * max);
+    (if position > max {
+        2 * max - position
+    } else {
+        position
+    }) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Frame;
+
+    const WIDTH: usize = 640;
+    const HEIGHT: usize = 360;
+
+    fn renderer(pattern: Pattern, layout: PixelLayout) -> Renderer {
+        Renderer {
+            pattern,
+            layout,
+            width: WIDTH,
+            height: HEIGHT,
+            framerate: 30.0,
+            pool: BufferPool::default(),
+        }
+    }
+
+    fn render(renderer: &Renderer, number: u64) -> Vec<u8> {
+        renderer.render(number).pixels().to_vec()
+    }
+
+    /// The luma and the chroma at a pixel of a packed frame.
+    fn color(data: &[u8], layout: PixelLayout, x: usize, y: usize) -> (u8, u8, u8) {
+        let chroma = &data[WIDTH * HEIGHT..];
+        let index = y / 2 * WIDTH / 2 + x / 2;
+        let (u, v) = match layout {
+            PixelLayout::NV12 => (chroma[index * 2], chroma[index * 2 + 1]),
+            PixelLayout::I420 => (chroma[index], chroma[WIDTH * HEIGHT / 4 + index]),
+        };
+        (data[y * WIDTH + x], u, v)
+    }
+
+    #[test]
+    fn same_number_same_picture() {
+        for pattern in [Pattern::Bars, Pattern::MovingBox, Pattern::Gradient] {
+            for layout in [PixelLayout::NV12, PixelLayout::I420] {
+                let first = renderer(pattern, layout);
+                let frame = render(&first, 7);
+                assert_eq!(frame.len(), WIDTH * HEIGHT * 3 / 2);
+                render(&first, 8);
+                assert_eq!(render(&first, 7), frame, "{pattern:?} {layout:?}");
+                assert_eq!(
+                    render(&renderer(pattern, layout), 7),
+                    frame,
+                    "{pattern:?} {layout:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn smpte_bars() {
+        let top = [GRAY, YELLOW, CYAN, GREEN, MAGENTA, RED, BLUE];
+        let middle = [BLUE, BLACK, MAGENTA, BLACK, CYAN, BLACK, GRAY];
+        let bottom = [
+            (50, NEG_I),
+            (160, WHITE),
+            (280, POS_Q),
+            (400, BLACK),
+            (470, SUPER_BLACK),
+            (500, BLACK),
+            (530, NEAR_BLACK),
+            (600, BLACK),
+        ];
+
+        for layout in [PixelLayout::NV12, PixelLayout::I420] {
+            let frame = render(&renderer(Pattern::Bars, layout), 0);
+            let check = |x, y, Yuv(luma, u, v): Yuv| {
+                assert_eq!(
+                    color(&frame, layout, x, y),
+                    (luma, u, v),
+                    "{layout:?} {x}x{y}"
+                );
+            };
+            for bar in 0..7 {
+                // the middle of every bar
+                let x = (2 * bar + 1) * WIDTH / 14;
+                check(x, 100, top[bar]);
+                check(x, 254, middle[bar]);
+            }
+            for (x, color) in bottom {
+                check(x, 340, color);
+            }
+        }
+    }
+
+    #[test]
+    fn counter_and_clock_change() {
+        let renderer = renderer(Pattern::Bars, PixelLayout::I420);
+        let frame = render(&renderer, 10);
+        let next = render(&renderer, 11);
+
+        // 2 times scaled text with a margin of 4: the counter on rows 4 to 17, the clock on rows 22 to 35
+        let rows = |data: &[u8], rows: std::ops::Range<usize>| {
+            data[rows.start * WIDTH..rows.end * WIDTH].to_vec()
+        };
+        assert_ne!(rows(&frame, 4..18), rows(&next, 4..18));
+        assert_ne!(rows(&frame, 22..36), rows(&next, 22..36));
+        // the bars below stay the same
+        assert_eq!(frame[40 * WIDTH..], next[40 * WIDTH..]);
+        // and the box is black with white text
+        assert_eq!(color(&frame, PixelLayout::I420, 0, 0), (16, 128, 128));
+        assert!(frame[4 * WIDTH..36 * WIDTH].contains(&235));
+    }
+}
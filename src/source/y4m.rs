@@ -0,0 +1,528 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Error, ErrorKind, Result, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use super::producer::{self, Producer};
+use crate::{
+    frame::{BufferPool, Colorimetry, FrameStream, Range, SampleFormat, VideoFrame},
+    source::{DeviceFormat, VideoSource},
+};
+
+/// Plays a YUV4MPEG2 file, like the common test sequences foreman or akiyo, as I420 frames.
+///
+/// Frames are emitted in real time according to the frame rate of the file or as fast as possible.
+/// 4:2:2, 4:4:4 and monochrome files get converted to 4:2:0.
+pub struct Y4mFile {
+    name: String,
+    path: PathBuf,
+    header: Y4mHeader,
+    framerate: Option<f64>,
+    looping: bool,
+    producer: Producer,
+}
+
+impl Y4mFile {
+    /// Reads the header of the file. With `realtime` the frames are paced to the frame rate of the file,
+    /// with `looping` the file starts over at its end instead of ending the frames.
+    pub fn open(path: &Path, realtime: bool, looping: bool) -> Result<Self> {
+        let reader = Y4mReader::new(BufReader::new(File::open(path)?))?;
+        let header = reader.header().clone();
+        if header.interlacing != Interlacing::Progressive {
+            log::warn!(
+                "Y4mFile: {:?} frames are played like progressive frames.",
+                header.interlacing
+            );
+        }
+        let framerate = realtime.then(|| header.framerate());
+        Ok(Self {
+            name: format!("y4m {}", path.display()),
+            path: path.to_owned(),
+            header,
+            framerate,
+            looping,
+            producer: Producer::new(),
+        })
+    }
+
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+}
+
+impl VideoSource for Y4mFile {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formats(&self) -> Vec<DeviceFormat> {
+        vec![DeviceFormat {
+            width: self.header.width as i32,
+            height: self.header.height as i32,
+            max_framerate: self.header.framerate(),
+            pixel_format: "I420".to_string(),
+        }]
+    }
+
+    /// The file is played as it is, only the frame rate of the preferred format is taken when playing in real time.
+    fn set_preferred_format(&mut self, format: Option<DeviceFormat>) {
+        let Some(format) = format else { return };
+        if self.framerate.is_some() && format.max_framerate > 0.0 {
+            self.framerate = Some(format.max_framerate);
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let mut reader = Y4mReader::new(BufReader::new(File::open(&self.path)?))?;
+        let looping = self.looping;
+        let file_framerate = self.header.framerate();
+        let format = SampleFormat {
+            width: reader.header().width as i32,
+            height: reader.header().height as i32,
+            pixel_format: "I420".to_string(),
+        };
+        let colorimetry = Colorimetry {
+            range: reader.header().range,
+            ..Colorimetry::default()
+        };
+
+        let pool = BufferPool::default();
+        self.producer.start("y4m", self.framerate, move |number| {
+            // the time in the file, also when reading as fast as possible
+            let timestamp = producer::stream_time(number, file_framerate);
+            let mut frame = VideoFrame::new(&pool, format.clone(), timestamp)?;
+            frame.set_colorimetry(colorimetry);
+            let read = match reader.read_frame(frame.data_mut()) {
+                Ok(false) if looping => reader
+                    .rewind()
+                    .and_then(|_| reader.read_frame(frame.data_mut())),
+                read => read,
+            };
+            match read {
+                Ok(true) => Some(Arc::new(frame)),
+                Ok(false) => {
+                    log::debug!("Y4mFile: End of file. End.");
+                    None
+                }
+                Err(err) => {
+                    log::error!("Y4mFile: Reading frame failed. End. ({})", err);
+                    None
+                }
+            }
+        })
+    }
+
+    fn stop(&mut self) {
+        self.producer.stop();
+    }
+
+    fn frames(&self) -> FrameStream {
+        self.producer.frames()
+    }
+}
+
+/// The stream header of a YUV4MPEG2 file, like `YUV4MPEG2 W352 H288 F30000:1001 Ip A128:117 C420jpeg`.
+///
+/// <https://wiki.multimedia.cx/index.php/YUV4MPEG2>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Y4mHeader {
+    pub width: usize,
+    pub height: usize,
+    /// Frames per second as numerator and denominator.
+    pub framerate: (u32, u32),
+    pub interlacing: Interlacing,
+    /// Pixel aspect ratio, `(0, 0)` if unknown.
+    pub aspect: (u32, u32),
+    pub colorspace: Colorspace,
+    /// Video range unless the `XCOLORRANGE=FULL` extension of FFmpeg tells otherwise.
+    pub range: Range,
+}
+
+impl Y4mHeader {
+    pub fn framerate(&self) -> f64 {
+        let (num, den) = self.framerate;
+        if den == 0 {
+            0.0
+        } else {
+            num as f64 / den as f64
+        }
+    }
+
+    /// The size of the planes of a frame as stored in the file.
+    fn frame_size(&self) -> usize {
+        let luma = self.width * self.height;
+        let (chroma_width, chroma_height) = self.colorspace.chroma_size(self.width, self.height);
+        luma + 2 * chroma_width * chroma_height
+    }
+}
+
+impl FromStr for Y4mHeader {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut params = line.split_ascii_whitespace();
+        if params.next() != Some("YUV4MPEG2") {
+            return Err(invalid_data("not a YUV4MPEG2 stream"));
+        }
+
+        let mut header = Self {
+            width: 0,
+            height: 0,
+            framerate: (25, 1),
+            interlacing: Interlacing::Progressive,
+            aspect: (0, 0),
+            colorspace: Colorspace::C420jpeg,
+            range: Range::Video,
+        };
+
+        for param in params {
+            // by char, as a tag of a foreign or corrupt file may be any
+            let mut chars = param.chars();
+            let tag = chars.next();
+            let value = chars.as_str();
+            match tag {
+                Some('W') => header.width = parse_number(value)?,
+                Some('H') => header.height = parse_number(value)?,
+                Some('F') => header.framerate = parse_ratio(value)?,
+                Some('A') => header.aspect = parse_ratio(value)?,
+                Some('I') => header.interlacing = value.parse()?,
+                Some('C') => header.colorspace = value.parse()?,
+                // X are application specific extensions
+                Some('X') if value == "COLORRANGE=FULL" => header.range = Range::Full,
+                Some('X') if value == "COLORRANGE=LIMITED" => header.range = Range::Video,
+                _ => log::trace!("Y4mHeader: Ignore parameter {}.", param),
+            }
+        }
+
+        if header.width == 0 || header.height == 0 {
+            return Err(invalid_data("missing width or height"));
+        }
+        Ok(header)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interlacing {
+    Progressive,
+    TopFieldFirst,
+    BottomFieldFirst,
+    /// Every frame header tells by itself.
+    Mixed,
+}
+
+impl FromStr for Interlacing {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "p" | "?" => Ok(Self::Progressive),
+            "t" => Ok(Self::TopFieldFirst),
+            "b" => Ok(Self::BottomFieldFirst),
+            "m" => Ok(Self::Mixed),
+            _ => Err(invalid_data(&format!("unknown interlacing I{value}"))),
+        }
+    }
+}
+
+/// Chroma subsampling and siting of the 8 bit planar frames. The 4:2:0 variants only differ in siting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colorspace {
+    /// 4:2:0, chroma centered between luma samples, the default
+    C420jpeg,
+    /// 4:2:0, chroma left aligned with luma samples
+    C420mpeg2,
+    /// 4:2:0, chroma alternating siting like PAL DV
+    C420paldv,
+    C422,
+    C444,
+    Mono,
+}
+
+impl Colorspace {
+    fn chroma_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Colorspace::C420jpeg | Colorspace::C420mpeg2 | Colorspace::C420paldv => {
+                ((width + 1) / 2, (height + 1) / 2)
+            }
+            Colorspace::C422 => ((width + 1) / 2, height),
+            Colorspace::C444 => (width, height),
+            Colorspace::Mono => (0, 0),
+        }
+    }
+}
+
+impl FromStr for Colorspace {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "420jpeg" | "420" => Ok(Self::C420jpeg),
+            "420mpeg2" => Ok(Self::C420mpeg2),
+            "420paldv" => Ok(Self::C420paldv),
+            "422" => Ok(Self::C422),
+            "444" => Ok(Self::C444),
+            "mono" => Ok(Self::Mono),
+            _ => Err(invalid_data(&format!("unsupported colorspace C{value}"))),
+        }
+    }
+}
+
+/// Reads the header and then frame by frame of a YUV4MPEG2 stream.
+pub struct Y4mReader<R> {
+    reader: R,
+    header: Y4mHeader,
+    /// Where the first frame starts, to start over.
+    frames_start: u64,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead + Seek> Y4mReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let line = read_line(&mut reader)?.ok_or_else(|| invalid_data("empty stream"))?;
+        let header: Y4mHeader = line.parse()?;
+        let frames_start = reader.stream_position()?;
+        let buffer = vec![0; header.frame_size()];
+        Ok(Self {
+            reader,
+            header,
+            frames_start,
+            buffer,
+        })
+    }
+
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+
+    /// Reads the next frame into `data` in I420 layout, which needs to hold an I420 frame of the header size.
+    /// Returns `false` at the end of the stream.
+    pub fn read_frame(&mut self, data: &mut [u8]) -> Result<bool> {
+        let Some(line) = read_line(&mut self.reader)? else {
+            return Ok(false);
+        };
+        if !line.starts_with("FRAME") {
+            return Err(invalid_data("missing FRAME header"));
+        }
+        self.reader.read_exact(&mut self.buffer)?;
+        to_i420(&self.header, &self.buffer, data);
+        Ok(true)
+    }
+
+    /// Starts over with the first frame.
+    pub fn rewind(&mut self) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(self.frames_start))?;
+        Ok(())
+    }
+}
+
+/// Reads a header line without its line feed. Returns `None` at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = vec![];
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid_data("header is not UTF-8"))
+}
+
+/// Copies 4:2:0 planes as they are and subsamples or fills the chroma planes of all other colorspaces.
+fn to_i420(header: &Y4mHeader, planes: &[u8], data: &mut [u8]) {
+    let Y4mHeader { width, height, .. } = *header;
+    let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+    let luma = width * height;
+    let chroma = chroma_width * chroma_height;
+    let data = &mut data[..luma + 2 * chroma];
+
+    match header.colorspace {
+        Colorspace::C420jpeg | Colorspace::C420mpeg2 | Colorspace::C420paldv => {
+            data.copy_from_slice(planes)
+        }
+        Colorspace::Mono => {
+            data[..luma].copy_from_slice(&planes[..luma]);
+            data[luma..].fill(128);
+        }
+        Colorspace::C422 | Colorspace::C444 => {
+            let (source_width, source_height) = header.colorspace.chroma_size(width, height);
+            let source_size = source_width * source_height;
+            // 4:4:4 halves both directions, 4:2:2 only the vertical one
+            let step_x = if source_width == width { 2 } else { 1 };
+            let step_y = if source_height == height { 2 } else { 1 };
+
+            data[..luma].copy_from_slice(&planes[..luma]);
+            for plane in 0..2 {
+                let source = &planes[luma + plane * source_size..][..source_size];
+                let target = &mut data[luma + plane * chroma..][..chroma];
+                for y in 0..chroma_height {
+                    for x in 0..chroma_width {
+                        let sx = (x * step_x).min(source_width - 1);
+                        let sy = (y * step_y).min(source_height - 1);
+                        let sx2 = (sx + step_x - 1).min(source_width - 1);
+                        let sy2 = (sy + step_y - 1).min(source_height - 1);
+                        let sum = source[sy * source_width + sx] as u32
+                            + source[sy * source_width + sx2] as u32
+                            + source[sy2 * source_width + sx] as u32
+                            + source[sy2 * source_width + sx2] as u32;
+                        target[y * chroma_width + x] = ((sum + 2) / 4) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .map_err(|_| invalid_data(&format!("invalid number {value}")))
+}
+
+fn parse_ratio(value: &str) -> Result<(u32, u32)> {
+    let (num, den) = value
+        .split_once(':')
+        .ok_or_else(|| invalid_data(&format!("invalid ratio {value}")))?;
+    let num = num
+        .parse()
+        .map_err(|_| invalid_data(&format!("invalid ratio {value}")))?;
+    let den = den
+        .parse()
+        .map_err(|_| invalid_data(&format!("invalid ratio {value}")))?;
+    Ok((num, den))
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Y4M: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(line: &str) -> Y4mHeader {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn foreman_header() {
+        let header = header("YUV4MPEG2 W352 H288 F30000:1001 Ip A128:117 C420jpeg XYSCSS=420JPEG");
+        assert_eq!(
+            header,
+            Y4mHeader {
+                width: 352,
+                height: 288,
+                framerate: (30000, 1001),
+                interlacing: Interlacing::Progressive,
+                aspect: (128, 117),
+                colorspace: Colorspace::C420jpeg,
+                range: Range::Video,
+            }
+        );
+        assert!((header.framerate() - 29.97).abs() < 0.001);
+        assert_eq!(header.frame_size(), 352 * 288 * 3 / 2);
+    }
+
+    #[test]
+    fn defaults() {
+        let header = header("YUV4MPEG2 W4 H2");
+        assert_eq!(header.framerate, (25, 1));
+        assert_eq!(header.interlacing, Interlacing::Progressive);
+        assert_eq!(header.aspect, (0, 0));
+        assert_eq!(header.colorspace, Colorspace::C420jpeg);
+        assert_eq!(header.range, Range::Video);
+        assert_eq!(header.framerate(), 25.0);
+    }
+
+    #[test]
+    fn colorspaces() {
+        // 5x3 rounds the 4:2:0 and 4:2:2 chroma planes up
+        for (tag, colorspace, frame_size) in [
+            ("C420", Colorspace::C420jpeg, 15 + 2 * 3 * 2),
+            ("C420jpeg", Colorspace::C420jpeg, 15 + 2 * 3 * 2),
+            ("C420mpeg2", Colorspace::C420mpeg2, 15 + 2 * 3 * 2),
+            ("C420paldv", Colorspace::C420paldv, 15 + 2 * 3 * 2),
+            ("C422", Colorspace::C422, 15 + 2 * 3 * 3),
+            ("C444", Colorspace::C444, 15 * 3),
+            ("Cmono", Colorspace::Mono, 15),
+        ] {
+            let header = header(&format!("YUV4MPEG2 W5 H3 {tag}"));
+            assert_eq!(header.colorspace, colorspace, "{tag}");
+            assert_eq!(header.frame_size(), frame_size, "{tag}");
+        }
+        assert!("YUV4MPEG2 W4 H2 C420p10".parse::<Y4mHeader>().is_err());
+    }
+
+    #[test]
+    fn interlacing() {
+        for (tag, interlacing) in [
+            ("Ip", Interlacing::Progressive),
+            ("I?", Interlacing::Progressive),
+            ("It", Interlacing::TopFieldFirst),
+            ("Ib", Interlacing::BottomFieldFirst),
+            ("Im", Interlacing::Mixed),
+        ] {
+            let header = header(&format!("YUV4MPEG2 W720 H576 F25:1 {tag}"));
+            assert_eq!(header.interlacing, interlacing, "{tag}");
+        }
+        assert!("YUV4MPEG2 W4 H2 Ix".parse::<Y4mHeader>().is_err());
+    }
+
+    #[test]
+    fn color_range() {
+        assert_eq!(
+            header("YUV4MPEG2 W4 H2 XCOLORRANGE=FULL").range,
+            Range::Full
+        );
+        assert_eq!(
+            header("YUV4MPEG2 W4 H2 XCOLORRANGE=LIMITED").range,
+            Range::Video
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_tags() {
+        assert_eq!(
+            header("YUV4MPEG2 W4 H2 Zzz XOTHER=1 é €5"),
+            header("YUV4MPEG2 W4 H2")
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        for line in [
+            "",
+            "YUV4MPEG W4 H2",
+            "YUV4MPEG2 H2",
+            "YUV4MPEG2 W4",
+            "YUV4MPEG2 W0 H2",
+            "YUV4MPEG2 W4 H2 F30",
+            "YUV4MPEG2 W4 H2 A1:x",
+            // a value, unlike a tag, which is not ASCII
+            "YUV4MPEG2 Wé H2",
+        ] {
+            let err = line.parse::<Y4mHeader>().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{line}");
+        }
+    }
+
+    #[test]
+    fn realtime_or_fast() {
+        let path = std::env::temp_dir().join(format!("y4m-test-{}.y4m", std::process::id()));
+        let mut data = b"YUV4MPEG2 W2 H2 F15:1 C420jpeg\nFRAME\n".to_vec();
+        data.extend_from_slice(&[16, 16, 16, 16, 128, 128]);
+        std::fs::write(&path, data).unwrap();
+
+        let realtime = Y4mFile::open(&path, true, false).unwrap();
+        let fast = Y4mFile::open(&path, false, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(realtime.framerate, Some(15.0));
+        assert_eq!(fast.framerate, None);
+        // the format tells the frame rate of the file either way
+        assert_eq!(fast.formats()[0].max_framerate, 15.0);
+    }
+}
@@ -0,0 +1,480 @@
+//! Crops, rotates and mirrors 4:2:0 frames, like the mirrored picture of a laptop camera
+//! or the picture of a camera mounted upside down.
+//!
+//! Every pixel of the result comes from one pixel of the source. Where it comes from changes by the same
+//! amount from one pixel to the next, so every plane is copied by stepping through the source.
+
+use std::str::FromStr;
+
+use crate::frame::{BufferPool, Frame, Plane, PlaneMut, SampleFormat, VideoFrame};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("can not transform {0}, only 4:2:0 formats")]
+    UnsupportedFormat(String),
+    #[error("the planes of the frame do not match {0}")]
+    InvalidPlanes(SampleFormat),
+}
+
+/// Crops, mirrors and rotates in this order, so the crop is in the coordinates of the source
+/// and mirroring is as the source sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Transform {
+    pub crop: Option<Crop>,
+    /// Swaps left and right, like a mirror.
+    pub flip_horizontal: bool,
+    /// Swaps top and bottom.
+    pub flip_vertical: bool,
+    pub rotation: Rotation,
+}
+
+/// A rectangle of a frame. Only even positions and sizes keep the chroma aligned,
+/// so it gets rounded down to them and clamped to the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Degrees90,
+    Degrees180,
+    Degrees270,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    /// Like `640x480+320+240` or `640x480` at the top left.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.split('+');
+        let size = parts.next().unwrap_or_default();
+        let (width, height) = size
+            .split_once('x')
+            .ok_or_else(|| format!("crop {s} is not like 640x480+320+240"))?;
+        let number = |part: Option<&str>, name: &str| {
+            part.unwrap_or("0")
+                .parse()
+                .map_err(|err| format!("{name} of crop {s}: {err}"))
+        };
+        let crop = Crop {
+            width: number(Some(width), "width")?,
+            height: number(Some(height), "height")?,
+            x: number(parts.next(), "x")?,
+            y: number(parts.next(), "y")?,
+        };
+        match parts.next() {
+            Some(_) => Err(format!("crop {s} is not like 640x480+320+240")),
+            None => Ok(crop),
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::None),
+            "90" => Ok(Self::Degrees90),
+            "180" => Ok(Self::Degrees180),
+            "270" => Ok(Self::Degrees270),
+            _ => Err(format!("unknown rotation {s}, use 0, 90, 180 or 270")),
+        }
+    }
+}
+
+impl Transform {
+    /// Whether frames pass as they are.
+    pub fn is_identity(&self) -> bool {
+        self.crop.is_none()
+            && !self.flip_horizontal
+            && !self.flip_vertical
+            && self.rotation == Rotation::None
+    }
+
+    /// The crop within a frame of this size, with even position and size.
+    fn crop_within(&self, width: u32, height: u32) -> Crop {
+        let (width, height) = (width & !1, height & !1);
+        let crop = self.crop.unwrap_or(Crop {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+        let x = (crop.x & !1).min(width.saturating_sub(2));
+        let y = (crop.y & !1).min(height.saturating_sub(2));
+        Crop {
+            x,
+            y,
+            width: (crop.width & !1).clamp(2, (width - x).max(2)),
+            height: (crop.height & !1).clamp(2, (height - y).max(2)),
+        }
+    }
+
+    /// The size of transformed frames of this size.
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        let crop = self.crop_within(width, height);
+        match self.rotation {
+            Rotation::None | Rotation::Degrees180 => (crop.width, crop.height),
+            Rotation::Degrees90 | Rotation::Degrees270 => (crop.height, crop.width),
+        }
+    }
+
+    /// Transforms a 4:2:0 frame, like NV12 and I420, into a frame of the same format with a buffer of the pool.
+    pub fn apply(&self, pool: &BufferPool, frame: &dyn Frame) -> Result<VideoFrame> {
+        let format = frame.format();
+        let layout = format
+            .plane_layout()
+            .filter(|layout| {
+                layout.len() > 1 && layout[1..].iter().all(|plane| plane.subsampling == (2, 2))
+            })
+            .ok_or_else(|| Error::UnsupportedFormat(format.pixel_format.clone()))?;
+        let pixels = frame.pixels();
+        if !format.matches_planes(&pixels.planes) || format.width < 2 || format.height < 2 {
+            return Err(Error::InvalidPlanes(format));
+        }
+
+        let crop = self.crop_within(format.width as u32, format.height as u32);
+        let (width, height) = self.size(format.width as u32, format.height as u32);
+        let target_format = SampleFormat {
+            width: width as i32,
+            height: height as i32,
+            pixel_format: format.pixel_format.clone(),
+        };
+        let mut transformed = VideoFrame::new(pool, target_format, frame.timestamp())
+            .expect("4:2:0 formats have a plane layout");
+        transformed.set_colorimetry(frame.colorimetry());
+
+        let planes = pixels
+            .planes
+            .iter()
+            .zip(layout)
+            .zip(transformed.planes_mut());
+        for ((source, layout), mut target) in planes {
+            // the crop in samples of the plane, exact because it is even
+            let (horizontal, vertical) = layout.subsampling;
+            let plane_crop = Crop {
+                x: crop.x / horizontal as u32,
+                y: crop.y / vertical as u32,
+                width: crop.width / horizontal as u32,
+                height: crop.height / vertical as u32,
+            };
+            let mapping = Mapping::new(self, plane_crop);
+            copy_plane(source, &mut target, &mapping);
+        }
+        Ok(transformed)
+    }
+
+    /// Where a pixel of the transformed crop is in the source.
+    fn locate(&self, crop: Crop, x: isize, y: isize) -> (isize, isize) {
+        let (width, height) = (crop.width as isize, crop.height as isize);
+        // undo the rotation
+        let (x, y) = match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Degrees90 => (y, height - 1 - x),
+            Rotation::Degrees180 => (width - 1 - x, height - 1 - y),
+            Rotation::Degrees270 => (width - 1 - y, x),
+        };
+        // undo the mirroring
+        let x = if self.flip_horizontal {
+            width - 1 - x
+        } else {
+            x
+        };
+        let y = if self.flip_vertical {
+            height - 1 - y
+        } else {
+            y
+        };
+        (crop.x as isize + x, crop.y as isize + y)
+    }
+}
+
+/// Where the first target sample is in the source plane, and how the position in the source changes
+/// from one target sample to the next to the right and down.
+struct Mapping {
+    origin: (isize, isize),
+    right: (isize, isize),
+    down: (isize, isize),
+}
+
+impl Mapping {
+    fn new(transform: &Transform, crop: Crop) -> Self {
+        let origin = transform.locate(crop, 0, 0);
+        let right = transform.locate(crop, 1, 0);
+        let down = transform.locate(crop, 0, 1);
+        Self {
+            origin,
+            right: (right.0 - origin.0, right.1 - origin.1),
+            down: (down.0 - origin.0, down.1 - origin.1),
+        }
+    }
+}
+
+fn copy_plane(source: &Plane, target: &mut PlaneMut, mapping: &Mapping) {
+    match source.bytes_per_pixel {
+        1 => copy_samples::<1>(source, target, mapping),
+        2 => copy_samples::<2>(source, target, mapping),
+        bytes => unreachable!("4:2:0 planes have no samples of {bytes} bytes"),
+    }
+}
+
+/// Copies samples of `N` bytes, a constant so that copying a sample is no call.
+fn copy_samples<const N: usize>(source: &Plane, target: &mut PlaneMut, mapping: &Mapping) {
+    let stride = source.stride as isize;
+    // the byte offset in the source of a sample and how it changes to the right and down
+    let offset = |(x, y): (isize, isize)| y * stride + x * N as isize;
+    let start = offset(mapping.origin);
+    let (right, down) = (offset(mapping.right), offset(mapping.down));
+
+    for y in 0..target.height {
+        let row_start = start + y as isize * down;
+        let row = target.row_mut(y);
+        if right == N as isize {
+            // rows stay rows in the same direction
+            let row_start = row_start as usize;
+            row.copy_from_slice(&source.data[row_start..row_start + row.len()]);
+        } else if right == -(N as isize) {
+            // rows stay rows, mirrored
+            let row_end = row_start as usize + N;
+            let source_row = &source.data[row_end - row.len()..row_end];
+            let samples = row
+                .chunks_exact_mut(N)
+                .zip(source_row.chunks_exact(N).rev());
+            for (sample, source_sample) in samples {
+                sample.copy_from_slice(source_sample);
+            }
+        } else {
+            for (x, sample) in row.chunks_exact_mut(N).enumerate() {
+                let position = (row_start + x as isize * right) as usize;
+                sample.copy_from_slice(&source.data[position..position + N]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// 6x4 pixels with every luma sample its index, 0 to 23, and every chroma sample of the 3x2 chroma samples
+    /// its index in U from 100 and in V from 200.
+    fn labelled(pixel_format: &str) -> VideoFrame {
+        let format = SampleFormat {
+            width: 6,
+            height: 4,
+            pixel_format: pixel_format.to_string(),
+        };
+        let mut frame = VideoFrame::new(&BufferPool::default(), format, Duration::ZERO).unwrap();
+        for (index, mut plane) in frame.planes_mut().enumerate() {
+            let (width, samples) = (plane.width, plane.bytes_per_pixel);
+            for y in 0..plane.height {
+                for (x, value) in plane.row_mut(y).iter_mut().enumerate() {
+                    let label = (y * width + x / samples) as u8;
+                    *value = match (index, samples) {
+                        (0, _) => label,
+                        // interleaved U and V
+                        (_, 2) => 100 * (x % 2 + 1) as u8 + label,
+                        _ => 100 * index as u8 + label,
+                    };
+                }
+            }
+        }
+        frame
+    }
+
+    fn planes(frame: &VideoFrame) -> Vec<Vec<Vec<u8>>> {
+        let planes = frame
+            .pixels()
+            .planes
+            .iter()
+            .map(|plane| plane.rows().map(<[u8]>::to_vec).collect())
+            .collect();
+        planes
+    }
+
+    /// Checks the labels of the transformed luma and chroma samples, of I420 and NV12.
+    fn check(transform: Transform, luma: &[&[u8]], chroma: &[&[u8]]) {
+        let rows = |rows: &[&[u8]], offset: u8| -> Vec<Vec<u8>> {
+            rows.iter()
+                .map(|row| row.iter().map(|label| label + offset).collect())
+                .collect()
+        };
+        let (luma, u, v) = (rows(luma, 0), rows(chroma, 100), rows(chroma, 200));
+        let uv: Vec<Vec<u8>> = u
+            .iter()
+            .zip(&v)
+            .map(|(u, v)| u.iter().zip(v).flat_map(|(u, v)| [*u, *v]).collect())
+            .collect();
+
+        let pool = BufferPool::default();
+        let i420 = transform.apply(&pool, &labelled("I420")).unwrap();
+        assert_eq!(planes(&i420), [luma.clone(), u, v], "I420 {transform:?}");
+        let nv12 = transform.apply(&pool, &labelled("NV12")).unwrap();
+        assert_eq!(planes(&nv12), [luma, uv], "NV12 {transform:?}");
+        let size = (nv12.format().width as u32, nv12.format().height as u32);
+        assert_eq!(size, transform.size(6, 4));
+    }
+
+    fn rotated(rotation: Rotation) -> Transform {
+        Transform {
+            rotation,
+            ..Transform::default()
+        }
+    }
+
+    #[test]
+    fn identity() {
+        let luma: [&[u8]; 4] = [
+            &[0, 1, 2, 3, 4, 5],
+            &[6, 7, 8, 9, 10, 11],
+            &[12, 13, 14, 15, 16, 17],
+            &[18, 19, 20, 21, 22, 23],
+        ];
+        check(Transform::default(), &luma, &[&[0, 1, 2], &[3, 4, 5]]);
+    }
+
+    #[test]
+    fn rotations() {
+        let luma: [&[u8]; 6] = [
+            &[18, 12, 6, 0],
+            &[19, 13, 7, 1],
+            &[20, 14, 8, 2],
+            &[21, 15, 9, 3],
+            &[22, 16, 10, 4],
+            &[23, 17, 11, 5],
+        ];
+        check(
+            rotated(Rotation::Degrees90),
+            &luma,
+            &[&[3, 0], &[4, 1], &[5, 2]],
+        );
+
+        let luma: [&[u8]; 4] = [
+            &[23, 22, 21, 20, 19, 18],
+            &[17, 16, 15, 14, 13, 12],
+            &[11, 10, 9, 8, 7, 6],
+            &[5, 4, 3, 2, 1, 0],
+        ];
+        check(
+            rotated(Rotation::Degrees180),
+            &luma,
+            &[&[5, 4, 3], &[2, 1, 0]],
+        );
+
+        let luma: [&[u8]; 6] = [
+            &[5, 11, 17, 23],
+            &[4, 10, 16, 22],
+            &[3, 9, 15, 21],
+            &[2, 8, 14, 20],
+            &[1, 7, 13, 19],
+            &[0, 6, 12, 18],
+        ];
+        check(
+            rotated(Rotation::Degrees270),
+            &luma,
+            &[&[2, 5], &[1, 4], &[0, 3]],
+        );
+    }
+
+    #[test]
+    fn flips() {
+        let mirrored = Transform {
+            flip_horizontal: true,
+            ..Transform::default()
+        };
+        let luma: [&[u8]; 4] = [
+            &[5, 4, 3, 2, 1, 0],
+            &[11, 10, 9, 8, 7, 6],
+            &[17, 16, 15, 14, 13, 12],
+            &[23, 22, 21, 20, 19, 18],
+        ];
+        check(mirrored, &luma, &[&[2, 1, 0], &[5, 4, 3]]);
+
+        let flipped = Transform {
+            flip_vertical: true,
+            ..Transform::default()
+        };
+        let luma: [&[u8]; 4] = [
+            &[18, 19, 20, 21, 22, 23],
+            &[12, 13, 14, 15, 16, 17],
+            &[6, 7, 8, 9, 10, 11],
+            &[0, 1, 2, 3, 4, 5],
+        ];
+        check(flipped, &luma, &[&[3, 4, 5], &[0, 1, 2]]);
+
+        // mirrored before rotated, so the mirrored picture turns
+        let both = Transform {
+            flip_horizontal: true,
+            rotation: Rotation::Degrees90,
+            ..Transform::default()
+        };
+        let luma: [&[u8]; 6] = [
+            &[23, 17, 11, 5],
+            &[22, 16, 10, 4],
+            &[21, 15, 9, 3],
+            &[20, 14, 8, 2],
+            &[19, 13, 7, 1],
+            &[18, 12, 6, 0],
+        ];
+        check(both, &luma, &[&[5, 2], &[4, 1], &[3, 0]]);
+    }
+
+    #[test]
+    fn crops_to_even() {
+        // 5x3+3+1 becomes 4x2+2+0
+        let crop = Transform {
+            crop: Some(Crop {
+                x: 3,
+                y: 1,
+                width: 5,
+                height: 3,
+            }),
+            ..Transform::default()
+        };
+        check(crop, &[&[2, 3, 4, 5], &[8, 9, 10, 11]], &[&[1, 2]]);
+
+        // clamped to the bottom right 2x2 pixels
+        let beyond = Transform {
+            crop: Some(Crop {
+                x: 10,
+                y: 10,
+                width: 100,
+                height: 100,
+            }),
+            ..Transform::default()
+        };
+        check(beyond, &[&[16, 17], &[22, 23]], &[&[5]]);
+    }
+
+    #[test]
+    fn crops_then_rotates() {
+        let transform = Transform {
+            crop: Some(Crop {
+                x: 2,
+                y: 0,
+                width: 4,
+                height: 4,
+            }),
+            rotation: Rotation::Degrees90,
+            ..Transform::default()
+        };
+        let luma: [&[u8]; 4] = [
+            &[20, 14, 8, 2],
+            &[21, 15, 9, 3],
+            &[22, 16, 10, 4],
+            &[23, 17, 11, 5],
+        ];
+        check(transform, &luma, &[&[4, 1], &[5, 2]]);
+    }
+}
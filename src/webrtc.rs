@@ -1,10 +1,10 @@
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 use tokio::sync::broadcast;
 
 pub use webrtc::api::interceptor_registry::register_default_interceptors;
-pub use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_VP8};
+pub use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_VP8, MIME_TYPE_VP9};
 pub use webrtc::api::APIBuilder;
 pub use webrtc::api::API;
 pub use webrtc::ice_transport::ice_server::RTCIceServer;
@@ -30,9 +30,10 @@ use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
-use crate::EncodedFrame;
+use crate::codec::Codec;
+use crate::pipeline::EncodedFrame;
 
-type OfferAnswerExchange = (RTCSessionDescription, mpsc::Sender<RTCSessionDescription>);
+pub type OfferAnswerExchange = (RTCSessionDescription, mpsc::Sender<RTCSessionDescription>);
 
 pub async fn http_testapp(
     port: u16,
@@ -78,13 +79,14 @@ pub async fn webrtc_testapp(
     mut exchange_rx: mpsc::Receiver<OfferAnswerExchange>,
     mut encoded_frames_rx: mpsc::Receiver<EncodedFrame>,
     picture_loss_indicator: Arc<AtomicBool>,
+    codec: Codec,
 ) -> webrtc::error::Result<()> {
     let api = create_webrtc_api().expect("webrtc api");
     let config = rtc_configuration();
 
     let peer_connection = Arc::new(api.new_peer_connection(config).await?);
     let _peer_connection_state_change = PeerConnectionStateChange::new(&peer_connection);
-    let output_track = create_vp8_track();
+    let output_track = create_track(codec);
     let output_track_pc = Arc::clone(&output_track);
 
     // let peer_connection2 = peer_connection.clone();
@@ -115,12 +117,14 @@ pub async fn webrtc_testapp(
         return Ok(());
     }
 
-    let ms33 = Duration::from_millis(33);
+    // The RTP timestamps advance by the sample durations, so these follow the capture clock.
+    let mut start_time = None;
     while let Some(frame) = encoded_frames_rx.recv().await {
+        let start_time = *start_time.get_or_insert_with(|| SystemTime::now() - frame.pts);
         let sample = Sample {
             data: frame.bytes,
-            timestamp: SystemTime::now(),
-            duration: ms33,
+            timestamp: start_time + frame.pts,
+            duration: frame.duration,
             ..Default::default()
         };
         if frame.keyframe {
@@ -254,12 +258,21 @@ impl PeerConnectionStateChange {
     }
 }
 
-fn create_vp8_track() -> Arc<TrackLocalStaticSample> {
-    Arc::new(TrackLocalStaticSample::new(
-        RTCRtpCodecCapability {
+/// VP9 in profile 0, 8 bit 4:2:0 like the encoder makes it, which the browsers support.
+fn create_track(codec: Codec) -> Arc<TrackLocalStaticSample> {
+    let capability = match codec {
+        Codec::Vp8 => RTCRtpCodecCapability {
             mime_type: MIME_TYPE_VP8.to_owned(),
             ..Default::default()
         },
+        Codec::Vp9 => RTCRtpCodecCapability {
+            mime_type: MIME_TYPE_VP9.to_owned(),
+            sdp_fmtp_line: "profile-id=0".to_owned(),
+            ..Default::default()
+        },
+    };
+    Arc::new(TrackLocalStaticSample::new(
+        capability,
         "video".to_owned(),     // id
         "webrtc-rs".to_owned(), // stream_id
     ))